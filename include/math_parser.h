@@ -0,0 +1,36 @@
+#ifndef MATH_PARSER_H
+#define MATH_PARSER_H
+
+/* Generated by cbindgen from src/ffi.rs, which says what every function does */
+
+typedef struct MpEnv MpEnv;
+
+typedef struct MpResult MpResult;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+struct MpEnv *mp_env_new(void);
+
+void mp_env_free(struct MpEnv *env);
+
+int mp_env_set_double(struct MpEnv *env, const char *name, double val);
+
+struct MpResult *mp_eval(struct MpEnv *env, const char *input);
+
+double mp_result_as_double(const struct MpResult *result);
+
+char *mp_result_to_string(const struct MpEnv *env, const struct MpResult *result);
+
+void mp_result_free(struct MpResult *result);
+
+void mp_string_free(char *text);
+
+const char *mp_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* MATH_PARSER_H */
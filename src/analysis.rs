@@ -0,0 +1,243 @@
+use math_parser::error::{LexError, LexErrorKind, Span, NO_SPAN};
+use math_parser::expr::{Env, Expr, Function, SeriesKind};
+use math_parser::lexer::{tokenize, Token, Tokens};
+use math_parser::optimize::is_constant;
+use math_parser::parser::{parse_with, ParseOptions, Parser};
+use math_parser::symbol::Symbol;
+use math_parser::Error;
+
+// What the language server works out about a file from its text: what's
+// wrong with it, what the constant parts evaluate to and where names are
+// declared. Positions are byte offsets into the text, the server turns them
+// into lines and columns.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+pub struct Diagnostic {
+  pub span: Span,
+  pub severity: Severity,
+  pub message: String,
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+  span.start <= offset && offset <= span.end
+}
+
+// The same syntax errors and warnings running the file would report before
+// evaluating anything
+pub fn diagnostics(input: &str) -> Vec<Diagnostic> {
+  let (errors, warnings) = match parse_with(input, &ParseOptions::default()) {
+    Ok((_, warnings)) => (Vec::new(), warnings),
+    Err(errors) => (errors, Vec::new()),
+  };
+  let errors = errors
+    .into_iter()
+    // A file being written starts out empty, that's not a mistake
+    .filter(|err| {
+      !matches!(
+        err,
+        Error::Lex(LexError {
+          kind: LexErrorKind::EmptyInput,
+          ..
+        })
+      )
+    })
+    .map(|err| Diagnostic {
+      span: err.span().unwrap_or_else(|| Span::new(0, 0)),
+      severity: Severity::Error,
+      message: err.message(),
+    });
+  let warnings = warnings.into_iter().map(|warning| Diagnostic {
+    span: warning.span,
+    severity: Severity::Warning,
+    message: warning.message(),
+  });
+  errors.chain(warnings).collect()
+}
+
+// Every statement that parses, so the rest of the file still works while
+// one line has a mistake in it
+fn statements(input: &str) -> Vec<Expr> {
+  match Parser::new(input) {
+    Ok(parser) => parser
+      .filter_map(Result::ok)
+      .map(|statement| statement.expr)
+      .collect(),
+    Err(_) => Vec::new(),
+  }
+}
+
+// Calls are found by their name, the other nodes by all of their source.
+// Literals are left out, there's nothing to work out about them.
+fn span(expr: &Expr) -> Option<Span> {
+  match (expr, expr.span()) {
+    (Expr::Literal(..), _) | (_, NO_SPAN) => None,
+    (_, span) => Some(span),
+  }
+}
+
+// The biggest constant part of the expression under the cursor, that isn't
+// already just a number
+fn constant_at(expr: &Expr, offset: usize) -> Option<(&Expr, Span)> {
+  let span = span(expr);
+  if span.is_some_and(|span| contains(span, offset)) && is_constant(expr) {
+    return span.map(|span| (expr, span));
+  }
+  expr
+    .children()
+    .into_iter()
+    .find_map(|child| constant_at(child, offset))
+}
+
+// "2 * 3 = 6" for the constant under the cursor, evaluated where prints
+// can't get to the server's stdout
+pub fn hover(input: &str, offset: usize) -> Option<(Span, String)> {
+  let statements = statements(input);
+  let (expr, span) = statements
+    .iter()
+    .find_map(|statement| constant_at(statement, offset))?;
+  let mut env = Env::new();
+  env.set_output(std::io::sink());
+  let val = expr.eval(&mut env).ok()?;
+  Some((span, format!("{} = {}", expr, env.display(&val))))
+}
+
+// Where a name can come from besides a global declaration
+#[derive(Copy, Clone)]
+enum Scope<'a> {
+  Params(&'a Function),
+  Index(SeriesKind, &'a Symbol),
+}
+
+impl Scope<'_> {
+  fn binds(self, name: &str) -> bool {
+    match self {
+      Scope::Params(function) => function.params.iter().any(|param| param == name),
+      Scope::Index(_, index) => index == name,
+    }
+  }
+}
+
+// A name under the cursor
+enum Found<'a> {
+  Var(&'a Symbol, Span, Option<Scope<'a>>),
+  Call(&'a str, Span),
+  Declaration(Span),
+}
+
+fn find<'a>(expr: &'a Expr, offset: usize, scopes: &mut Vec<Scope<'a>>) -> Option<Found<'a>> {
+  match expr {
+    Expr::Var(name, span) if contains(*span, offset) => {
+      let scope = scopes.iter().rev().find(|scope| scope.binds(name));
+      return Some(Found::Var(name, *span, scope.copied()));
+    }
+    Expr::Call(name, _, span) if contains(*span, offset) => return Some(Found::Call(name, *span)),
+    Expr::VarDeclaration(_, _, span) if contains(*span, offset) => {
+      return Some(Found::Declaration(*span))
+    }
+    _ => (),
+  }
+  let scope = match expr {
+    Expr::FnDeclaration(function, _) => Some(Scope::Params(function)),
+    Expr::Series(kind, index, ..) => Some(Scope::Index(*kind, index)),
+    _ => None,
+  };
+  for (i, child) in expr.children().into_iter().enumerate() {
+    // A series' index is only declared in its term, the last child
+    let scoped = match scope {
+      Some(Scope::Index(..)) => i == 2,
+      Some(_) => true,
+      None => false,
+    };
+    if scoped {
+      scopes.extend(scope);
+    }
+    let found = find(child, offset, scopes);
+    if scoped {
+      scopes.pop();
+    }
+    if found.is_some() {
+      return found;
+    }
+  }
+  None
+}
+
+// Names only have spans where they're used, so parameters and indices are
+// found in the tokens: the last "fn name(" or "sum(" written before the use
+fn binder(tokens: &Tokens, scope: Scope, name: &str, before: usize) -> Option<Span> {
+  let starts = |i: usize| match (scope, &tokens[i..]) {
+    (
+      Scope::Params(function),
+      [(Token::FnKeyword, _), (Token::Name(f), _), (Token::LeftParen, _), ..],
+    ) => declares(f, &function.name),
+    (Scope::Index(kind, _), [(Token::Name(f), _), (Token::LeftParen, _), ..]) => *f == kind.name(),
+    _ => false,
+  };
+  let start = (0..tokens.len())
+    .rev()
+    .find(|&i| tokens[i].1.start < before && starts(i))?;
+  tokens[start..]
+    .iter()
+    .take_while(|(token, _)| *token != Token::RightParen)
+    .find(|(token, _)| *token == Token::Name(name))
+    .map(|(_, span)| *span)
+}
+
+// The last span written before the use, or the first one after it, e.g. for
+// a global a function reads that's declared below the function
+fn closest(spans: impl IntoIterator<Item = Span>, before: usize) -> Option<Span> {
+  let spans = spans.into_iter().collect::<Vec<Span>>();
+  let earlier = spans.iter().rev().find(|span| span.start < before);
+  earlier.or_else(|| spans.first()).copied()
+}
+
+fn declarations<'a>(statements: &'a [Expr], name: &str) -> Vec<Span> {
+  let mut spans = Vec::new();
+  for statement in statements {
+    statement.walk(|expr: &'a Expr| match expr {
+      Expr::VarDeclaration(declared, _, span) if declared == name => spans.push(*span),
+      _ => (),
+    });
+  }
+  spans.sort_by_key(|span| span.start);
+  spans
+}
+
+// Whether "fn written(" declares the function, which is qualified when it's
+// written in a module
+fn declares(written: &str, name: &str) -> bool {
+  name
+    .strip_suffix(written)
+    .is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+}
+
+// The name of every "fn name(", where functions are declared
+fn function_names(tokens: &Tokens, name: &str) -> Vec<Span> {
+  tokens
+    .windows(2)
+    .filter_map(|pair| match pair {
+      [(Token::FnKeyword, _), (Token::Name(f), span)] if declares(f, name) => Some(*span),
+      _ => None,
+    })
+    .collect()
+}
+
+// Where the variable or function under the cursor is declared
+pub fn definition(input: &str, offset: usize) -> Option<Span> {
+  let statements = statements(input);
+  let tokens = tokenize(input).ok()?;
+  let found = statements
+    .iter()
+    .find_map(|statement| find(statement, offset, &mut Vec::new()))?;
+  match found {
+    Found::Var(name, span, Some(scope)) => binder(&tokens, scope, name, span.start),
+    Found::Var(name, span, None) => closest(declarations(&statements, name), span.start),
+    Found::Call(name, span) => closest(function_names(&tokens, name), span.start),
+    Found::Declaration(span) => Some(span),
+  }
+}
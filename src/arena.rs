@@ -0,0 +1,294 @@
+use crate::error::{RuntimeError, Span};
+use crate::expr::{
+  apply_binary, apply_percent_change, apply_unary, eval_call_with, eval_var, BinOp, Env, Expr,
+  Function, SeriesKind, UnOp, Value,
+};
+use crate::prelude::*;
+use crate::symbol::Symbol;
+use alloc::rc::Rc;
+use core::ops::Index;
+
+// Expressions kept in one Vec, with children referred to by their index
+// instead of each node getting a Box of its own. Programs generating huge
+// formulas can push nodes straight into an arena, which costs one growing
+// allocation instead of one per node and keeps the nodes next to each other
+// when they're walked.
+//
+// Nodes only refer to nodes pushed before them, so a whole arena can be
+// gone through front to back, leaves first.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ExprId(u32);
+
+impl ExprId {
+  pub fn index(self) -> usize {
+    self.0 as usize
+  }
+}
+
+// A run of ids in the arena, the arguments of a call or the items of a list
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Children {
+  start: u32,
+  len: u32,
+}
+
+impl Children {
+  pub fn len(self) -> usize {
+    self.len as usize
+  }
+
+  pub fn is_empty(self) -> bool {
+    self.len == 0
+  }
+}
+
+// Like Expr, with ids for children
+#[derive(Debug, Clone)]
+pub enum Node {
+  Literal(Value, Span),
+  VarDeclaration(Symbol, ExprId, Span),
+  Var(Symbol, Span),
+  Print(Children, Span),
+  Assert(ExprId, Span),
+  Binary(BinOp, ExprId, ExprId, Span),
+  Unary(UnOp, ExprId, Span),
+  Conditional(ExprId, ExprId, ExprId, Span),
+  Call(String, Children, Span),
+  // Function bodies stay trees, they're shared with the values they become
+  FnDeclaration(Rc<Function>, Span),
+  List(Children, Span),
+  Interpolation(Children, Span),
+  Series(SeriesKind, Symbol, ExprId, ExprId, ExprId, Span),
+  Import(String, Span),
+  Module(String, Children, Span),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExprArena {
+  nodes: Vec<Node>,
+  children: Vec<ExprId>,
+}
+
+type EvalResult = Result<Value, RuntimeError>;
+
+impl ExprArena {
+  pub fn new() -> Self {
+    ExprArena::default()
+  }
+
+  pub fn with_capacity(nodes: usize) -> Self {
+    ExprArena {
+      nodes: Vec::with_capacity(nodes),
+      children: Vec::new(),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.nodes.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.nodes.is_empty()
+  }
+
+  // Children have to be in the arena already
+  pub fn push(&mut self, node: Node) -> ExprId {
+    let id = ExprId(self.nodes.len() as u32);
+    self.nodes.push(node);
+    id
+  }
+
+  pub fn push_children(&mut self, ids: &[ExprId]) -> Children {
+    let start = self.children.len() as u32;
+    self.children.extend_from_slice(ids);
+    Children {
+      start,
+      len: ids.len() as u32,
+    }
+  }
+
+  pub fn children(&self, children: Children) -> &[ExprId] {
+    let start = children.start as usize;
+    &self.children[start..start + children.len()]
+  }
+
+  // Every node in the order they were pushed, leaves before their parents
+  pub fn iter(&self) -> impl Iterator<Item = (ExprId, &Node)> {
+    self
+      .nodes
+      .iter()
+      .enumerate()
+      .map(|(index, node)| (ExprId(index as u32), node))
+  }
+
+  // Copies a tree in, returning the id of its root
+  pub fn alloc(&mut self, expr: &Expr) -> ExprId {
+    let node = match expr {
+      Expr::Literal(val, span) => Node::Literal(val.clone(), *span),
+      Expr::VarDeclaration(name, val, span) => {
+        Node::VarDeclaration(name.clone(), self.alloc(val), *span)
+      }
+      Expr::Var(name, span) => Node::Var(name.clone(), *span),
+      Expr::Print(vals, span) => Node::Print(self.alloc_all(vals), *span),
+      Expr::Assert(cond, span) => Node::Assert(self.alloc(cond), *span),
+      Expr::Binary(op, left, right, span) => {
+        Node::Binary(*op, self.alloc(left), self.alloc(right), *span)
+      }
+      Expr::Unary(op, val, span) => Node::Unary(*op, self.alloc(val), *span),
+      Expr::Conditional(cond, val_if_true, val_if_false, span) => Node::Conditional(
+        self.alloc(cond),
+        self.alloc(val_if_true),
+        self.alloc(val_if_false),
+        *span,
+      ),
+      Expr::Call(name, args, span) => Node::Call(name.clone(), self.alloc_all(args), *span),
+      Expr::FnDeclaration(function, span) => Node::FnDeclaration(function.clone(), *span),
+      Expr::List(items, span) => Node::List(self.alloc_all(items), *span),
+      Expr::Interpolation(parts, span) => Node::Interpolation(self.alloc_all(parts), *span),
+      Expr::Series(kind, index, from, to, term, span) => Node::Series(
+        *kind,
+        index.clone(),
+        self.alloc(from),
+        self.alloc(to),
+        self.alloc(term),
+        *span,
+      ),
+      Expr::Import(path, span) => Node::Import(path.clone(), *span),
+      Expr::Module(name, body, span) => Node::Module(name.clone(), self.alloc_all(body), *span),
+    };
+    self.push(node)
+  }
+
+  fn alloc_all(&mut self, exprs: &[Expr]) -> Children {
+    let ids: Vec<ExprId> = exprs.iter().map(|expr| self.alloc(expr)).collect();
+    self.push_children(&ids)
+  }
+
+  // Builds the tree back, for printing it or anything else that takes an Expr
+  pub fn to_expr(&self, id: ExprId) -> Expr {
+    let boxed = |id| Box::new(self.to_expr(id));
+    match &self[id] {
+      Node::Literal(val, span) => Expr::Literal(val.clone(), *span),
+      Node::VarDeclaration(name, val, span) => {
+        Expr::VarDeclaration(name.clone(), boxed(*val), *span)
+      }
+      Node::Var(name, span) => Expr::Var(name.clone(), *span),
+      Node::Print(vals, span) => Expr::Print(self.to_exprs(*vals), *span),
+      Node::Assert(cond, span) => Expr::Assert(boxed(*cond), *span),
+      Node::Binary(op, left, right, span) => Expr::Binary(*op, boxed(*left), boxed(*right), *span),
+      Node::Unary(op, val, span) => Expr::Unary(*op, boxed(*val), *span),
+      Node::Conditional(cond, val_if_true, val_if_false, span) => Expr::Conditional(
+        boxed(*cond),
+        boxed(*val_if_true),
+        boxed(*val_if_false),
+        *span,
+      ),
+      Node::Call(name, args, span) => Expr::Call(name.clone(), self.to_exprs(*args), *span),
+      Node::FnDeclaration(function, span) => Expr::FnDeclaration(function.clone(), *span),
+      Node::List(items, span) => Expr::List(self.to_exprs(*items), *span),
+      Node::Interpolation(parts, span) => Expr::Interpolation(self.to_exprs(*parts), *span),
+      Node::Series(kind, index, from, to, term, span) => Expr::Series(
+        *kind,
+        index.clone(),
+        boxed(*from),
+        boxed(*to),
+        boxed(*term),
+        *span,
+      ),
+      Node::Import(path, span) => Expr::Import(path.clone(), *span),
+      Node::Module(name, body, span) => Expr::Module(name.clone(), self.to_exprs(*body), *span),
+    }
+  }
+
+  fn to_exprs(&self, children: Children) -> Vec<Expr> {
+    let ids = self.children(children);
+    ids.iter().map(|&id| self.to_expr(id)).collect()
+  }
+
+  // Gives the same results as evaluating the tree. Prints, asserts, series,
+  // imports, modules and function declarations are rare enough in big
+  // formulas that they're turned back into trees and evaluated like that,
+  // and so is everything
+  // while the Env is recording, since recordings keep the expressions.
+  pub fn eval(&self, id: ExprId, env: &mut Env) -> EvalResult {
+    env.enter()?;
+    let result = self.eval_node(id, env);
+    env.leave();
+    result
+  }
+
+  fn eval_node(&self, id: ExprId, env: &mut Env) -> EvalResult {
+    match &self[id] {
+      Node::Print(..)
+      | Node::Assert(..)
+      | Node::Series(..)
+      | Node::FnDeclaration(..)
+      | Node::Import(..)
+      | Node::Module(..) => return self.to_expr(id).eval(env),
+      _ if env.is_recording() => return self.to_expr(id).eval(env),
+      _ => (),
+    }
+    env.count_step();
+    match &self[id] {
+      Node::Literal(val, _) => Ok(val.clone()),
+      Node::VarDeclaration(name, val, _) => {
+        let val = self.eval(*val, env)?;
+        env.set_symbol(name.clone(), val.clone());
+        Ok(val)
+      }
+      Node::Var(name, span) => eval_var(name, env).map_err(|err| err.at(*span)),
+      Node::Binary(op, left, right, span) => {
+        let percent_change = matches!(op, BinOp::Add | BinOp::Sub)
+          && matches!(self[*right], Node::Unary(UnOp::Percent, ..));
+        let (left, right) = (self.eval(*left, env)?, self.eval(*right, env)?);
+        let result = if percent_change {
+          apply_percent_change(*op, left, right)
+        } else {
+          apply_binary(*op, left, right, env)
+        };
+        result.map_err(|err| err.at(*span))
+      }
+      Node::Unary(op, val, span) => {
+        apply_unary(*op, self.eval(*val, env)?).map_err(|err| err.at(*span))
+      }
+      Node::Conditional(cond, val_if_true, val_if_false, _) => match self.eval(*cond, env)? {
+        Value::Bool(true) => self.eval(*val_if_true, env),
+        _ => self.eval(*val_if_false, env),
+      },
+      Node::Call(name, args, span) => eval_call_with(name, args.len(), *span, env, |env| {
+        self.eval_all(*args, env)
+      }),
+      Node::List(items, _) => Ok(Value::List(Rc::new(self.eval_all(*items, env)?))),
+      Node::Interpolation(parts, span) => {
+        let mut text = String::new();
+        for &part in self.children(*parts) {
+          let val = self.eval(part, env).map_err(|err| err.at(*span))?;
+          text.push_str(&env.display(&val));
+        }
+        Ok(Value::Str(text.into()))
+      }
+      Node::Print(..)
+      | Node::Assert(..)
+      | Node::Series(..)
+      | Node::FnDeclaration(..)
+      | Node::Import(..)
+      | Node::Module(..) => {
+        unreachable!("evaluated as trees above")
+      }
+    }
+  }
+
+  fn eval_all(&self, children: Children, env: &mut Env) -> Result<Vec<Value>, RuntimeError> {
+    let ids = self.children(children);
+    ids.iter().map(|&id| self.eval(id, env)).collect()
+  }
+}
+
+impl Index<ExprId> for ExprArena {
+  type Output = Node;
+
+  fn index(&self, id: ExprId) -> &Node {
+    &self.nodes[id.index()]
+  }
+}
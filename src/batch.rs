@@ -0,0 +1,210 @@
+use crate::error::{RuntimeError, RuntimeErrorKind};
+use crate::expr::{BinOp, Env, Expr, UnOp, Value};
+use crate::prelude::*;
+
+// Evaluates one expression over many input points on the CPU, e.g. every x
+// of a plot. Purely numeric expressions are lowered to a flat list of float
+// operations first, which is much faster than walking the tree for every
+// point. Anything else goes through the interpreter, one point at a time.
+//
+// There's no GPU backend: wgpu can't be a dependency of this crate yet, and
+// an optional dependency still has to resolve for every build. A Kernel is
+// already what a compute shader would be generated from, so one can be added
+// behind a feature once wgpu can be.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Op {
+  Const(f64),
+  // The value of the nth parameter at the current point
+  Input(usize),
+  Add,
+  Sub,
+  Mul,
+  Div,
+  FloorDiv,
+  Pow,
+  Neg,
+  Sqrt,
+}
+
+// Operations in postfix order, each one pops its operands off a stack
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kernel {
+  ops: Vec<Op>,
+  inputs: usize,
+}
+
+impl Kernel {
+  // Only succeeds when evaluating the kernel gives the same floats as the
+  // interpreter would, anything with side effects, calls or non-numbers
+  // can't be lowered. Parts that don't depend on the parameters are
+  // evaluated right away, so integer math like "1 // 2" keeps its meaning.
+  pub fn lower(expr: &Expr, params: &[&str], env: &mut Env) -> Option<Kernel> {
+    // Constant expressions keep their own type, e.g. an int
+    if !is_pure(expr) || !uses_params(expr, params) {
+      return None;
+    }
+    let mut ops = Vec::new();
+    lower_into(expr, params, env, &mut ops)?;
+    Some(Kernel {
+      ops,
+      inputs: params.len(),
+    })
+  }
+
+  pub fn ops(&self) -> &[Op] {
+    &self.ops
+  }
+
+  // `columns` holds one slice of values per parameter, all the same length
+  pub fn run(&self, columns: &[&[f64]]) -> Result<Vec<f64>, RuntimeError> {
+    let points = count_points(self.inputs, columns)?;
+    let mut stack: Vec<f64> = Vec::with_capacity(self.ops.len());
+    let results = (0..points)
+      .map(|point| {
+        stack.clear();
+        for &op in &self.ops {
+          let val = match op {
+            Op::Const(num) => num,
+            Op::Input(index) => columns[index][point],
+            Op::Neg => -stack.pop().unwrap(),
+            Op::Sqrt => stack.pop().unwrap().sqrt(),
+            _ => {
+              let right = stack.pop().unwrap();
+              let left = stack.pop().unwrap();
+              match op {
+                Op::Add => left + right,
+                Op::Sub => left - right,
+                Op::Mul => left * right,
+                Op::Div => left / right,
+                Op::FloorDiv => (left / right).floor(),
+                _ => left.powf(right),
+              }
+            }
+          };
+          stack.push(val);
+        }
+        stack[0]
+      })
+      .collect();
+    Ok(results)
+  }
+}
+
+// How many points the columns have values for, without parameters there's
+// the one point of evaluating the expression once
+fn count_points(params: usize, columns: &[&[f64]]) -> Result<usize, RuntimeError> {
+  if columns.len() != params {
+    let kind = RuntimeErrorKind::WrongColumnCount(params, columns.len());
+    return Err(RuntimeError::new(kind));
+  }
+  let points = columns.first().map_or(1, |column| column.len());
+  match columns.iter().find(|column| column.len() != points) {
+    Some(column) => Err(RuntimeError::new(RuntimeErrorKind::RaggedColumns(
+      points,
+      column.len(),
+    ))),
+    None => Ok(points),
+  }
+}
+
+fn uses_params(expr: &Expr, params: &[&str]) -> bool {
+  match expr {
+    Expr::Var(name, _) => params.contains(&name.as_str()),
+    expr => expr
+      .children()
+      .iter()
+      .any(|child| uses_params(child, params)),
+  }
+}
+
+// Binary and unary operators on numbers, variables and literals don't have
+// side effects
+fn is_pure(expr: &Expr) -> bool {
+  match expr {
+    Expr::Literal(..) | Expr::Var(..) | Expr::Binary(..) | Expr::Unary(..) => {
+      expr.children().into_iter().all(is_pure)
+    }
+    _ => false,
+  }
+}
+
+fn lower_into(expr: &Expr, params: &[&str], env: &mut Env, ops: &mut Vec<Op>) -> Option<()> {
+  if !uses_params(expr, params) {
+    let num = match expr.eval(env).ok()? {
+      Value::Function(_) => return None,
+      val => val.f64().ok()?,
+    };
+    ops.push(Op::Const(num));
+    return Some(());
+  }
+  match expr {
+    Expr::Var(name, _) => {
+      let index = params.iter().position(|param| name == param)?;
+      ops.push(Op::Input(index));
+    }
+    Expr::Binary(op, left, right, _) => {
+      let op = match op {
+        BinOp::Add => Op::Add,
+        BinOp::Sub => Op::Sub,
+        BinOp::Mul => Op::Mul,
+        BinOp::Div => Op::Div,
+        BinOp::FloorDiv => Op::FloorDiv,
+        BinOp::Pow => Op::Pow,
+        // Comparisons give booleans
+        _ => return None,
+      };
+      // "x + 8%" adds 8% of x, so x is needed twice
+      let percent_of_left =
+        matches!(**right, Expr::Unary(UnOp::Percent, ..)) && (op == Op::Add || op == Op::Sub);
+      lower_into(left, params, env, ops)?;
+      if percent_of_left {
+        lower_into(left, params, env, ops)?;
+      }
+      lower_into(right, params, env, ops)?;
+      if percent_of_left {
+        ops.push(Op::Mul);
+      }
+      ops.push(op);
+    }
+    Expr::Unary(op, val, _) => {
+      lower_into(val, params, env, ops)?;
+      match op {
+        UnOp::Neg => ops.push(Op::Neg),
+        UnOp::Sqrt => ops.push(Op::Sqrt),
+        UnOp::Percent => ops.extend([Op::Const(100.0), Op::Div]),
+        // Only works on booleans
+        UnOp::Not => return None,
+      }
+    }
+    _ => return None,
+  }
+  Some(())
+}
+
+// Evaluates `expr` once per point, with the parameters set to that point's
+// values. Uses a kernel when the expression can be lowered, otherwise the
+// interpreter, which leaves the variables of `env` as they were. There has to
+// be one column per parameter, all of them the same length.
+pub fn eval_batch(
+  expr: &Expr,
+  params: &[&str],
+  columns: &[&[f64]],
+  env: &mut Env,
+) -> Result<Vec<Value>, RuntimeError> {
+  if let Some(kernel) = Kernel::lower(expr, params, env) {
+    return Ok(kernel.run(columns)?.into_iter().map(Value::Float).collect());
+  }
+  let points = count_points(params.len(), columns)?;
+  let snapshot = env.snapshot();
+  let results = (0..points)
+    .map(|point| {
+      for (param, column) in params.iter().zip(columns) {
+        env.set(param, Value::Float(column[point]));
+      }
+      expr.eval(env)
+    })
+    .collect();
+  env.rollback(snapshot);
+  results
+}
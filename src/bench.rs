@@ -0,0 +1,67 @@
+use math_parser::error::RuntimeError;
+use math_parser::expr::{EnvBuilder, Expr};
+use math_parser::program::eval_all;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_ITERATIONS: usize = 100;
+
+pub struct Report {
+  nodes: usize,
+  // Expressions evaluated by a single run
+  steps: u64,
+  // How long each run took, fastest first
+  samples: Vec<Duration>,
+}
+
+// Evaluates the already parsed program `iterations` times, each run in a fresh
+// environment so they all do the same work
+pub fn bench(exprs: &[Expr], iterations: usize, env: &EnvBuilder) -> Result<Report, RuntimeError> {
+  let mut samples = Vec::with_capacity(iterations);
+  let mut steps = 0;
+  for _ in 0..iterations {
+    let mut env = env.clone().build();
+    let start = Instant::now();
+    eval_all(exprs, &mut env)?;
+    samples.push(start.elapsed());
+    steps = env.steps();
+  }
+  samples.sort();
+  Ok(Report {
+    nodes: exprs.iter().map(Expr::node_count).sum(),
+    steps,
+    samples,
+  })
+}
+
+impl Report {
+  // Nearest-rank percentile, `percent` between 0 and 100
+  fn percentile(&self, percent: usize) -> Duration {
+    let rank = (self.samples.len() * percent).div_ceil(100);
+    self.samples[rank.saturating_sub(1)]
+  }
+
+  fn total(&self) -> Duration {
+    self.samples.iter().sum()
+  }
+}
+
+impl fmt::Display for Report {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let runs = self.samples.len();
+    writeln!(
+      f,
+      "{} runs of a program with {} nodes, evaluating {} expressions each",
+      runs, self.nodes, self.steps
+    )?;
+    writeln!(f, "  min   {:.2?}", self.samples[0])?;
+    for percent in [50, 90, 99] {
+      writeln!(f, "  p{}   {:.2?}", percent, self.percentile(percent))?;
+    }
+    writeln!(f, "  max   {:.2?}", self.samples[runs - 1])?;
+    writeln!(f, "  mean  {:.2?}", self.total() / runs as u32)?;
+    let seconds = self.total().as_secs_f64();
+    let throughput = (self.steps * runs as u64) as f64 / seconds;
+    write!(f, "  {:.0} expressions/s", throughput)
+  }
+}
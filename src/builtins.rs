@@ -0,0 +1,886 @@
+use crate::error::{RuntimeError, RuntimeErrorKind};
+use crate::expr::{call_function, round_significant, AngleMode, Env, Function, Value};
+use crate::plot;
+use crate::prelude::*;
+use crate::symbol::Symbol;
+use alloc::rc::Rc;
+use core::convert::TryFrom;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Arity {
+  Exact(usize),
+  AtLeast(usize),
+  // Both included
+  Between(usize, usize),
+}
+
+impl Arity {
+  pub fn accepts(self, count: usize) -> bool {
+    match self {
+      Arity::Exact(arity) => count == arity,
+      Arity::AtLeast(arity) => count >= arity,
+      Arity::Between(min, max) => (min..=max).contains(&count),
+    }
+  }
+}
+
+pub struct Builtin {
+  pub name: &'static str,
+  pub arity: Arity,
+  // Gets the environment for builtins that change how it evaluates
+  pub call: fn(&[Value], &mut Env) -> Result<Value, RuntimeError>,
+  // Whether results are worth remembering, for expensive builtins whose
+  // result only depends on the arguments
+  pub cached: bool,
+}
+
+fn float_fn(args: &[Value], f: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+  Ok(Value::Float(f(args[0].f64()?)))
+}
+
+// Takes an angle in the env's angle mode
+fn angle_fn(args: &[Value], env: &mut Env, f: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+  let angle = args[0].f64()?;
+  let radians = match env.angle_mode() {
+    AngleMode::Radians => angle,
+    AngleMode::Degrees => angle.to_radians(),
+  };
+  Ok(Value::Float(f(radians)))
+}
+
+// Gives an angle in the env's angle mode
+fn inverse_angle_fn(
+  args: &[Value],
+  env: &mut Env,
+  f: fn(f64) -> f64,
+) -> Result<Value, RuntimeError> {
+  let radians = f(args[0].f64()?);
+  match env.angle_mode() {
+    AngleMode::Radians => Ok(Value::Float(radians)),
+    AngleMode::Degrees => Ok(Value::Float(radians.to_degrees())),
+  }
+}
+
+fn int_arg(val: &Value) -> Result<i64, RuntimeError> {
+  match val {
+    Value::Int(num) => Ok(*num),
+    val => Err(RuntimeError::new(RuntimeErrorKind::ExpectedInt(
+      val.type_name(),
+    ))),
+  }
+}
+
+// Between a and b, both included
+fn random_int(args: &[Value], env: &mut Env) -> Result<Value, RuntimeError> {
+  let (low, high) = (int_arg(&args[0])?, int_arg(&args[1])?);
+  if low > high {
+    return Err(RuntimeError::new(RuntimeErrorKind::EmptyRange(low, high)));
+  }
+  Ok(Value::Int(env.rng().range(low, high)))
+}
+
+fn seed(args: &[Value], env: &mut Env) -> Result<Value, RuntimeError> {
+  let seed = int_arg(&args[0])?;
+  env.seed(seed as u64);
+  Ok(Value::Int(seed))
+}
+
+// Takes "deg" or "rad" and returns it
+fn set_angle_mode(args: &[Value], env: &mut Env) -> Result<Value, RuntimeError> {
+  let mode = match &args[0] {
+    Value::Str(name) => AngleMode::from_name(name),
+    _ => None,
+  };
+  match mode {
+    Some(mode) => {
+      env.set_angle_mode(mode);
+      Ok(Value::Str(mode.name().into()))
+    }
+    None => Err(RuntimeError::new(RuntimeErrorKind::InvalidAngleMode(
+      args[0].to_source(),
+    ))),
+  }
+}
+
+fn list_arg(val: &Value) -> Result<&[Value], RuntimeError> {
+  match val {
+    Value::List(items) => Ok(items),
+    val => Err(RuntimeError::new(RuntimeErrorKind::ExpectedList(
+      val.type_name(),
+    ))),
+  }
+}
+
+// The items of a list as floats, there have to be at least `needed` of them
+fn numbers(name: &'static str, val: &Value, needed: usize) -> Result<Vec<f64>, RuntimeError> {
+  let items = list_arg(val)?;
+  if items.len() < needed {
+    return Err(RuntimeError::new(RuntimeErrorKind::TooFewItems(
+      name,
+      needed,
+      items.len(),
+    )));
+  }
+  items.iter().map(Value::f64).collect()
+}
+
+fn mean_of(nums: &[f64]) -> f64 {
+  nums.iter().sum::<f64>() / nums.len() as f64
+}
+
+// The sample variance, dividing by one less than the count
+fn variance_of(nums: &[f64]) -> f64 {
+  let mean = mean_of(nums);
+  let squares: f64 = nums.iter().map(|num| (num - mean).powi(2)).sum();
+  squares / (nums.len() - 1) as f64
+}
+
+fn median(args: &[Value]) -> Result<Value, RuntimeError> {
+  let mut nums = numbers("median", &args[0], 1)?;
+  nums.sort_by(f64::total_cmp);
+  let middle = nums.len() / 2;
+  if nums.len() % 2 == 1 {
+    Ok(Value::Float(nums[middle]))
+  } else {
+    Ok(Value::Float((nums[middle - 1] + nums[middle]) / 2.0))
+  }
+}
+
+// Stays an int when every item is one
+fn sum(args: &[Value]) -> Result<Value, RuntimeError> {
+  let items = list_arg(&args[0])?;
+  let mut total = Some(0i64);
+  for item in items {
+    total = match (total, item) {
+      (Some(total), Value::Int(num)) => Some(
+        total
+          .checked_add(*num)
+          .ok_or_else(|| RuntimeError::new(RuntimeErrorKind::Overflow))?,
+      ),
+      _ => None,
+    };
+  }
+  match total {
+    Some(total) => Ok(Value::Int(total)),
+    None => Ok(Value::Float(numbers("sum", &args[0], 0)?.iter().sum())),
+  }
+}
+
+// Numbers from the smallest, or strings in alphabetical order
+fn sort(args: &[Value]) -> Result<Value, RuntimeError> {
+  let mut items = list_arg(&args[0])?.to_vec();
+  let texts: Option<Vec<Rc<str>>> = items
+    .iter()
+    .map(|item| match item {
+      Value::Str(text) => Some(text.clone()),
+      _ => None,
+    })
+    .collect();
+  if let Some(mut texts) = texts {
+    texts.sort();
+    items = texts.into_iter().map(Value::Str).collect();
+  } else {
+    let mut keyed = items
+      .into_iter()
+      .map(|item| Ok((item.f64()?, item)))
+      .collect::<Result<Vec<(f64, Value)>, RuntimeError>>()?;
+    keyed.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    items = keyed.into_iter().map(|(_, item)| item).collect();
+  }
+  Ok(Value::List(Rc::new(items)))
+}
+
+// Takes the numbers as arguments or as a single list. Ints stay ints unless
+// there's a float among them, like in arithmetic
+fn extreme(name: &'static str, args: &[Value], greatest: bool) -> Result<Value, RuntimeError> {
+  let items = match args {
+    [Value::List(items)] => &items[..],
+    args => args,
+  };
+  if items.is_empty() {
+    return Err(RuntimeError::new(RuntimeErrorKind::TooFewItems(name, 1, 0)));
+  }
+  let ints: Option<Vec<i64>> = items
+    .iter()
+    .map(|item| match item {
+      Value::Int(num) => Some(*num),
+      _ => None,
+    })
+    .collect();
+  if let Some(ints) = ints {
+    let int = match greatest {
+      true => ints.into_iter().max(),
+      false => ints.into_iter().min(),
+    };
+    return Ok(Value::Int(int.unwrap()));
+  }
+  let nums = items
+    .iter()
+    .map(Value::f64)
+    .collect::<Result<Vec<f64>, RuntimeError>>()?;
+  // NaN isn't smaller or greater than anything, so it's the answer
+  if nums.iter().any(|num| num.is_nan()) {
+    return Ok(Value::Float(f64::NAN));
+  }
+  let pick = if greatest { f64::max } else { f64::min };
+  Ok(Value::Float(nums[1..].iter().copied().fold(nums[0], pick)))
+}
+
+// Ints are already whole, floats stay floats
+fn whole_fn(args: &[Value], f: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+  match &args[0] {
+    Value::Int(num) => Ok(Value::Int(*num)),
+    val => Ok(Value::Float(f(val.f64()?))),
+  }
+}
+
+// Rounds half away from zero to `digits` decimal places, negative counts
+// round to tens, hundreds and so on
+fn round(args: &[Value]) -> Result<Value, RuntimeError> {
+  let digits = match args.get(1) {
+    Some(digits) => int_arg(digits)?,
+    None => 0,
+  };
+  match &args[0] {
+    Value::Int(num) if digits >= 0 => Ok(Value::Int(*num)),
+    Value::Int(num) => {
+      // Rounding to more than 19 digits gives zero just the same
+      let factor = 10i128.pow(-digits.max(-19) as u32);
+      let num = i128::from(*num);
+      let half = factor / 2 * num.signum();
+      let rounded = (num + half) / factor * factor;
+      i64::try_from(rounded)
+        .map(Value::Int)
+        .map_err(|_| RuntimeError::new(RuntimeErrorKind::Overflow))
+    }
+    val => {
+      let num = val.f64()?;
+      // Floats don't have more digits than this either way
+      let digits = digits.clamp(-400, 400) as i32;
+      let rounded = if digits >= 0 {
+        let factor = 10f64.powi(digits);
+        let scaled = num * factor;
+        if scaled.is_finite() {
+          scaled.round() / factor
+        } else {
+          num
+        }
+      } else {
+        let factor = 10f64.powi(-digits);
+        (num / factor).round() * factor
+      };
+      Ok(Value::Float(rounded))
+    }
+  }
+}
+
+fn function_arg(val: &Value) -> Result<Rc<Function>, RuntimeError> {
+  match val {
+    Value::Function(function) => Ok(function.clone()),
+    val => Err(RuntimeError::new(RuntimeErrorKind::ExpectedFunction(
+      val.type_name(),
+    ))),
+  }
+}
+
+// Calls a function of one number
+fn call_at(function: &Function, x: f64, env: &mut Env) -> Result<f64, RuntimeError> {
+  call_function(function, vec![Value::Float(x)], env)?.f64()
+}
+
+// An optional tolerance argument, the env's by default
+fn tolerance_arg(arg: Option<&Value>, env: &Env) -> Result<f64, RuntimeError> {
+  let tolerance = match arg {
+    Some(tolerance) => tolerance.f64()?,
+    None => env.tolerance(),
+  };
+  if !(tolerance >= 0.0 && tolerance.is_finite()) {
+    return Err(RuntimeError::new(RuntimeErrorKind::InvalidTolerance(
+      tolerance,
+    )));
+  }
+  Ok(tolerance)
+}
+
+// The optional tolerance and iteration limit after the other arguments
+fn search_limits(
+  args: &[Value],
+  env: &Env,
+  default_iterations: i64,
+) -> Result<(f64, i64), RuntimeError> {
+  let tolerance = tolerance_arg(args.first(), env)?;
+  let iterations = match args.get(1) {
+    Some(iterations) => int_arg(iterations)?,
+    None => default_iterations,
+  };
+  if iterations <= 0 {
+    return Err(RuntimeError::new(RuntimeErrorKind::InvalidIterations(
+      iterations,
+    )));
+  }
+  Ok((tolerance, iterations))
+}
+
+// Newton's method from x0, with the slope estimated by central differences
+fn solve(args: &[Value], env: &mut Env) -> Result<Value, RuntimeError> {
+  let function = function_arg(&args[0])?;
+  let mut x = args[1].f64()?;
+  let (tolerance, iterations) = search_limits(&args[2..], env, 100)?;
+  for _ in 0..iterations {
+    let y = call_at(&function, x, env)?;
+    if y.abs() <= tolerance {
+      return Ok(Value::Float(x));
+    }
+    let h = 1e-7 * x.abs().max(1.0);
+    let slope = (call_at(&function, x + h, env)? - call_at(&function, x - h, env)?) / (2.0 * h);
+    if slope == 0.0 || !slope.is_finite() {
+      return Err(RuntimeError::new(RuntimeErrorKind::FlatSlope(x)));
+    }
+    let step = y / slope;
+    x -= step;
+    if step.abs() <= tolerance * x.abs().max(1.0) {
+      return Ok(Value::Float(x));
+    }
+  }
+  Err(RuntimeError::new(RuntimeErrorKind::NoConvergence(
+    "solve", iterations,
+  )))
+}
+
+// Halves [a, b] until it's narrower than the tolerance, the function has to
+// change sign in it
+fn bisect(args: &[Value], env: &mut Env) -> Result<Value, RuntimeError> {
+  let function = function_arg(&args[0])?;
+  let (mut a, mut b) = (args[1].f64()?, args[2].f64()?);
+  let (tolerance, iterations) = search_limits(&args[3..], env, 200)?;
+  let (mut fa, fb) = (call_at(&function, a, env)?, call_at(&function, b, env)?);
+  if fa == 0.0 {
+    return Ok(Value::Float(a));
+  }
+  if fb == 0.0 {
+    return Ok(Value::Float(b));
+  }
+  if fa.signum() == fb.signum() || fa.is_nan() || fb.is_nan() {
+    return Err(RuntimeError::new(RuntimeErrorKind::NoSignChange(a, b)));
+  }
+  for _ in 0..iterations {
+    let mid = a + (b - a) / 2.0;
+    let fmid = call_at(&function, mid, env)?;
+    if fmid == 0.0 || (b - a).abs() / 2.0 <= tolerance {
+      return Ok(Value::Float(mid));
+    }
+    if fmid.signum() == fa.signum() {
+      a = mid;
+      fa = fmid;
+    } else {
+      b = mid;
+    }
+  }
+  Err(RuntimeError::new(RuntimeErrorKind::NoConvergence(
+    "bisect", iterations,
+  )))
+}
+
+// How many times integrate may halve an interval, enough for smooth
+// functions without taking forever on ones that aren't
+const MAX_INTEGRATION_DEPTH: u32 = 16;
+
+// Samples of the function at the ends and the middle of an interval
+struct Panel {
+  a: f64,
+  b: f64,
+  fa: f64,
+  fm: f64,
+  fb: f64,
+}
+
+impl Panel {
+  fn simpson(&self) -> f64 {
+    (self.b - self.a) / 6.0 * (self.fa + 4.0 * self.fm + self.fb)
+  }
+}
+
+// Adaptive Simpson's rule, halving the panel until both halves together
+// agree with the whole
+fn integrate_panel(
+  function: &Function,
+  panel: Panel,
+  tolerance: f64,
+  depth: u32,
+  env: &mut Env,
+) -> Result<f64, RuntimeError> {
+  let m = panel.a + (panel.b - panel.a) / 2.0;
+  let left = Panel {
+    a: panel.a,
+    b: m,
+    fa: panel.fa,
+    fm: call_at(function, (panel.a + m) / 2.0, env)?,
+    fb: panel.fm,
+  };
+  let right = Panel {
+    a: m,
+    b: panel.b,
+    fa: panel.fm,
+    fm: call_at(function, (m + panel.b) / 2.0, env)?,
+    fb: panel.fb,
+  };
+  let halves = left.simpson() + right.simpson();
+  let error = halves - panel.simpson();
+  if depth == 0 || !error.is_finite() || error.abs() <= 15.0 * tolerance {
+    return Ok(halves + error / 15.0);
+  }
+  Ok(
+    integrate_panel(function, left, tolerance / 2.0, depth - 1, env)?
+      + integrate_panel(function, right, tolerance / 2.0, depth - 1, env)?,
+  )
+}
+
+fn integrate(args: &[Value], env: &mut Env) -> Result<Value, RuntimeError> {
+  let function = function_arg(&args[0])?;
+  let (a, b) = (args[1].f64()?, args[2].f64()?);
+  let tolerance = tolerance_arg(args.get(3), env)?;
+  let panel = Panel {
+    a,
+    b,
+    fa: call_at(&function, a, env)?,
+    fm: call_at(&function, a + (b - a) / 2.0, env)?,
+    fb: call_at(&function, b, env)?,
+  };
+  let area = integrate_panel(&function, panel, tolerance, MAX_INTEGRATION_DEPTH, env)?;
+  Ok(Value::Float(area))
+}
+
+// Checks the ends of an interval to go through from a to b
+pub fn interval(a: f64, b: f64) -> Result<(f64, f64), RuntimeError> {
+  if !(a < b && a.is_finite() && b.is_finite()) {
+    return Err(RuntimeError::new(RuntimeErrorKind::InvalidInterval(a, b)));
+  }
+  Ok((a, b))
+}
+
+// Returns the graph as a string, so it shows up like any other result
+fn plot(args: &[Value], env: &mut Env) -> Result<Value, RuntimeError> {
+  let function = function_arg(&args[0])?;
+  let (a, b) = interval(args[1].f64()?, args[2].f64()?)?;
+  let xs = plot::sample_points(a, b);
+  let ys = xs
+    .iter()
+    .map(|&x| call_at(&function, x, env))
+    .collect::<Result<Vec<f64>, RuntimeError>>()?;
+  match plot::render(&xs, &ys) {
+    Some(graph) => Ok(Value::Str(graph.into())),
+    None => Err(RuntimeError::new(RuntimeErrorKind::NothingToPlot(a, b))),
+  }
+}
+
+// Tables longer than this are almost always a step that's too small
+pub const MAX_TABLE_ROWS: usize = 10_000;
+
+// Two right-aligned columns with a header over each
+fn format_table(header: [String; 2], rows: Vec<[String; 2]>) -> String {
+  let width = |column: usize| {
+    let cells = rows.iter().map(|row| row[column].chars().count());
+    cells.fold(header[column].chars().count(), usize::max)
+  };
+  let (left, right) = (width(0), width(1));
+  let mut lines = vec![
+    format!(
+      "{:>left$} | {:>right$}",
+      header[0],
+      header[1],
+      left = left,
+      right = right
+    ),
+    format!("{}-+-{}", "-".repeat(left), "-".repeat(right)),
+  ];
+  for [x, y] in rows {
+    lines.push(format!(
+      "{:>left$} | {:>right$}",
+      x,
+      y,
+      left = left,
+      right = right
+    ));
+  }
+  lines.join("\n")
+}
+
+// The values of f from a to b, returned as a string like plot does. Stays
+// in ints when a, b and the step all are
+fn table(args: &[Value], env: &mut Env) -> Result<Value, RuntimeError> {
+  let function = function_arg(&args[0])?;
+  let (a, b, step) = (args[1].f64()?, args[2].f64()?, args[3].f64()?);
+  if !(step > 0.0 && step.is_finite()) {
+    return Err(RuntimeError::new(RuntimeErrorKind::InvalidStep(step)));
+  }
+  if !(a <= b && a.is_finite() && b.is_finite()) {
+    return Err(RuntimeError::new(RuntimeErrorKind::InvalidInterval(a, b)));
+  }
+  // A little slack, so steps like 0.1 don't miss b to rounding
+  let rows = ((b - a) / step + 1e-9).floor() + 1.0;
+  if rows > MAX_TABLE_ROWS as f64 {
+    return Err(RuntimeError::new(RuntimeErrorKind::TooManyRows(rows)));
+  }
+  let ints = match (&args[1], &args[2], &args[3]) {
+    (Value::Int(a), Value::Int(_), Value::Int(step)) => Some((*a, *step)),
+    _ => None,
+  };
+  let param = function.params.first().map_or("x", Symbol::as_str);
+  let header = [param.to_owned(), format!("{}({})", function.name, param)];
+  let rows = (0..rows as i64)
+    .map(|row| {
+      let x = match ints {
+        Some((a, step)) => Value::Int(
+          row
+            .checked_mul(step)
+            .and_then(|offset| offset.checked_add(a))
+            .ok_or_else(|| RuntimeError::new(RuntimeErrorKind::Overflow))?,
+        ),
+        // Adding up steps like 0.1 drifts in the last digits
+        None => Value::Float(round_significant(a + row as f64 * step, 12)),
+      };
+      let y = call_function(&function, vec![x.clone()], env)?;
+      Ok([env.display(&x), env.display(&y)])
+    })
+    .collect::<Result<Vec<_>, RuntimeError>>()?;
+  Ok(Value::Str(format_table(header, rows).into()))
+}
+
+fn abs(args: &[Value]) -> Result<Value, RuntimeError> {
+  match &args[0] {
+    Value::Int(num) => num
+      .checked_abs()
+      .map(Value::Int)
+      .ok_or_else(|| RuntimeError::new(RuntimeErrorKind::Overflow)),
+    val => Ok(Value::Float(val.f64()?.abs())),
+  }
+}
+
+// Returns the new tolerance
+fn set_tolerance(args: &[Value], env: &mut Env) -> Result<Value, RuntimeError> {
+  let tolerance = args[0].f64()?;
+  if !(tolerance >= 0.0 && tolerance.is_finite()) {
+    return Err(RuntimeError::new(RuntimeErrorKind::InvalidTolerance(
+      tolerance,
+    )));
+  }
+  env.set_tolerance(tolerance);
+  Ok(Value::Float(tolerance))
+}
+
+fn type_of(args: &[Value]) -> Result<Value, RuntimeError> {
+  Ok(Value::Str(args[0].type_name().into()))
+}
+
+fn cant_convert(val: &Value, to: &'static str) -> RuntimeError {
+  RuntimeError::new(RuntimeErrorKind::CantConvert(val.to_source(), to))
+}
+
+// Floats are truncated towards zero, strings have to be written like ints
+fn to_int(args: &[Value]) -> Result<Value, RuntimeError> {
+  let val = &args[0];
+  match val {
+    Value::Int(num) => Ok(Value::Int(*num)),
+    // The range check has to be on the float side, i64::MAX isn't a float
+    Value::Float(num) if num.is_finite() && num.abs() < 9_223_372_036_854_775_808.0 => {
+      Ok(Value::Int(num.trunc() as i64))
+    }
+    Value::Bool(b) => Ok(Value::Int(*b as i64)),
+    Value::Str(text) => text
+      .trim()
+      .parse::<i64>()
+      .map(Value::Int)
+      .map_err(|_| cant_convert(val, "int")),
+    _ => Err(cant_convert(val, "int")),
+  }
+}
+
+fn to_float(args: &[Value]) -> Result<Value, RuntimeError> {
+  let val = &args[0];
+  match val {
+    Value::Str(text) => text
+      .trim()
+      .parse::<f64>()
+      .map(Value::Float)
+      .map_err(|_| cant_convert(val, "float")),
+    Value::Function(_) => Err(cant_convert(val, "float")),
+    val => Ok(Value::Float(val.f64()?)),
+  }
+}
+
+// Zero is false and every other number true, strings have to say which
+fn to_bool(args: &[Value]) -> Result<Value, RuntimeError> {
+  let val = &args[0];
+  match val {
+    Value::Bool(b) => Ok(Value::Bool(*b)),
+    Value::Int(num) => Ok(Value::Bool(*num != 0)),
+    Value::Float(num) if !num.is_nan() => Ok(Value::Bool(*num != 0.0)),
+    Value::Str(text) => match text.trim() {
+      "true" => Ok(Value::Bool(true)),
+      "false" => Ok(Value::Bool(false)),
+      _ => Err(cant_convert(val, "bool")),
+    },
+    _ => Err(cant_convert(val, "bool")),
+  }
+}
+
+pub const BUILTINS: &[Builtin] = &[
+  Builtin {
+    name: "sin",
+    arity: Arity::Exact(1),
+    call: |args, env| angle_fn(args, env, f64::sin),
+    cached: false,
+  },
+  Builtin {
+    name: "cos",
+    arity: Arity::Exact(1),
+    call: |args, env| angle_fn(args, env, f64::cos),
+    cached: false,
+  },
+  Builtin {
+    name: "tan",
+    arity: Arity::Exact(1),
+    call: |args, env| angle_fn(args, env, f64::tan),
+    cached: false,
+  },
+  Builtin {
+    name: "asin",
+    arity: Arity::Exact(1),
+    call: |args, env| inverse_angle_fn(args, env, f64::asin),
+    cached: false,
+  },
+  Builtin {
+    name: "acos",
+    arity: Arity::Exact(1),
+    call: |args, env| inverse_angle_fn(args, env, f64::acos),
+    cached: false,
+  },
+  Builtin {
+    name: "atan",
+    arity: Arity::Exact(1),
+    call: |args, env| inverse_angle_fn(args, env, f64::atan),
+    cached: false,
+  },
+  Builtin {
+    name: "sqrt",
+    arity: Arity::Exact(1),
+    call: |args, _| float_fn(args, f64::sqrt),
+    cached: false,
+  },
+  Builtin {
+    name: "exp",
+    arity: Arity::Exact(1),
+    call: |args, _| float_fn(args, f64::exp),
+    cached: false,
+  },
+  Builtin {
+    name: "ln",
+    arity: Arity::Exact(1),
+    call: |args, _| float_fn(args, f64::ln),
+    cached: false,
+  },
+  Builtin {
+    name: "round",
+    arity: Arity::Between(1, 2),
+    call: |args, _| round(args),
+    cached: false,
+  },
+  Builtin {
+    name: "trunc",
+    arity: Arity::Exact(1),
+    call: |args, _| whole_fn(args, f64::trunc),
+    cached: false,
+  },
+  Builtin {
+    name: "floor",
+    arity: Arity::Exact(1),
+    call: |args, _| whole_fn(args, f64::floor),
+    cached: false,
+  },
+  Builtin {
+    name: "ceil",
+    arity: Arity::Exact(1),
+    call: |args, _| whole_fn(args, f64::ceil),
+    cached: false,
+  },
+  Builtin {
+    name: "abs",
+    arity: Arity::Exact(1),
+    call: |args, _| abs(args),
+    cached: false,
+  },
+  Builtin {
+    name: "min",
+    arity: Arity::AtLeast(1),
+    call: |args, _| extreme("min", args, false),
+    cached: false,
+  },
+  Builtin {
+    name: "max",
+    arity: Arity::AtLeast(1),
+    call: |args, _| extreme("max", args, true),
+    cached: false,
+  },
+  Builtin {
+    name: "solve",
+    arity: Arity::Between(2, 4),
+    call: solve,
+    cached: true,
+  },
+  Builtin {
+    name: "bisect",
+    arity: Arity::Between(3, 5),
+    call: bisect,
+    cached: true,
+  },
+  Builtin {
+    name: "integrate",
+    arity: Arity::Between(3, 4),
+    call: integrate,
+    cached: true,
+  },
+  Builtin {
+    name: "table",
+    arity: Arity::Exact(4),
+    call: table,
+    cached: false,
+  },
+  Builtin {
+    name: "plot",
+    arity: Arity::Exact(3),
+    call: plot,
+    cached: false,
+  },
+  Builtin {
+    name: "mean",
+    arity: Arity::Exact(1),
+    call: |args, _| Ok(Value::Float(mean_of(&numbers("mean", &args[0], 1)?))),
+    cached: false,
+  },
+  Builtin {
+    name: "median",
+    arity: Arity::Exact(1),
+    call: |args, _| median(args),
+    cached: false,
+  },
+  Builtin {
+    name: "variance",
+    arity: Arity::Exact(1),
+    call: |args, _| {
+      Ok(Value::Float(variance_of(&numbers(
+        "variance", &args[0], 2,
+      )?)))
+    },
+    cached: false,
+  },
+  Builtin {
+    name: "stddev",
+    arity: Arity::Exact(1),
+    call: |args, _| {
+      let nums = numbers("stddev", &args[0], 2)?;
+      Ok(Value::Float(variance_of(&nums).sqrt()))
+    },
+    cached: false,
+  },
+  Builtin {
+    name: "sum",
+    arity: Arity::Exact(1),
+    call: |args, _| sum(args),
+    cached: false,
+  },
+  Builtin {
+    name: "sort",
+    arity: Arity::Exact(1),
+    call: |args, _| sort(args),
+    cached: false,
+  },
+  Builtin {
+    name: "typeof",
+    arity: Arity::Exact(1),
+    call: |args, _| type_of(args),
+    cached: false,
+  },
+  Builtin {
+    name: "int",
+    arity: Arity::Exact(1),
+    call: |args, _| to_int(args),
+    cached: false,
+  },
+  Builtin {
+    name: "float",
+    arity: Arity::Exact(1),
+    call: |args, _| to_float(args),
+    cached: false,
+  },
+  Builtin {
+    name: "bool",
+    arity: Arity::Exact(1),
+    call: |args, _| to_bool(args),
+    cached: false,
+  },
+  Builtin {
+    name: "random",
+    arity: Arity::Exact(0),
+    call: |_, env| Ok(Value::Float(env.rng().next_f64())),
+    cached: false,
+  },
+  Builtin {
+    name: "random_int",
+    arity: Arity::Exact(2),
+    call: random_int,
+    cached: false,
+  },
+  Builtin {
+    name: "seed",
+    arity: Arity::Exact(1),
+    call: seed,
+    cached: false,
+  },
+  Builtin {
+    name: "set_angle_mode",
+    arity: Arity::Exact(1),
+    call: set_angle_mode,
+    cached: false,
+  },
+  Builtin {
+    name: "set_tolerance",
+    arity: Arity::Exact(1),
+    call: set_tolerance,
+    cached: false,
+  },
+];
+
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+  BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn overflows(result: Result<Value, RuntimeError>) -> bool {
+    matches!(result, Err(err) if err.kind == RuntimeErrorKind::Overflow)
+  }
+
+  #[test]
+  fn abs_of_the_smallest_int_overflows() {
+    assert!(overflows(abs(&[Value::Int(i64::MIN)])));
+    assert_eq!(abs(&[Value::Int(i64::MIN + 1)]), Ok(Value::Int(i64::MAX)));
+  }
+
+  #[test]
+  fn rounding_ints_to_any_number_of_tens() {
+    assert_eq!(
+      round(&[Value::Int(5), Value::Int(i64::MIN)]),
+      Ok(Value::Int(0))
+    );
+    assert_eq!(
+      round(&[Value::Int(150), Value::Int(-2)]),
+      Ok(Value::Int(200))
+    );
+    assert_eq!(
+      round(&[Value::Int(-150), Value::Int(-2)]),
+      Ok(Value::Int(-200))
+    );
+  }
+}
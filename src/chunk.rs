@@ -0,0 +1,431 @@
+use crate::error::{ChunkError, Source, Span};
+use crate::expr::{BinOp, Expr, Function, SeriesKind, UnOp, Value};
+use crate::prelude::*;
+use crate::symbol::Symbol;
+use alloc::rc::Rc;
+use core::convert::TryFrom;
+
+// Compiled programs, saved so running them again skips lexing and parsing.
+// A chunk is the program's source, kept for error messages, followed by
+// its expressions as instructions in postfix order: every instruction pops
+// its operands off a stack and pushes the expression they make up. Whatever
+// is left on the stack at the end is the program.
+
+const MAGIC: &[u8] = b"MATHC";
+// Bumped whenever the instructions change, old chunks have to be compiled
+// again
+pub const VERSION: u8 = 5;
+pub const EXTENSION: &str = "mathc";
+
+const BINARY_OPS: &[BinOp] = &[
+  BinOp::Pow,
+  BinOp::Mul,
+  BinOp::Div,
+  BinOp::FloorDiv,
+  BinOp::Add,
+  BinOp::Sub,
+  BinOp::Less,
+  BinOp::LessEqual,
+  BinOp::Greater,
+  BinOp::GreaterEqual,
+  BinOp::Equal,
+  BinOp::NotEqual,
+  BinOp::BitAnd,
+  BinOp::BitOr,
+  BinOp::BitXor,
+  BinOp::ShiftLeft,
+  BinOp::ShiftRight,
+];
+const UNARY_OPS: &[UnOp] = &[UnOp::Neg, UnOp::Sqrt, UnOp::Percent, UnOp::Not];
+const SERIES_KINDS: &[SeriesKind] = &[SeriesKind::Sum, SeriesKind::Product];
+
+mod op {
+  pub const LITERAL: u8 = 0;
+  pub const VAR: u8 = 1;
+  pub const LET: u8 = 2;
+  pub const PRINT: u8 = 3;
+  pub const ASSERT: u8 = 4;
+  pub const BINARY: u8 = 5;
+  pub const UNARY: u8 = 6;
+  pub const IF: u8 = 7;
+  pub const CALL: u8 = 8;
+  pub const FN: u8 = 9;
+  pub const LIST: u8 = 10;
+  pub const INTERPOLATION: u8 = 11;
+  pub const SERIES: u8 = 12;
+  // Ends the body of a function
+  pub const END: u8 = 13;
+  pub const IMPORT: u8 = 14;
+  pub const MODULE: u8 = 15;
+}
+
+mod tag {
+  pub const INT: u8 = 0;
+  pub const FLOAT: u8 = 1;
+  pub const BOOL: u8 = 2;
+  pub const STR: u8 = 3;
+  pub const LIST: u8 = 4;
+  pub const FUNCTION: u8 = 5;
+}
+
+#[derive(Debug, Clone)]
+pub struct Chunk {
+  pub source: String,
+  pub exprs: Vec<Expr>,
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+  fn u8(&mut self, byte: u8) {
+    self.0.push(byte);
+  }
+
+  fn u64(&mut self, num: u64) {
+    self.0.extend_from_slice(&num.to_le_bytes());
+  }
+
+  fn len(&mut self, len: usize) {
+    self.u64(len as u64);
+  }
+
+  fn str(&mut self, text: &str) {
+    self.len(text.len());
+    self.0.extend_from_slice(text.as_bytes());
+  }
+
+  fn span(&mut self, span: Span) {
+    self.len(span.start);
+    self.len(span.end);
+  }
+
+  fn function(&mut self, function: &Function) {
+    self.str(&function.name);
+    self.len(function.params.len());
+    for param in &function.params {
+      self.str(param);
+    }
+    self.expr(&function.body);
+    self.u8(op::END);
+  }
+
+  fn value(&mut self, val: &Value) {
+    match val {
+      Value::Int(num) => {
+        self.u8(tag::INT);
+        self.u64(*num as u64);
+      }
+      Value::Float(num) => {
+        self.u8(tag::FLOAT);
+        self.u64(num.to_bits());
+      }
+      Value::Bool(b) => {
+        self.u8(tag::BOOL);
+        self.u8(*b as u8);
+      }
+      Value::Str(text) => {
+        self.u8(tag::STR);
+        self.str(text);
+      }
+      Value::List(vals) => {
+        self.u8(tag::LIST);
+        self.len(vals.len());
+        for val in vals.iter() {
+          self.value(val);
+        }
+      }
+      Value::Function(function) => {
+        self.u8(tag::FUNCTION);
+        self.function(function);
+      }
+    }
+  }
+
+  fn exprs(&mut self, exprs: &[Expr]) {
+    for expr in exprs {
+      self.expr(expr);
+    }
+  }
+
+  // Operands first, then the instruction
+  fn expr(&mut self, expr: &Expr) {
+    match expr {
+      Expr::Literal(val, span) => {
+        self.u8(op::LITERAL);
+        self.value(val);
+        self.span(*span);
+      }
+      Expr::Var(name, span) => {
+        self.u8(op::VAR);
+        self.str(name);
+        self.span(*span);
+      }
+      Expr::VarDeclaration(name, val, span) => {
+        self.expr(val);
+        self.u8(op::LET);
+        self.str(name);
+        self.span(*span);
+      }
+      Expr::Print(vals, span) => {
+        self.exprs(vals);
+        self.u8(op::PRINT);
+        self.len(vals.len());
+        self.span(*span);
+      }
+      Expr::Assert(cond, span) => {
+        self.expr(cond);
+        self.u8(op::ASSERT);
+        self.span(*span);
+      }
+      Expr::Binary(binary, left, right, span) => {
+        self.expr(left);
+        self.expr(right);
+        self.u8(op::BINARY);
+        self.u8(BINARY_OPS.iter().position(|op| op == binary).unwrap() as u8);
+        self.span(*span);
+      }
+      Expr::Unary(unary, val, span) => {
+        self.expr(val);
+        self.u8(op::UNARY);
+        self.u8(UNARY_OPS.iter().position(|op| op == unary).unwrap() as u8);
+        self.span(*span);
+      }
+      Expr::Conditional(cond, val_if_true, val_if_false, span) => {
+        self.expr(cond);
+        self.expr(val_if_true);
+        self.expr(val_if_false);
+        self.u8(op::IF);
+        self.span(*span);
+      }
+      Expr::Call(name, args, span) => {
+        self.exprs(args);
+        self.u8(op::CALL);
+        self.str(name);
+        self.len(args.len());
+        self.span(*span);
+      }
+      // The body isn't an operand, it has to stay unevaluated until a call
+      Expr::FnDeclaration(function, span) => {
+        self.u8(op::FN);
+        self.function(function);
+        self.span(*span);
+      }
+      Expr::List(items, span) => {
+        self.exprs(items);
+        self.u8(op::LIST);
+        self.len(items.len());
+        self.span(*span);
+      }
+      Expr::Interpolation(parts, span) => {
+        self.exprs(parts);
+        self.u8(op::INTERPOLATION);
+        self.len(parts.len());
+        self.span(*span);
+      }
+      Expr::Series(kind, index, from, to, term, span) => {
+        self.expr(from);
+        self.expr(to);
+        self.expr(term);
+        self.u8(op::SERIES);
+        self.u8(SERIES_KINDS.iter().position(|k| k == kind).unwrap() as u8);
+        self.str(index);
+        self.span(*span);
+      }
+      Expr::Import(path, span) => {
+        self.u8(op::IMPORT);
+        self.str(path);
+        self.span(*span);
+      }
+      Expr::Module(name, body, span) => {
+        self.exprs(body);
+        self.u8(op::MODULE);
+        self.str(name);
+        self.len(body.len());
+        self.span(*span);
+      }
+    }
+  }
+}
+
+pub fn compile(source: &str, exprs: &[Expr]) -> Vec<u8> {
+  let mut writer = Writer(MAGIC.to_vec());
+  writer.u8(VERSION);
+  writer.str(source);
+  writer.exprs(exprs);
+  writer.0
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  position: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn bytes(&mut self, len: usize) -> Result<&'a [u8], ChunkError> {
+    let end = self
+      .position
+      .checked_add(len)
+      .filter(|&end| end <= self.bytes.len())
+      .ok_or(ChunkError::Damaged)?;
+    let bytes = &self.bytes[self.position..end];
+    self.position = end;
+    Ok(bytes)
+  }
+
+  fn u8(&mut self) -> Result<u8, ChunkError> {
+    Ok(self.bytes(1)?[0])
+  }
+
+  fn u64(&mut self) -> Result<u64, ChunkError> {
+    let mut num = [0; 8];
+    num.copy_from_slice(self.bytes(8)?);
+    Ok(u64::from_le_bytes(num))
+  }
+
+  fn len(&mut self) -> Result<usize, ChunkError> {
+    usize::try_from(self.u64()?).map_err(|_| ChunkError::Damaged)
+  }
+
+  fn str(&mut self) -> Result<String, ChunkError> {
+    let len = self.len()?;
+    let bytes = self.bytes(len)?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| ChunkError::Damaged)
+  }
+
+  fn symbol(&mut self) -> Result<Symbol, ChunkError> {
+    self.str().map(Symbol::from)
+  }
+
+  fn span(&mut self) -> Result<Span, ChunkError> {
+    Ok(Span {
+      start: self.len()?,
+      end: self.len()?,
+    })
+  }
+
+  fn index<T: Copy>(&mut self, table: &[T]) -> Result<T, ChunkError> {
+    let index = self.u8()?;
+    table
+      .get(index as usize)
+      .copied()
+      .ok_or(ChunkError::Damaged)
+  }
+
+  fn function(&mut self) -> Result<Function, ChunkError> {
+    let name = self.str()?;
+    let params = (0..self.len()?)
+      .map(|_| self.symbol())
+      .collect::<Result<Vec<Symbol>, ChunkError>>()?;
+    let mut body = self.exprs(Some(op::END))?;
+    if body.len() != 1 {
+      return Err(ChunkError::Damaged);
+    }
+    Ok(Function {
+      name,
+      params,
+      body: body.remove(0),
+      source: Source::Input,
+    })
+  }
+
+  fn value(&mut self) -> Result<Value, ChunkError> {
+    Ok(match self.u8()? {
+      tag::INT => Value::Int(self.u64()? as i64),
+      tag::FLOAT => Value::Float(f64::from_bits(self.u64()?)),
+      tag::BOOL => Value::Bool(self.u8()? != 0),
+      tag::STR => Value::Str(self.str()?.into()),
+      tag::LIST => {
+        let vals = (0..self.len()?)
+          .map(|_| self.value())
+          .collect::<Result<Vec<Value>, ChunkError>>()?;
+        Value::List(Rc::new(vals))
+      }
+      tag::FUNCTION => Value::Function(Rc::new(self.function()?)),
+      _ => return Err(ChunkError::Damaged),
+    })
+  }
+
+  // Runs instructions until `end`, or the end of the chunk when it's None
+  fn exprs(&mut self, end: Option<u8>) -> Result<Vec<Expr>, ChunkError> {
+    let mut stack: Vec<Expr> = Vec::new();
+    let pop = |stack: &mut Vec<Expr>, count: usize| {
+      let start = stack.len().checked_sub(count).ok_or(ChunkError::Damaged)?;
+      Ok::<Vec<Expr>, ChunkError>(stack.split_off(start))
+    };
+    let pop_one = |stack: &mut Vec<Expr>| stack.pop().map(Box::new).ok_or(ChunkError::Damaged);
+    loop {
+      if end.is_none() && self.position == self.bytes.len() {
+        return Ok(stack);
+      }
+      let expr = match self.u8()? {
+        byte if Some(byte) == end => return Ok(stack),
+        op::LITERAL => Expr::Literal(self.value()?, self.span()?),
+        op::VAR => Expr::Var(self.symbol()?, self.span()?),
+        op::LET => {
+          let val = pop_one(&mut stack)?;
+          Expr::VarDeclaration(self.symbol()?, val, self.span()?)
+        }
+        op::PRINT => Expr::Print(pop(&mut stack, self.len()?)?, self.span()?),
+        op::ASSERT => Expr::Assert(pop_one(&mut stack)?, self.span()?),
+        op::BINARY => {
+          let right = pop_one(&mut stack)?;
+          let left = pop_one(&mut stack)?;
+          Expr::Binary(self.index(BINARY_OPS)?, left, right, self.span()?)
+        }
+        op::UNARY => {
+          let val = pop_one(&mut stack)?;
+          Expr::Unary(self.index(UNARY_OPS)?, val, self.span()?)
+        }
+        op::IF => {
+          let val_if_false = pop_one(&mut stack)?;
+          let val_if_true = pop_one(&mut stack)?;
+          let cond = pop_one(&mut stack)?;
+          Expr::Conditional(cond, val_if_true, val_if_false, self.span()?)
+        }
+        op::CALL => {
+          let name = self.str()?;
+          let args = pop(&mut stack, self.len()?)?;
+          Expr::Call(name, args, self.span()?)
+        }
+        op::FN => Expr::FnDeclaration(Rc::new(self.function()?), self.span()?),
+        op::LIST => Expr::List(pop(&mut stack, self.len()?)?, self.span()?),
+        op::INTERPOLATION => Expr::Interpolation(pop(&mut stack, self.len()?)?, self.span()?),
+        op::SERIES => {
+          let term = pop_one(&mut stack)?;
+          let to = pop_one(&mut stack)?;
+          let from = pop_one(&mut stack)?;
+          let (kind, index) = (self.index(SERIES_KINDS)?, self.symbol()?);
+          Expr::Series(kind, index, from, to, term, self.span()?)
+        }
+        op::IMPORT => Expr::Import(self.str()?, self.span()?),
+        op::MODULE => {
+          let name = self.str()?;
+          Expr::Module(name, pop(&mut stack, self.len()?)?, self.span()?)
+        }
+        _ => return Err(ChunkError::Damaged),
+      };
+      stack.push(expr);
+    }
+  }
+}
+
+pub fn is_chunk(bytes: &[u8]) -> bool {
+  bytes.starts_with(MAGIC)
+}
+
+pub fn load(bytes: &[u8]) -> Result<Chunk, ChunkError> {
+  if !is_chunk(bytes) {
+    return Err(ChunkError::NotAChunk);
+  }
+  let mut reader = Reader {
+    bytes,
+    position: MAGIC.len(),
+  };
+  let version = reader.u8()?;
+  if version != VERSION {
+    return Err(ChunkError::WrongVersion(version));
+  }
+  let source = reader.str()?;
+  let exprs = reader.exprs(None)?;
+  Ok(Chunk { source, exprs })
+}
@@ -0,0 +1,408 @@
+use crate::bench::DEFAULT_ITERATIONS;
+use math_parser::expr::{AngleMode, EnvBuilder};
+use math_parser::lint::{Level, Lint, LINTS};
+use math_parser::parser::ParseOptions;
+
+// The usage is put together from parts, leaving out what needs the json
+// feature when it's off
+const COMMANDS: &str = "\
+Usage:
+  math-parser                       start the REPL, or run a program piped into stdin
+  math-parser <file>...             run the files in order, sharing variables, compiled
+                                    ones too
+  math-parser compile <file> [-o <out>]
+                                    compile a file to bytecode that runs without parsing,
+                                    saved next to it as .mathc unless -o says where
+  math-parser repl [--session <name>]
+                                    start the REPL, optionally keeping a named session
+  math-parser -e, --eval <code>     evaluate code and print every result
+  math-parser --tokens <file>       print the tokens of a file
+  math-parser --ast <file> [--ast-format <debug|sexpr>]
+                                    print the syntax tree of a file, as Rust debug output
+                                    or as s-expressions
+  math-parser bench <file> [--iters <n>]
+                                    time evaluating a file over and over
+  math-parser test <dir>            run the asserts of every *_test.math file in a
+                                    directory
+  math-parser fmt [<file>...] [--check]
+                                    rewrite files in the canonical layout, or stdin to
+                                    stdout, with --check only list what would change
+  math-parser lint <file>...        report the lints' warnings for files without running
+                                    them";
+
+#[cfg(feature = "json")]
+const JSON_COMMANDS: &str = "
+  math-parser --from-json [file]    evaluate an expression tree serialized as JSON, read
+                                    from stdin unless a file is given
+  math-parser lsp                   serve the Language Server Protocol over stdio, for
+                                    editors to show errors, values and definitions";
+
+const OPTIONS: &str = "
+
+Lints:
+  --allow, --warn, --deny <lint>    set how strict a lint is, or all of them with \"warnings\"
+  --exact                           the same as --warn inexact-float
+
+Evaluation:
+  --check                           find type errors before running anything
+  --optimize                        evaluate the constant parts of the program before
+                                    running it
+  --degrees                         measure angles in degrees instead of radians
+  --no-prelude                      start without the standard library's constants and
+                                    functions, like pi and gcd
+  --tolerance <t>                   how close floats have to be to count as equal,
+                                    0.000001 unless changed
+  --precision <n>                   show floats with at most n significant digits
+  --time                            report how long each statement of a program took";
+
+#[cfg(feature = "json")]
+const JSON_OPTIONS: &str = "
+  --json                            print results, printed lines and errors as JSON, one
+                                    object per line, for other programs to read";
+
+const OTHER: &str = "
+
+Other:
+  -h, --help                        show this message
+  -V, --version                     show the version";
+
+pub fn usage() -> String {
+  let mut usage = String::from(COMMANDS);
+  #[cfg(feature = "json")]
+  usage.push_str(JSON_COMMANDS);
+  usage.push_str(OPTIONS);
+  #[cfg(feature = "json")]
+  usage.push_str(JSON_OPTIONS);
+  usage.push_str(OTHER);
+  usage
+}
+
+pub enum Command {
+  // No arguments, what happens depends on whether stdin is a terminal
+  Default,
+  Repl {
+    session: Option<String>,
+  },
+  Run {
+    paths: Vec<String>,
+  },
+  Eval {
+    code: String,
+  },
+  Tokens {
+    path: String,
+  },
+  Ast {
+    path: String,
+    format: AstFormat,
+  },
+  // Reads stdin without a path
+  FromJson {
+    path: Option<String>,
+  },
+  Bench {
+    path: String,
+    iterations: usize,
+  },
+  Test {
+    path: String,
+  },
+  Compile {
+    path: String,
+    output: Option<String>,
+  },
+  // Formats stdin to stdout without paths
+  Fmt {
+    paths: Vec<String>,
+    check: bool,
+  },
+  // Warnings without running anything
+  Lint {
+    paths: Vec<String>,
+  },
+  // A language server for editors, on stdin and stdout
+  Lsp,
+  Help,
+  Version,
+}
+
+// How running programs reports what happened
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+  Human,
+  #[cfg(feature = "json")]
+  Json,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AstFormat {
+  Debug,
+  Sexpr,
+}
+
+impl AstFormat {
+  fn from_name(name: &str) -> Result<AstFormat, String> {
+    match name {
+      "debug" => Ok(AstFormat::Debug),
+      "sexpr" => Ok(AstFormat::Sexpr),
+      _ => Err(format!(
+        "Hmm, I don't know the \"{}\" format, try debug or sexpr",
+        name
+      )),
+    }
+  }
+}
+
+pub struct Args {
+  pub command: Command,
+  pub options: ParseOptions,
+  // Settings for the environment programs run in
+  pub env: EnvBuilder,
+  // Whether to report how long every statement took
+  pub time: bool,
+  pub format: Format,
+}
+
+// Takes the value of a flag like `--session <name>`, or `--session=<name>`
+fn value<'a>(
+  flag: &str,
+  inline: &mut Option<&'a str>,
+  args: &mut impl Iterator<Item = &'a str>,
+) -> Result<&'a str, String> {
+  inline
+    .take()
+    .or_else(|| args.next())
+    .ok_or_else(|| format!("Hey, {} needs a value after it", flag))
+}
+
+fn set_lint(options: &mut ParseOptions, level: Level, name: &str) -> Result<(), String> {
+  if name == "warnings" {
+    options.lints.set_warnings(level);
+    return Ok(());
+  }
+  match Lint::from_name(name) {
+    Some(lint) => {
+      options.lints.set(lint, level);
+      Ok(())
+    }
+    None => {
+      let names = LINTS.iter().map(|lint| lint.name()).collect::<Vec<_>>();
+      Err(format!(
+        "Hmm, I don't know any lint called \"{}\", try one of: warnings, {}",
+        name,
+        names.join(", ")
+      ))
+    }
+  }
+}
+
+fn parse_tolerance(tolerance: &str) -> Result<f64, String> {
+  match tolerance.parse::<f64>() {
+    Ok(tolerance) if tolerance >= 0.0 && tolerance.is_finite() => Ok(tolerance),
+    _ => Err(format!(
+      "Hey, \"{}\" isn't a tolerance, I need a number that's 0 or more",
+      tolerance
+    )),
+  }
+}
+
+fn parse_precision(digits: &str) -> Result<usize, String> {
+  match digits.parse::<usize>() {
+    Ok(digits) if digits > 0 => Ok(digits),
+    _ => Err(format!(
+      "Hey, \"{}\" isn't a precision, I need a whole number of digits above 0",
+      digits
+    )),
+  }
+}
+
+#[cfg(feature = "json")]
+fn format(json: bool) -> Result<Format, String> {
+  Ok(if json { Format::Json } else { Format::Human })
+}
+
+#[cfg(not(feature = "json"))]
+fn format(json: bool) -> Result<Format, String> {
+  match json {
+    true => {
+      Err("Sorry, I was built without JSON support, --json needs the json feature :(".to_owned())
+    }
+    false => Ok(Format::Human),
+  }
+}
+
+fn parse_iterations(iterations: &str) -> Result<usize, String> {
+  match iterations.parse::<usize>() {
+    Ok(iterations) if iterations > 0 => Ok(iterations),
+    _ => Err(format!(
+      "Hey, \"{}\" isn't a number of iterations, I need a whole number above 0",
+      iterations
+    )),
+  }
+}
+
+// Flags can go anywhere, only the positional arguments decide what to do
+pub fn parse_args<'a>(args: impl IntoIterator<Item = &'a str>) -> Result<Args, String> {
+  let mut options = ParseOptions::default();
+  let mut env = EnvBuilder::new();
+  let mut positional = Vec::new();
+  let mut session = None;
+  let mut iterations = None;
+  let mut code = None;
+  let mut tokens = None;
+  let mut from_json = false;
+  let mut output = None;
+  let mut time = false;
+  let mut json = false;
+  let mut ast = false;
+  let mut ast_format = None;
+  let mut args = args.into_iter();
+  while let Some(arg) = args.next() {
+    let (arg, mut inline) = match arg.split_once('=') {
+      Some((flag, val)) if flag.starts_with("--") => (flag, Some(val)),
+      _ => (arg, None),
+    };
+    match arg {
+      "-h" | "--help" => {
+        return Ok(Args {
+          command: Command::Help,
+          options,
+          env,
+          time,
+          format: Format::Human,
+        })
+      }
+      "-V" | "--version" => {
+        return Ok(Args {
+          command: Command::Version,
+          options,
+          env,
+          time,
+          format: Format::Human,
+        })
+      }
+      "--allow" => set_lint(
+        &mut options,
+        Level::Allow,
+        value(arg, &mut inline, &mut args)?,
+      )?,
+      "--warn" => set_lint(
+        &mut options,
+        Level::Warn,
+        value(arg, &mut inline, &mut args)?,
+      )?,
+      "--deny" => set_lint(
+        &mut options,
+        Level::Deny,
+        value(arg, &mut inline, &mut args)?,
+      )?,
+      "--exact" => options.lints.set(Lint::InexactFloat, Level::Warn),
+      "--degrees" => env = env.angle_mode(AngleMode::Degrees),
+      "--no-prelude" => env = env.no_prelude(),
+      "--check" => options.typecheck = true,
+      "--optimize" => options.optimize = true,
+      "--time" => time = true,
+      "--json" => json = true,
+      "--tolerance" => env = env.tolerance(parse_tolerance(value(arg, &mut inline, &mut args)?)?),
+      "--precision" => env = env.precision(parse_precision(value(arg, &mut inline, &mut args)?)?),
+      "--session" => session = Some(value(arg, &mut inline, &mut args)?.to_owned()),
+      "--iters" => iterations = Some(parse_iterations(value(arg, &mut inline, &mut args)?)?),
+      "-e" | "--eval" => code = Some(value(arg, &mut inline, &mut args)?.to_owned()),
+      "--tokens" => tokens = Some(value(arg, &mut inline, &mut args)?.to_owned()),
+      "--from-json" => from_json = true,
+      "-o" | "--output" => output = Some(value(arg, &mut inline, &mut args)?.to_owned()),
+      "--ast" => ast = true,
+      "--ast-format" => {
+        ast_format = Some(AstFormat::from_name(value(arg, &mut inline, &mut args)?)?)
+      }
+      flag if flag.starts_with('-') => {
+        return Err(format!("Hmm, I don't know the {} flag", flag));
+      }
+      arg => positional.push(arg.to_owned()),
+    }
+    if inline.is_some() {
+      return Err(format!("Hey, {} doesn't take a value", arg));
+    }
+  }
+  if ast_format.is_some() && !ast {
+    return Err("Hey, --ast-format only works with --ast".to_owned());
+  }
+  if session.is_some() && positional.first().map(String::as_str) != Some("repl") {
+    return Err("Hey, --session only works with the repl command".to_owned());
+  }
+  if iterations.is_some() && positional.first().map(String::as_str) != Some("bench") {
+    return Err("Hey, --iters only works with the bench command".to_owned());
+  }
+  if output.is_some() && positional.first().map(String::as_str) != Some("compile") {
+    return Err("Hey, --output only works with the compile command".to_owned());
+  }
+  let positional = positional.iter().map(String::as_str).collect::<Vec<&str>>();
+  let command = match (code, tokens, &positional[..]) {
+    _ if ast && from_json => return Err("Hey, that's too many things to do at once".to_owned()),
+    (None, None, [path]) if ast => Command::Ast {
+      path: path.to_string(),
+      format: ast_format.unwrap_or(AstFormat::Debug),
+    },
+    (None, None, []) if ast => return Err("Hey, --ast needs a file after it".to_owned()),
+    (None, None, []) if from_json => Command::FromJson { path: None },
+    (None, None, [path]) if from_json => Command::FromJson {
+      path: Some(path.to_string()),
+    },
+    _ if ast || from_json => return Err("Hey, that's too many things to do at once".to_owned()),
+    (Some(code), None, []) => Command::Eval { code },
+    (None, Some(path), []) => Command::Tokens { path },
+    (None, None, []) => Command::Default,
+    (None, None, ["repl"]) => Command::Repl { session },
+    (None, None, ["bench", path]) => Command::Bench {
+      path: path.to_string(),
+      iterations: iterations.unwrap_or(DEFAULT_ITERATIONS),
+    },
+    (None, None, ["bench", ..]) => return Err("Hey, bench takes exactly one file".to_owned()),
+    (None, None, ["test", path]) => Command::Test {
+      path: path.to_string(),
+    },
+    (None, None, ["test", ..]) => return Err("Hey, test takes exactly one directory".to_owned()),
+    (None, None, ["compile", path]) => Command::Compile {
+      path: path.to_string(),
+      output,
+    },
+    (None, None, ["lint"]) => return Err("Hey, lint needs a file to look at".to_owned()),
+    (None, None, ["lint", paths @ ..]) => Command::Lint {
+      paths: paths.iter().map(|path| path.to_string()).collect(),
+    },
+    (None, None, ["lsp"]) => Command::Lsp,
+    // --check asks fmt for a check too, of the layout instead of the types
+    (None, None, ["fmt", paths @ ..]) => Command::Fmt {
+      paths: paths.iter().map(|path| path.to_string()).collect(),
+      check: std::mem::take(&mut options.typecheck),
+    },
+    (None, None, ["compile", ..]) => return Err("Hey, compile takes exactly one file".to_owned()),
+    (None, None, paths) => Command::Run {
+      paths: paths.iter().map(|path| path.to_string()).collect(),
+    },
+    _ => return Err("Hey, that's too many things to do at once".to_owned()),
+  };
+  if time
+    && !matches!(
+      command,
+      Command::Default | Command::Run { .. } | Command::Eval { .. }
+    )
+  {
+    return Err("Hey, --time only works when running programs".to_owned());
+  }
+  if json
+    && !matches!(
+      command,
+      Command::Default | Command::Run { .. } | Command::Eval { .. }
+    )
+  {
+    return Err("Hey, --json only works when running programs".to_owned());
+  }
+  Ok(Args {
+    command,
+    options,
+    env,
+    time,
+    format: format(json)?,
+  })
+}
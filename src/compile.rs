@@ -0,0 +1,138 @@
+use crate::builtins;
+use crate::error::{RuntimeError, RuntimeErrorKind, Span};
+use crate::expr::{
+  apply_binary, apply_percent_change, apply_unary, eval_var, BinOp, Env, Expr, UnOp, Value,
+};
+use crate::optimize::PURE_BUILTINS;
+use crate::prelude::*;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+
+// Turns an expression into closures once, for formulas that get evaluated
+// over and over with different variables. Builtins are looked up and
+// operators picked while compiling, so running it only does the math.
+//
+// A compiled expression only reads the environment: declarations, prints,
+// asserts, series and calls to anything but the pure builtins fail when
+// they're reached, and evaluations aren't counted or recorded.
+
+type EvalResult = Result<Value, RuntimeError>;
+
+// Builtins take a mutable environment even when they only read its
+// settings, so they get this one, with the settings copied over
+type Node = Box<dyn Fn(&Env, &RefCell<Env>) -> EvalResult>;
+
+fn unsupported(what: &str) -> Node {
+  let what = what.to_owned();
+  Box::new(move |_, _| {
+    Err(RuntimeError::new(RuntimeErrorKind::NotCompilable(
+      what.clone(),
+    )))
+  })
+}
+
+fn compile_all(exprs: &[Expr]) -> Vec<Node> {
+  exprs.iter().map(compile_node).collect()
+}
+
+fn eval_all(nodes: &[Node], env: &Env, scratch: &RefCell<Env>) -> Result<Vec<Value>, RuntimeError> {
+  nodes.iter().map(|node| node(env, scratch)).collect()
+}
+
+fn compile_call(name: &str, args: &[Expr], span: Span) -> Node {
+  let builtin = match builtins::lookup(name) {
+    Some(builtin) if PURE_BUILTINS.contains(&name) && builtin.arity.accepts(args.len()) => builtin,
+    _ => return unsupported(&format!("call \"{}\"", name)),
+  };
+  let args = compile_all(args);
+  let name = name.to_owned();
+  Box::new(move |env, scratch| {
+    // A function declared with the same name would have been called instead
+    if env.get(&name).is_some() || !env.builtin_allowed(&name) {
+      return Err(
+        RuntimeError::new(RuntimeErrorKind::NotCompilable(format!(
+          "call \"{}\"",
+          name
+        )))
+        .at(span),
+      );
+    }
+    let args = eval_all(&args, env, scratch)?;
+    let mut scratch = scratch.borrow_mut();
+    scratch.set_angle_mode(env.angle_mode());
+    scratch.set_tolerance(env.tolerance());
+    (builtin.call)(&args, &mut scratch).map_err(|err| err.at(span))
+  })
+}
+
+fn compile_node(expr: &Expr) -> Node {
+  match expr {
+    Expr::Literal(val, _) => {
+      let val = val.clone();
+      Box::new(move |_, _| Ok(val.clone()))
+    }
+    Expr::Var(name, span) => {
+      let (name, span) = (name.clone(), *span);
+      Box::new(move |env, _| eval_var(&name, env).map_err(|err| err.at(span)))
+    }
+    Expr::Binary(op, left, right, span) => {
+      let (op, span) = (*op, *span);
+      let percent_change =
+        matches!(op, BinOp::Add | BinOp::Sub) && matches!(**right, Expr::Unary(UnOp::Percent, ..));
+      let (left, right) = (compile_node(left), compile_node(right));
+      if percent_change {
+        return Box::new(move |env, scratch| {
+          apply_percent_change(op, left(env, scratch)?, right(env, scratch)?)
+            .map_err(|err| err.at(span))
+        });
+      }
+      Box::new(move |env, scratch| {
+        apply_binary(op, left(env, scratch)?, right(env, scratch)?, env).map_err(|err| err.at(span))
+      })
+    }
+    Expr::Unary(op, val, span) => {
+      let (op, val, span) = (*op, compile_node(val), *span);
+      Box::new(move |env, scratch| apply_unary(op, val(env, scratch)?).map_err(|err| err.at(span)))
+    }
+    Expr::Conditional(cond, val_if_true, val_if_false, _) => {
+      let cond = compile_node(cond);
+      let val_if_true = compile_node(val_if_true);
+      let val_if_false = compile_node(val_if_false);
+      Box::new(move |env, scratch| match cond(env, scratch)? {
+        Value::Bool(true) => val_if_true(env, scratch),
+        _ => val_if_false(env, scratch),
+      })
+    }
+    Expr::Call(name, args, span) => compile_call(name, args, *span),
+    Expr::List(items, _) => {
+      let items = compile_all(items);
+      Box::new(move |env, scratch| Ok(Value::List(Rc::new(eval_all(&items, env, scratch)?))))
+    }
+    Expr::Interpolation(parts, span) => {
+      let (parts, span) = (compile_all(parts), *span);
+      Box::new(move |env, scratch| {
+        let mut text = String::new();
+        for part in &parts {
+          let val = part(env, scratch).map_err(|err| err.at(span))?;
+          text.push_str(&env.display(&val));
+        }
+        Ok(Value::Str(text.into()))
+      })
+    }
+    Expr::VarDeclaration(..) => unsupported("declare variables"),
+    Expr::FnDeclaration(..) => unsupported("declare functions"),
+    Expr::Print(..) => unsupported("print"),
+    Expr::Assert(..) => unsupported("assert"),
+    Expr::Series(kind, ..) => unsupported(&format!("take a {}", kind.name())),
+    Expr::Import(..) => unsupported("import files"),
+    Expr::Module(..) => unsupported("declare modules"),
+  }
+}
+
+impl Expr {
+  pub fn compile(&self) -> impl Fn(&Env) -> Result<Value, RuntimeError> {
+    let node = compile_node(self);
+    let scratch = RefCell::new(Env::without_prelude());
+    move |env| node(env, &scratch)
+  }
+}
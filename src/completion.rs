@@ -0,0 +1,118 @@
+use math_parser::expr::Env;
+use math_parser::lexer::{classify, is_name_char, TokenClass, KEYWORDS};
+use rustyline::completion::Completer;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::borrow::Cow;
+
+// Completes the word under the cursor from keywords, builtins and declared
+// variables.
+// The list of names is refreshed from the Env after every evaluated line.
+pub struct Completion {
+  names: Vec<String>,
+}
+
+impl Completion {
+  pub fn new(env: &Env) -> Self {
+    let mut completion = Completion { names: Vec::new() };
+    completion.refresh(env);
+    completion
+  }
+
+  pub fn refresh(&mut self, env: &Env) {
+    let mut names: Vec<String> = KEYWORDS.iter().map(|(k, _)| (*k).to_owned()).collect();
+    names.extend(env.builtin_names().map(|name| name.to_owned()));
+    names.extend(env.var_names().map(|name| name.to_owned()));
+    names.extend(env.prelude_names().map(|name| name.to_owned()));
+    names.sort();
+    names.dedup();
+    self.names = names;
+  }
+
+  fn candidates(&self, prefix: &str) -> Vec<String> {
+    self
+      .names
+      .iter()
+      .filter(|name| name.starts_with(prefix))
+      .cloned()
+      .collect()
+  }
+}
+
+// Qualified names like geom.area are one word, so they complete whole
+fn word_start(line: &str, pos: usize) -> usize {
+  line[..pos]
+    .char_indices()
+    .rev()
+    .take_while(|&(_, c)| is_name_char(c) || c == '.')
+    .last()
+    .map(|(i, _)| i)
+    .unwrap_or(pos)
+}
+
+impl Completer for Completion {
+  type Candidate = String;
+
+  fn complete(
+    &self,
+    line: &str,
+    pos: usize,
+    _ctx: &Context<'_>,
+  ) -> rustyline::Result<(usize, Vec<String>)> {
+    let start = word_start(line, pos);
+    if start == pos {
+      return Ok((pos, Vec::new()));
+    }
+    Ok((start, self.candidates(&line[start..pos])))
+  }
+}
+
+impl Hinter for Completion {
+  type Hint = String;
+}
+
+const RESET: &str = "\x1b[0m";
+
+// Bold blue keywords, cyan numbers, green strings and red for anything the
+// lexer can't read
+fn style(class: TokenClass) -> Option<&'static str> {
+  match class {
+    TokenClass::Keyword => Some("\x1b[1;34m"),
+    TokenClass::Number => Some("\x1b[36m"),
+    TokenClass::Str => Some("\x1b[32m"),
+    TokenClass::Invalid => Some("\x1b[31m"),
+    TokenClass::Identifier | TokenClass::Operator | TokenClass::Punctuation => None,
+  }
+}
+
+// Colors the line while typing, with the classes the lexer gives its tokens
+impl Highlighter for Completion {
+  fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+    if std::env::var_os("NO_COLOR").is_some() {
+      return Cow::Borrowed(line);
+    }
+    let mut out = String::with_capacity(line.len());
+    let mut end = 0;
+    for (span, class) in classify(line) {
+      out.push_str(&line[end..span.start]);
+      let text = &line[span.start..span.end];
+      match style(class) {
+        Some(style) => out.push_str(&format!("{}{}{}", style, text, RESET)),
+        None => out.push_str(text),
+      }
+      end = span.end;
+    }
+    out.push_str(&line[end..]);
+    Cow::Owned(out)
+  }
+
+  fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+    true
+  }
+}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
@@ -0,0 +1,211 @@
+use crate::error::{DiffError, Span, NO_SPAN};
+use crate::expr::{binary, conditional, int, unary, BinOp, Expr, SeriesKind, UnOp};
+use crate::prelude::*;
+
+// Symbolic differentiation, the derivative is a new expression that can be
+// printed or evaluated like any other. It isn't simplified, "x * x" gives
+// "1 * x + x * 1". Trigonometric functions are differentiated as if angles
+// were in radians.
+
+fn mentions(expr: &Expr, var: &str) -> bool {
+  match expr {
+    Expr::Var(name, _) => name == var,
+    expr => expr
+      .children()
+      .into_iter()
+      .any(|child| mentions(child, var)),
+  }
+}
+
+fn call(name: &str, arg: &Expr, span: Span) -> Expr {
+  Expr::Call(name.to_owned(), vec![arg.clone()], span)
+}
+
+fn mul(left: Expr, right: Expr) -> Expr {
+  binary(BinOp::Mul, left, right)
+}
+
+fn div(left: Expr, right: Expr) -> Expr {
+  binary(BinOp::Div, left, right)
+}
+
+fn square(val: &Expr) -> Expr {
+  binary(BinOp::Pow, val.clone(), int(2))
+}
+
+// The parts of `expr` whose derivatives its derivative is made of, in the
+// order `combine` takes them, or an error if it has none
+fn parts<'e>(expr: &'e Expr, var: &str) -> Result<Vec<&'e Expr>, DiffError> {
+  let unsupported = || DiffError {
+    expr: expr.to_string(),
+  };
+  match expr {
+    Expr::Var(..) => Ok(Vec::new()),
+    Expr::Binary(
+      BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Pow,
+      left,
+      right,
+      _,
+    ) => Ok(vec![&**left, &**right]),
+    // Floor division, comparisons and bit operations jump instead of
+    // changing smoothly
+    Expr::Binary(..) => Err(unsupported()),
+    Expr::Unary(UnOp::Not, ..) => Err(unsupported()),
+    Expr::Unary(_, val, _) => Ok(vec![&**val]),
+    Expr::Conditional(_, val_if_true, val_if_false, _) => Ok(vec![&**val_if_true, &**val_if_false]),
+    Expr::Series(SeriesKind::Sum, index, from, to, term, _)
+      if index != var && !mentions(from, var) && !mentions(to, var) =>
+    {
+      Ok(vec![&**term])
+    }
+    Expr::Call(name, args, _) if args.len() == 1 => match name.as_str() {
+      "sin" | "cos" | "tan" | "exp" | "ln" | "sqrt" | "asin" | "acos" | "atan" | "abs" => {
+        Ok(vec![&args[0]])
+      }
+      _ => Err(unsupported()),
+    },
+    _ => Err(unsupported()),
+  }
+}
+
+// The derivative of `expr` from the derivatives of its parts
+fn combine(expr: &Expr, var: &str, derivatives: Vec<Expr>) -> Expr {
+  let mut derivatives = derivatives.into_iter();
+  let mut d = || derivatives.next().expect("a derivative for every part");
+  match expr {
+    Expr::Var(..) => int(1),
+    // "a + b%" adds b% of a
+    Expr::Binary(op @ (BinOp::Add | BinOp::Sub), left, right, _)
+      if matches!(**right, Expr::Unary(UnOp::Percent, ..)) =>
+    {
+      let (a, b) = (&**left, &**right);
+      let (da, db) = (d(), d());
+      let change = match mentions(&mul(a.clone(), b.clone()), var) {
+        true => binary(BinOp::Add, mul(da.clone(), b.clone()), mul(a.clone(), db)),
+        false => int(0),
+      };
+      binary(*op, da, change)
+    }
+    Expr::Binary(op, left, right, _) => {
+      let (a, b) = (&**left, &**right);
+      let (da, db) = (d(), d());
+      match op {
+        BinOp::Add | BinOp::Sub => binary(*op, da, db),
+        BinOp::Mul => binary(BinOp::Add, mul(da, b.clone()), mul(a.clone(), db)),
+        BinOp::Div => div(
+          binary(BinOp::Sub, mul(da, b.clone()), mul(a.clone(), db)),
+          square(b),
+        ),
+        // a^n, n * a^(n - 1) * a'
+        _ if !mentions(b, var) => {
+          let lowered = binary(BinOp::Pow, a.clone(), binary(BinOp::Sub, b.clone(), int(1)));
+          mul(mul(b.clone(), lowered), da)
+        }
+        // n^b, n^b * ln(n) * b'
+        _ if !mentions(a, var) => mul(mul(expr.clone(), call("ln", a, NO_SPAN)), db),
+        // a^b, a^b * (b' * ln(a) + b * a' / a)
+        _ => {
+          let ln = call("ln", a, NO_SPAN);
+          let inner = binary(BinOp::Add, mul(db, ln), div(mul(b.clone(), da), a.clone()));
+          mul(expr.clone(), inner)
+        }
+      }
+    }
+    Expr::Unary(op, val, _) => match op {
+      UnOp::Sqrt => div(d(), mul(int(2), unary(UnOp::Sqrt, (**val).clone()))),
+      _ => unary(*op, d()),
+    },
+    // The derivative of whichever branch is taken
+    Expr::Conditional(cond, ..) => conditional((**cond).clone(), d(), d()),
+    // Term by term, as long as the index isn't the variable
+    Expr::Series(kind, index, from, to, ..) => Expr::Series(
+      *kind,
+      index.clone(),
+      from.clone(),
+      to.clone(),
+      Box::new(d()),
+      NO_SPAN,
+    ),
+    // Builtins by the chain rule, user functions would have to be inlined
+    Expr::Call(name, args, span) => {
+      let (a, span) = (&args[0], *span);
+      let outer = match name.as_str() {
+        "sin" => call("cos", a, span),
+        "cos" => unary(UnOp::Neg, call("sin", a, span)),
+        "tan" => div(int(1), square(&call("cos", a, span))),
+        "exp" => expr.clone(),
+        "ln" => div(int(1), a.clone()),
+        "sqrt" => div(int(1), mul(int(2), expr.clone())),
+        "asin" => div(
+          int(1),
+          call("sqrt", &binary(BinOp::Sub, int(1), square(a)), span),
+        ),
+        "acos" => unary(
+          UnOp::Neg,
+          div(
+            int(1),
+            call("sqrt", &binary(BinOp::Sub, int(1), square(a)), span),
+          ),
+        ),
+        "atan" => div(int(1), binary(BinOp::Add, int(1), square(a))),
+        _ => div(a.clone(), expr.clone()),
+      };
+      mul(outer, d())
+    }
+    _ => unreachable!("parts only has parts for the nodes above"),
+  }
+}
+
+enum Task<'e> {
+  Differentiate(&'e Expr),
+  // Once the derivatives of the node's parts are on the stack
+  Combine(&'e Expr, usize),
+}
+
+// The derivative of `expr` with respect to the variable `var`. The tree is
+// walked with a stack of its own instead of recursing, as deep as the
+// parser allows would overflow the real one.
+pub fn differentiate(expr: &Expr, var: &str) -> Result<Expr, DiffError> {
+  let mut tasks = vec![Task::Differentiate(expr)];
+  let mut derivatives = Vec::new();
+  while let Some(task) = tasks.pop() {
+    match task {
+      Task::Differentiate(expr) if !mentions(expr, var) => derivatives.push(int(0)),
+      Task::Differentiate(expr) => {
+        let parts = parts(expr, var)?;
+        tasks.push(Task::Combine(expr, parts.len()));
+        tasks.extend(parts.into_iter().rev().map(Task::Differentiate));
+      }
+      Task::Combine(expr, count) => {
+        let parts = derivatives.split_off(derivatives.len() - count);
+        derivatives.push(combine(expr, var, parts));
+      }
+    }
+  }
+  Ok(
+    derivatives
+      .pop()
+      .expect("the derivative of the whole expression"),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::{parse, DEFAULT_MAX_DEPTH};
+
+  #[test]
+  fn as_deep_as_the_parser_allows() {
+    let input = format!("{}x", "-".repeat(DEFAULT_MAX_DEPTH));
+    let exprs = parse(&input).unwrap();
+    let mut derivative = &differentiate(&exprs[0], "x").unwrap();
+    // Printing it would recurse as deep as the tree is
+    let mut negations = 0;
+    while let Expr::Unary(UnOp::Neg, val, _) = derivative {
+      negations += 1;
+      derivative = val;
+    }
+    assert_eq!(negations, DEFAULT_MAX_DEPTH);
+    assert_eq!(derivative, &int(1));
+  }
+}
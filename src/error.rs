@@ -1,15 +1,1195 @@
-pub fn print_err(input: &str, pos: usize, msg: &str) -> String {
-  let line_num = input[..pos].lines().count();
-  let line_end = pos + input[pos..].find('\n').unwrap_or(input.len() - pos);
-  let line_start = pos
-    - input[..pos]
-      .chars()
-      .rev()
-      .position(|c| c == '\n')
-      .unwrap_or(pos);
-  let line = &input[line_start..line_end];
-  let spaces: String = std::iter::repeat(' ')
-    .take(pos - line_start + line_num.to_string().len() + 2)
-    .collect();
-  format!("{}\n{}| {}\n{}^", msg, line_num, line, spaces)
+use crate::builtins::MAX_TABLE_ROWS;
+use crate::chunk::VERSION;
+use crate::lint::Lint;
+use crate::prelude::*;
+use alloc::rc::Rc;
+#[cfg(feature = "std")]
+use std::io::IsTerminal;
+
+// Byte offsets of a piece of the input, end exclusive
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+}
+
+impl Span {
+  pub fn new(start: usize, end: usize) -> Self {
+    Span { start, end }
+  }
+}
+
+// For nodes that weren't parsed from anything, like the ones derivatives
+// are made of. Errors never point there.
+pub const NO_SPAN: Span = Span { start: 0, end: 0 };
+
+// The offset moved into the input, and back to the start of the character
+// it's in, for spans that don't belong to it
+fn clamp(input: &str, pos: usize) -> usize {
+  let mut pos = pos.min(input.len());
+  while !input.is_char_boundary(pos) {
+    pos -= 1;
+  }
+  pos
+}
+
+// 1-based line and column (in characters) of a byte offset
+pub fn line_col(input: &str, pos: usize) -> (usize, usize) {
+  let before = &input[..clamp(input, pos)];
+  let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+  let line = before.matches('\n').count() + 1;
+  let col = before[line_start..].chars().count() + 1;
+  (line, col)
+}
+
+// Number of single character insertions, deletions and substitutions needed
+// to turn one string into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut row: Vec<usize> = (0..=b.len()).collect();
+  for (i, ca) in a.chars().enumerate() {
+    let mut diagonal = row[0];
+    row[0] = i + 1;
+    for (j, cb) in b.iter().enumerate() {
+      let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+      diagonal = row[j + 1];
+      row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+    }
+  }
+  row[b.len()]
+}
+
+// The candidate closest to `name`, if any of them is close enough to be a
+// plausible typo
+pub fn suggest<'a, I: IntoIterator<Item = &'a str>>(name: &str, candidates: I) -> Option<String> {
+  let max_distance = (name.chars().count() + 1) / 3;
+  candidates
+    .into_iter()
+    .map(|candidate| (edit_distance(name, candidate), candidate))
+    .filter(|&(distance, _)| distance <= max_distance)
+    .min()
+    .map(|(_, candidate)| candidate.to_owned())
+}
+
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+// How diagnostics are rendered. Colors are off unless a program printing
+// to a terminal turns them on, strings handed to other programs have no
+// escape codes in them
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+  pub colors: bool,
+}
+
+impl RenderOptions {
+  // Colors when stdout is a terminal and NO_COLOR isn't set (see
+  // https://no-color.org)
+  #[cfg(feature = "std")]
+  pub fn terminal() -> Self {
+    RenderOptions {
+      colors: std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal(),
+    }
+  }
+}
+
+fn paint(text: &str, style: &str, colors: bool) -> String {
+  if colors {
+    format!("{}{}{}", style, text, RESET)
+  } else {
+    text.to_owned()
+  }
+}
+
+// Tabs are shown as spaces up to the next multiple of this, so underlines
+// line up with what's printed above them
+const TAB_WIDTH: usize = 4;
+
+fn expand_tabs(line: &str) -> String {
+  let mut out = String::new();
+  for c in line.chars() {
+    match c {
+      '\t' => out.push_str(&" ".repeat(TAB_WIDTH - out.chars().count() % TAB_WIDTH)),
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+// Where a byte offset of a line ends up once the line is printed
+fn display_col(line: &str, pos: usize) -> usize {
+  expand_tabs(&line[..pos]).chars().count()
+}
+
+// A part of the input a diagnostic points at. The primary label is where the
+// problem is, secondary ones explain it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+  pub span: Span,
+  pub text: String,
+  pub primary: bool,
+}
+
+// A message with any number of labelled spans, rendered like this:
+// Hey, I expected a closing parenthesis here (line 2, column 1)
+// 1| (1 + 2
+//    - to close this one
+// 2| let a = 4
+//    ^^^
+pub struct Diagnostic {
+  message: String,
+  labels: Vec<Label>,
+}
+
+// The part of a label that's on one line, spans over several lines get one
+// for every line
+struct Segment<'a> {
+  start_col: usize,
+  end_col: usize,
+  label: &'a Label,
+  // Only the last segment of a label shows its text
+  last: bool,
+}
+
+impl Diagnostic {
+  pub fn new(message: &str) -> Self {
+    Diagnostic {
+      message: message.to_owned(),
+      labels: Vec::new(),
+    }
+  }
+
+  pub fn primary(mut self, span: Span, text: &str) -> Self {
+    self.labels.push(Label {
+      span,
+      text: text.to_owned(),
+      primary: true,
+    });
+    self
+  }
+
+  pub fn secondary(mut self, span: Span, text: &str) -> Self {
+    self.labels.push(Label {
+      span,
+      text: text.to_owned(),
+      primary: false,
+    });
+    self
+  }
+
+  // Splits every label into its lines, keyed by the 0-based line index
+  fn segments(
+    &self,
+    input: &str,
+    lines: &[&str],
+    line_starts: &[usize],
+  ) -> Vec<(usize, Segment<'_>)> {
+    let line_of = |pos: usize| line_starts.partition_point(|&start| start <= pos) - 1;
+    let mut segments = Vec::new();
+    for label in &self.labels {
+      let start = clamp(input, label.span.start);
+      let span = Span::new(start, clamp(input, label.span.end).max(start));
+      let (first, last) = (line_of(span.start), line_of(span.end));
+      // A span ending right after a line break doesn't reach into the next
+      // line
+      let last = if last > first && span.end == line_starts[last] {
+        last - 1
+      } else {
+        last
+      };
+      for line in first..=last {
+        let text = lines[line];
+        let start = span.start.saturating_sub(line_starts[line]);
+        let start = if line == first { start } else { 0 };
+        let end = (span.end - line_starts[line].min(span.end)).min(text.len());
+        let end = if line == last {
+          end.max(start)
+        } else {
+          text.len()
+        };
+        segments.push((
+          line,
+          Segment {
+            start_col: display_col(text, start),
+            end_col: display_col(text, end),
+            label,
+            last: line == last,
+          },
+        ));
+      }
+    }
+    segments.sort_by_key(|(line, segment)| (*line, segment.start_col));
+    segments
+  }
+
+  pub fn render(&self, input: &str) -> String {
+    self.render_with(input, &RenderOptions::default())
+  }
+
+  pub fn render_with(&self, input: &str, options: &RenderOptions) -> String {
+    let colors = options.colors;
+    let main = self
+      .labels
+      .iter()
+      .find(|label| label.primary)
+      .or_else(|| self.labels.first());
+    let main = match main {
+      Some(label) => label,
+      None => return paint(&self.message, BOLD, colors),
+    };
+    let (line_num, col) = line_col(input, main.span.start);
+    let mut out = format!(
+      "{} (line {}, column {})",
+      paint(&self.message, BOLD, colors),
+      line_num,
+      col
+    );
+    // Without the \r of a CRLF line ending, the terminal would go back to
+    // the start of the line there
+    let lines: Vec<&str> = input
+      .split('\n')
+      .map(|line| line.strip_suffix('\r').unwrap_or(line))
+      .collect();
+    let mut line_starts = vec![0];
+    line_starts.extend(input.match_indices('\n').map(|(i, _)| i + 1));
+    let segments = self.segments(input, &lines, &line_starts);
+    let gutter = segments
+      .iter()
+      .map(|(line, _)| (line + 1).to_string().len())
+      .max()
+      .unwrap_or(1);
+    let mut previous_line = None;
+    for (line, segment) in &segments {
+      if previous_line != Some(*line) {
+        if previous_line.is_some_and(|previous| previous + 1 < *line) {
+          out.push_str(&format!(
+            "\n{}",
+            paint(&format!("{:>1$}", "...", gutter + 1), DIM, colors)
+          ));
+        }
+        let source = format!("{:>2$}| {}", line + 1, expand_tabs(lines[*line]), gutter);
+        out.push_str(&format!("\n{}", paint(&source, DIM, colors)));
+        previous_line = Some(*line);
+      }
+      // Lines in the middle of a long span are only underlined when there's
+      // something to underline
+      let width = segment.end_col - segment.start_col;
+      if width == 0 && !segment.last {
+        continue;
+      }
+      let (marker, style) = match segment.label.primary {
+        true => ("^", RED),
+        false => ("-", BOLD),
+      };
+      let mut underline = marker.repeat(width.max(1));
+      if segment.last && !segment.label.text.is_empty() {
+        underline = format!("{} {}", underline, segment.label.text);
+      }
+      out.push_str(&format!(
+        "\n{}{}",
+        " ".repeat(segment.start_col + gutter + 2),
+        paint(&underline, style, colors)
+      ));
+    }
+    out
+  }
+}
+
+pub fn print_err(input: &str, span: Span, msg: &str) -> String {
+  print_err_with(input, span, msg, &RenderOptions::default())
+}
+
+fn print_err_with(input: &str, span: Span, msg: &str, options: &RenderOptions) -> String {
+  Diagnostic::new(msg)
+    .primary(span, "")
+    .render_with(input, options)
+}
+
+// The span shown in the text it's from, which is `input` unless it's in
+// another file
+fn print_in(
+  input: &str,
+  source: &Source,
+  span: Span,
+  msg: &str,
+  options: &RenderOptions,
+) -> String {
+  match source {
+    Source::Input => print_err_with(input, span, msg, options),
+    // Not the user's code, so there's nothing of theirs to show
+    Source::Prelude => format!("{} (inside the prelude)", msg),
+    Source::File(file) => {
+      let msg = format!("{}, in \"{}\"", msg, file.path);
+      print_err_with(&file.text, span, &msg, options)
+    }
+  }
+}
+
+// -- Error types
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LexErrorKind {
+  EmptyInput,
+  UnexpectedChar,
+  UnknownOperator,
+  InvalidNumber,
+  InvalidDigit,
+  UnterminatedString,
+  InvalidEscape,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+  pub kind: LexErrorKind,
+  pub span: Span,
+}
+
+impl LexError {
+  pub fn new(kind: LexErrorKind, span: Span) -> Self {
+    LexError { kind, span }
+  }
+
+  pub fn message(&self) -> &'static str {
+    match self.kind {
+      LexErrorKind::EmptyInput => "Didn't find any input. Give me something to parse next time!",
+      LexErrorKind::UnexpectedChar => "Sorry, I dont know what to do with this character :(",
+      LexErrorKind::UnknownOperator => "Hmm, I don't know any operator like this one",
+      LexErrorKind::InvalidNumber => {
+        "Sorry, I couldn't construct this number :( Make sure that it's not too big!"
+      }
+      LexErrorKind::InvalidDigit => "Hmm, this number has a digit that its base doesn't have",
+      LexErrorKind::UnterminatedString => "Hey, this string never ends, it needs a closing \"",
+      LexErrorKind::InvalidEscape => "Hmm, I don't know this escape, try \\\", \\\\, \\n or \\t",
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ParseErrorKind {
+  UnexpectedEof,
+  UnexpectedToken,
+  ExpectedClosingParen,
+  ExpectedClosingBracket,
+  UnclosedBrace,
+  UnmatchedBrace,
+  InvalidInterpolation,
+  ExpectedEquals,
+  ExpectedVarName,
+  ExpectedThen,
+  ExpectedElse,
+  ExpectedFnName,
+  ExpectedParamName,
+  ExpectedParams,
+  ExpectedImportPath,
+  ExpectedModuleName,
+  ExpectedModuleBody,
+  ExpectedClosingBrace,
+  TooManyTokens,
+  TooDeep,
+  TooManyNodes,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+  pub kind: ParseErrorKind,
+  pub span: Span,
+  // Other places that help explain the error
+  pub labels: Vec<Label>,
+}
+
+impl ParseError {
+  pub fn new(kind: ParseErrorKind, span: Span) -> Self {
+    ParseError {
+      kind,
+      span,
+      labels: Vec::new(),
+    }
+  }
+
+  pub fn with_label(mut self, span: Span, text: &str) -> Self {
+    self.labels.push(Label {
+      span,
+      text: text.to_owned(),
+      primary: false,
+    });
+    self
+  }
+
+  pub fn message(&self) -> &'static str {
+    match self.kind {
+      ParseErrorKind::UnexpectedEof => "Hey, I didn't expect the input to end right here",
+      ParseErrorKind::UnexpectedToken => "Hey, I didn't expect this thing right here",
+      ParseErrorKind::ExpectedClosingParen => "Hey, I expected a closing parenthesis here",
+      ParseErrorKind::UnclosedBrace => "Hey, this \"{\" needs a \"}\" after the expression, write \"{{\" for a brace",
+      ParseErrorKind::UnmatchedBrace => "Hey, there's no \"{\" for this \"}\", write \"}}\" for a brace",
+      ParseErrorKind::InvalidInterpolation => "Hmm, I can't read the expression in these braces",
+      ParseErrorKind::ExpectedClosingBracket => "Hey, I expected a \"]\" to close the list here",
+      ParseErrorKind::ExpectedEquals => "Hey, I expected \"=\" right here",
+      ParseErrorKind::ExpectedVarName => "Hey, I expected a name of a variable right here",
+      ParseErrorKind::ExpectedThen => "Hey, I expected a \"then\" keyword right here (conditional expressions look like this: if *condition* then *value* else *value*)",
+      ParseErrorKind::ExpectedElse => "Hey, I expected an \"else\" keyword right here",
+      ParseErrorKind::ExpectedFnName => "Hey, I expected a name of a function right here",
+      ParseErrorKind::ExpectedParamName => "Hey, I expected a name of a parameter right here",
+      ParseErrorKind::ExpectedParams => "Hey, I expected a \"(\" with the function's parameters right here (functions look like this: fn *name*(*parameters*) = *body*)",
+      ParseErrorKind::ExpectedImportPath => "Hey, I expected the path of the file to import in quotes right here (like this: import \"lib/geometry.math\")",
+      ParseErrorKind::ExpectedModuleName => "Hey, I expected a name of a module right here",
+      ParseErrorKind::ExpectedModuleBody => "Hey, I expected a \"{\" with the module's definitions right here (modules look like this: module *name* { *definitions* })",
+      ParseErrorKind::ExpectedClosingBrace => "Hey, I expected a \"}\" to close the module here",
+      ParseErrorKind::TooManyTokens => "Sorry, this input is too long for me, it stops being allowed right here",
+      ParseErrorKind::TooDeep => "Sorry, this is nested too deeply for me to handle",
+      ParseErrorKind::TooManyNodes => "Sorry, this input has too many parts for me to handle, it reaches the limit in this statement",
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeErrorKind {
+  // The name, and possibly a similar known name
+  UndefinedVariable(String, Option<String>),
+  UndefinedFunction(String, Option<String>),
+  DisabledBuiltin(String),
+  // Function name, expected and actual number of arguments
+  WrongArgumentCount(String, usize, usize),
+  // For functions that take any number of arguments above a minimum
+  TooFewArguments(String, usize, usize),
+  // Function name, the fewest and most arguments it takes and how many it got
+  WrongArgumentRange(String, usize, usize, usize),
+  // The name and the type of what it actually is
+  NotAFunction(String, &'static str),
+  // The type of the value that was found instead
+  ExpectedNumber(&'static str),
+  ExpectedBool(&'static str),
+  ExpectedInt(&'static str),
+  ExpectedList(&'static str),
+  ExpectedFunction(&'static str),
+  // The function, how many items it needs at least and how many it got
+  TooFewItems(&'static str, usize, usize),
+  // The number of bits something was shifted by
+  InvalidShift(i64),
+  InvalidTolerance(f64),
+  InvalidAngleMode(String),
+  // The bounds of a range without any numbers in it
+  EmptyRange(i64, i64),
+  // The value as source, and the type it couldn't become
+  CantConvert(String, &'static str),
+  // The function that gave up and how many iterations it tried
+  NoConvergence(&'static str, i64),
+  // Where Newton's method found a zero slope
+  FlatSlope(f64),
+  // The ends of an interval the function doesn't change sign in
+  NoSignChange(f64, f64),
+  InvalidIterations(i64),
+  // The ends of an interval that doesn't go from lower to higher
+  InvalidInterval(f64, f64),
+  // The ends of an interval the function isn't a number anywhere in
+  NothingToPlot(f64, f64),
+  InvalidStep(f64),
+  // What a compiled expression tried to do
+  NotCompilable(String),
+  // How many rows a table would have had
+  TooManyRows(f64),
+  // How many parameters a batch has and how many columns of values it got
+  WrongColumnCount(usize, usize),
+  // The length of the first column of a batch and of one that's different
+  RaggedColumns(usize, usize),
+  // The condition, and both sides as source when it was a comparison
+  AssertionFailed(String, Option<(String, String)>),
+  TooMuchRecursion,
+  DivisionByZero,
+  Overflow,
+  // What went wrong writing printed values, as the OS put it
+  PrintFailed(String),
+  // The path as written, and what went wrong reading it as the OS put it
+  ImportUnreadable(String, String),
+  // A file imported again while it's still running, which would never end
+  ImportCycle(String),
+  // The path, and the errors in the file rendered against its source
+  ImportFailed(String, String),
+  // Without std there are no files, and sandboxed environments can't read
+  // them
+  ImportsUnavailable,
+}
+
+// "an int" but "a float", for the type names in messages
+pub(crate) fn with_article(noun: &str) -> String {
+  let article = match noun.starts_with(['a', 'e', 'i', 'o', 'u']) {
+    true => "an",
+    false => "a",
+  };
+  format!("{} {}", article, noun)
+}
+
+// What a function was declared in, so that errors in its body are shown
+// against the right text
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Source {
+  // The code being run, which errors get rendered against
+  #[default]
+  Input,
+  // The standard library
+  Prelude,
+  // An imported file
+  File(Rc<SourceFile>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SourceFile {
+  // As the import wrote it
+  pub path: String,
+  pub text: String,
+}
+
+// A user function call that was in progress when an error happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+  pub function: String,
+  pub call_site: Span,
+  // Where the function was declared. The spans inside its body, the error's
+  // or the call sites of the frames before this one, are in there.
+  pub source: Source,
+}
+
+// How many calls of a trace get shown before the rest is summarized
+const MAX_SHOWN_FRAMES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+  pub kind: RuntimeErrorKind,
+  // Not every runtime error can be traced back to the source yet
+  pub span: Option<Span>,
+  // Calls the error went through, innermost first
+  pub trace: Vec<TraceFrame>,
+}
+
+impl RuntimeError {
+  pub fn new(kind: RuntimeErrorKind) -> Self {
+    RuntimeError {
+      kind,
+      span: None,
+      trace: Vec::new(),
+    }
+  }
+
+  // Points the error at the span unless it already knows a better place
+  pub fn at(mut self, span: Span) -> Self {
+    if self.span.is_none() && span != NO_SPAN {
+      self.span = Some(span);
+    }
+    self
+  }
+
+  // Records that the error escaped from a call to the function
+  pub fn called_from(mut self, function: &str, source: &Source, call_site: Span) -> Self {
+    self.trace.push(TraceFrame {
+      function: function.to_owned(),
+      call_site,
+      source: source.clone(),
+    });
+    self
+  }
+
+  // What the error's span is in, the body of the innermost call
+  pub fn source(&self) -> &Source {
+    self
+      .trace
+      .first()
+      .map_or(&Source::Input, |frame| &frame.source)
+  }
+
+  // What each frame's call site is in, the body of the call around it
+  pub fn call_sites(&self) -> impl Iterator<Item = (&TraceFrame, &Source)> {
+    let callers = self.trace.iter().skip(1).map(|frame| &frame.source);
+    self
+      .trace
+      .iter()
+      .zip(callers.chain(core::iter::once(&Source::Input)))
+  }
+
+  fn render_trace(&self, input: &str, options: &RenderOptions) -> String {
+    let mut out = String::new();
+    for (frame, source) in self.call_sites().take(MAX_SHOWN_FRAMES) {
+      let msg = format!("...inside \"{}\", called here", frame.function);
+      let frame = print_in(input, source, frame.call_site, &msg, options);
+      out.push_str(&format!("\n{}", frame));
+    }
+    if self.trace.len() > MAX_SHOWN_FRAMES {
+      out.push_str(&format!(
+        "\n...and {} more calls",
+        self.trace.len() - MAX_SHOWN_FRAMES
+      ));
+    }
+    out
+  }
+
+  pub fn message(&self) -> String {
+    match &self.kind {
+      RuntimeErrorKind::UndefinedVariable(name, None) => {
+        format!("Hmm, I don't know any variable called \"{}\"", name)
+      }
+      RuntimeErrorKind::UndefinedVariable(name, Some(suggestion)) => format!(
+        "Hmm, I don't know any variable called \"{}\", did you mean \"{}\"?",
+        name, suggestion
+      ),
+      RuntimeErrorKind::UndefinedFunction(name, None) => {
+        format!("Hmm, I don't know any function called \"{}\"", name)
+      }
+      RuntimeErrorKind::UndefinedFunction(name, Some(suggestion)) => format!(
+        "Hmm, I don't know any function called \"{}\", did you mean \"{}\"?",
+        name, suggestion
+      ),
+      RuntimeErrorKind::DisabledBuiltin(name) => format!(
+        "Sorry, the \"{}\" function exists but it's disabled here",
+        name
+      ),
+      RuntimeErrorKind::WrongArgumentCount(name, expected, found) => format!(
+        "Hey, \"{}\" takes {} argument(s) but got {}",
+        name, expected, found
+      ),
+      RuntimeErrorKind::TooFewArguments(name, minimum, found) => format!(
+        "Hey, \"{}\" takes at least {} argument(s) but got {}",
+        name, minimum, found
+      ),
+      RuntimeErrorKind::WrongArgumentRange(name, min, max, found) => format!(
+        "Hey, \"{}\" takes {} to {} arguments but got {}",
+        name, min, max, found
+      ),
+      RuntimeErrorKind::NotAFunction(name, type_name) => format!(
+        "Hey, \"{}\" is {}, not a function, so you can't call it",
+        name,
+        with_article(type_name)
+      ),
+      RuntimeErrorKind::ExpectedNumber(type_name) => {
+        format!("Hey, I expected a number here but got {}", with_article(type_name))
+      }
+      RuntimeErrorKind::ExpectedInt(type_name) => {
+        format!("Hey, I expected an integer here but got {}", with_article(type_name))
+      }
+      RuntimeErrorKind::ExpectedList(type_name) => {
+        format!("Hey, I expected a list here but got {}", with_article(type_name))
+      }
+      RuntimeErrorKind::ExpectedFunction(type_name) => {
+        format!("Hey, I expected a function here but got {}", with_article(type_name))
+      }
+      RuntimeErrorKind::NoConvergence(name, iterations) => format!(
+        "Hmm, \"{}\" didn't find an answer within {} iterations",
+        name, iterations
+      ),
+      RuntimeErrorKind::FlatSlope(x) => format!(
+        "Hmm, the function is flat at {}, so I can't follow it to a root from there, try another starting point",
+        x
+      ),
+      RuntimeErrorKind::NoSignChange(a, b) => format!(
+        "Hey, the function has the same sign at {} and {}, so I can't bisect between them",
+        a, b
+      ),
+      RuntimeErrorKind::InvalidIterations(iterations) => format!(
+        "Hey, the number of iterations has to be above 0, not {}",
+        iterations
+      ),
+      RuntimeErrorKind::InvalidInterval(a, b) => format!(
+        "Hey, the interval has to go from a lower number to a higher one, not from {} to {}",
+        a, b
+      ),
+      RuntimeErrorKind::NothingToPlot(a, b) => format!(
+        "Hmm, the function isn't a number anywhere from {} to {}, so there's nothing to plot",
+        a, b
+      ),
+      RuntimeErrorKind::NotCompilable(what) => format!(
+        "Sorry, compiled expressions can't {} :(, evaluate this one instead",
+        what
+      ),
+      RuntimeErrorKind::InvalidStep(step) => format!(
+        "Hey, the step has to be a number above 0, not {}",
+        step
+      ),
+      RuntimeErrorKind::TooManyRows(rows) => format!(
+        "Whoa, that table would have {} rows, I can only make {} at most, try a bigger step",
+        rows, MAX_TABLE_ROWS
+      ),
+      RuntimeErrorKind::WrongColumnCount(expected, found) => format!(
+        "Hey, I need one column of values per parameter, that's {} but I got {}",
+        expected, found
+      ),
+      RuntimeErrorKind::RaggedColumns(first, other) => format!(
+        "Hey, every column needs a value for every point, but one has {} values and another {}",
+        first, other
+      ),
+      RuntimeErrorKind::TooFewItems(name, needed, found) => format!(
+        "Hey, \"{}\" needs a list with at least {} item(s) but got {}",
+        name, needed, found
+      ),
+      RuntimeErrorKind::InvalidShift(bits) => format!(
+        "Hey, I can only shift by 0 to 63 bits, not {}",
+        bits
+      ),
+      RuntimeErrorKind::InvalidTolerance(tolerance) => format!(
+        "Hey, the tolerance has to be a number that's 0 or more, not {}",
+        tolerance
+      ),
+      RuntimeErrorKind::EmptyRange(low, high) => format!(
+        "Hey, there aren't any numbers from {} to {}, the first one has to be the smaller one",
+        low, high
+      ),
+      RuntimeErrorKind::InvalidAngleMode(mode) => format!(
+        "Hmm, I don't know the angle mode {}, try \"deg\" or \"rad\"",
+        mode
+      ),
+      RuntimeErrorKind::CantConvert(val, type_name) => {
+        format!("Sorry, I can't turn {} into {} :(", val, with_article(type_name))
+      }
+      RuntimeErrorKind::ExpectedBool(type_name) => {
+        format!("Hey, I expected true or false here but got {}", with_article(type_name))
+      }
+      RuntimeErrorKind::AssertionFailed(cond, None) => {
+        format!("Oops, this assertion failed: {}", cond)
+      }
+      RuntimeErrorKind::AssertionFailed(cond, Some((left, right))) => format!(
+        "Oops, this assertion failed: {}, the left side was {} and the right side was {}",
+        cond, left, right
+      ),
+      RuntimeErrorKind::TooMuchRecursion => format!(
+        "Whoa, this went deeper than I can follow, more than {} calls or {} levels of nesting in all, is there a recursion that never stops?",
+        crate::expr::MAX_CALL_DEPTH,
+        crate::expr::MAX_EVAL_DEPTH
+      ),
+      RuntimeErrorKind::DivisionByZero => "Oops, you can't divide an integer by zero".to_owned(),
+      RuntimeErrorKind::Overflow => "Whoa, this number got too big for me to handle :(".to_owned(),
+      RuntimeErrorKind::PrintFailed(reason) => {
+        format!("Oops, I couldn't print this :(\n{}", reason)
+      }
+      RuntimeErrorKind::ImportUnreadable(path, reason) => {
+        format!("Oops, I couldn't read \"{}\" to import it :(\n{}", path, reason)
+      }
+      RuntimeErrorKind::ImportCycle(path) => format!(
+        "Hey, \"{}\" is still being imported, importing it here would never end",
+        path
+      ),
+      RuntimeErrorKind::ImportFailed(path, errors) => {
+        format!("Hmm, \"{}\" didn't run, so I couldn't import it:\n{}", path, errors)
+      }
+      RuntimeErrorKind::ImportsUnavailable => {
+        "Sorry, importing files isn't allowed here :(".to_owned()
+      }
+    }
+  }
+}
+
+// An expression that has no derivative, or one that can't be found
+// symbolically
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffError {
+  // Written as source
+  pub expr: String,
+}
+
+impl DiffError {
+  pub fn message(&self) -> String {
+    format!("Sorry, I don't know how to differentiate {} :(", self.expr)
+  }
+}
+
+// A compiled program that couldn't be loaded
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkError {
+  NotAChunk,
+  // The version the chunk was compiled with
+  WrongVersion(u8),
+  // Cut short or changed after compiling
+  Damaged,
+}
+
+impl ChunkError {
+  pub fn message(&self) -> String {
+    match self {
+      ChunkError::NotAChunk => {
+        "Hmm, this isn't a compiled program, make one with \"math-parser compile\"".to_owned()
+      }
+      ChunkError::WrongVersion(version) => format!(
+        "Sorry, this program was compiled for version {} of my bytecode but I only run version {}, compile it again :(",
+        version, VERSION
+      ),
+      ChunkError::Damaged => "Oops, this compiled program is damaged, compile it again".to_owned(),
+    }
+  }
+}
+
+// Expressions sent over as JSON that couldn't be read, or failed to run
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonError {
+  // What was wrong with the JSON, as serde_json put it
+  Invalid(String),
+  Runtime(RuntimeError),
+}
+
+#[cfg(feature = "json")]
+impl JsonError {
+  pub fn message(&self) -> String {
+    match self {
+      JsonError::Invalid(reason) => format!(
+        "Hmm, that JSON isn't an expression I can read :(\n{}",
+        reason
+      ),
+      JsonError::Runtime(err) => err.message(),
+    }
+  }
+}
+
+// A saved environment that couldn't be written or read back
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvFileError {
+  // What went wrong with the file, as the OS put it
+  Io(String),
+  // The file was read but doesn't run, the errors are in `script`
+  Invalid { script: String, errors: Vec<Error> },
+}
+
+impl EnvFileError {
+  pub fn message(&self) -> String {
+    match self {
+      EnvFileError::Io(reason) => {
+        format!("Oops, I couldn't get to the saved variables :(\n{}", reason)
+      }
+      EnvFileError::Invalid { script, errors } => format!(
+        "Hmm, the saved variables don't run, was the file changed?\n{}",
+        render_all(errors, script)
+      ),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeErrorKind {
+  // The operator, and the type of the operand it can't work with
+  WrongOperand(&'static str, &'static str),
+  ConditionNotBool(&'static str),
+  AssertionNotBool(&'static str),
+  MismatchedBranches(&'static str, &'static str),
+  // Function name, expected and actual number of arguments
+  WrongArgumentCount(String, usize, usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+  pub kind: TypeErrorKind,
+  // The expression with the mistake, as source
+  pub expr: String,
+  // Of the node with the mistake, or the closest one around it for trees
+  // that weren't parsed
+  pub span: Option<Span>,
+}
+
+impl TypeError {
+  pub fn message(&self) -> String {
+    let message = match &self.kind {
+      TypeErrorKind::WrongOperand(op, type_name) => {
+        format!(
+          "Hey, \"{}\" can't work with {}",
+          op,
+          with_article(type_name)
+        )
+      }
+      TypeErrorKind::ConditionNotBool(type_name) => format!(
+        "Hey, the condition of this if is {}, not true or false",
+        with_article(type_name)
+      ),
+      TypeErrorKind::AssertionNotBool(type_name) => format!(
+        "Hey, this assertion checks {}, not true or false",
+        with_article(type_name)
+      ),
+      TypeErrorKind::MismatchedBranches(if_true, if_false) => format!(
+        "Hmm, one branch of this if gives {} and the other {}",
+        with_article(if_true),
+        with_article(if_false)
+      ),
+      TypeErrorKind::WrongArgumentCount(name, expected, found) => format!(
+        "Hey, \"{}\" takes {} argument(s) but got {}",
+        name, expected, found
+      ),
+    };
+    format!("{}, in \"{}\"", message, self.expr)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+  Lex(LexError),
+  Parse(ParseError),
+  Runtime(RuntimeError),
+  // Found by the type checker, before evaluating
+  Type(TypeError),
+  // A warning whose lint was set to deny
+  Denied(Warning),
+}
+
+impl Error {
+  pub fn span(&self) -> Option<Span> {
+    match self {
+      Error::Lex(err) => Some(err.span),
+      Error::Parse(err) => Some(err.span),
+      Error::Runtime(err) => err.span,
+      Error::Type(err) => err.span,
+      Error::Denied(warning) => Some(warning.span),
+    }
+  }
+
+  pub fn message(&self) -> String {
+    match self {
+      Error::Lex(err) => err.message().to_owned(),
+      Error::Parse(err) => err.message().to_owned(),
+      Error::Runtime(err) => err.message(),
+      Error::Type(err) => err.message(),
+      Error::Denied(warning) => format!(
+        "{} (\"{}\" is denied, so this counts as an error)",
+        warning.message(),
+        warning.kind.lint().name()
+      ),
+    }
+  }
+
+  // Renders the error with the offending part of the input underlined
+  pub fn render(&self, input: &str) -> String {
+    self.render_with(input, &RenderOptions::default())
+  }
+
+  pub fn render_with(&self, input: &str, options: &RenderOptions) -> String {
+    match self {
+      Error::Lex(LexError {
+        kind: LexErrorKind::EmptyInput,
+        ..
+      }) => self.message(),
+      Error::Parse(err) => {
+        let mut diagnostic = Diagnostic::new(err.message()).primary(err.span, "");
+        for label in &err.labels {
+          diagnostic = diagnostic.secondary(label.span, &label.text);
+        }
+        diagnostic.render_with(input, options)
+      }
+      Error::Runtime(err) => {
+        let first = match err.span {
+          Some(span) => print_in(input, err.source(), span, &self.message(), options),
+          None => self.message(),
+        };
+        first + &err.render_trace(input, options)
+      }
+      _ => match self.span() {
+        Some(span) => print_err_with(input, span, &self.message(), options),
+        None => self.message(),
+      },
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WarningKind {
+  InexactFloat,
+  UnusedVariable(String),
+  ShadowedVariable(String),
+  // What the condition always is
+  ConstantCondition(bool),
+}
+
+impl WarningKind {
+  pub fn lint(&self) -> Lint {
+    match self {
+      WarningKind::InexactFloat => Lint::InexactFloat,
+      WarningKind::UnusedVariable(_) => Lint::UnusedVariable,
+      WarningKind::ShadowedVariable(_) => Lint::ShadowedVariable,
+      WarningKind::ConstantCondition(_) => Lint::ConstantCondition,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+  pub kind: WarningKind,
+  pub span: Span,
+}
+
+impl Warning {
+  pub fn new(kind: WarningKind, span: Span) -> Self {
+    Warning { kind, span }
+  }
+
+  pub fn message(&self) -> String {
+    match &self.kind {
+      WarningKind::InexactFloat => "This number can't be stored exactly, so I'll round it to the closest float I can (keeping it exact would need a rational or decimal number mode)".to_owned(),
+      WarningKind::UnusedVariable(name) => format!(
+        "The variable \"{}\" is declared but never used",
+        name
+      ),
+      WarningKind::ShadowedVariable(name) => format!(
+        "This \"{}\" hides the variable with the same name declared before it",
+        name
+      ),
+      WarningKind::ConstantCondition(val) => format!(
+        "This condition is always {}, so the {} branch never runs",
+        val,
+        if *val { "else" } else { "then" }
+      ),
+    }
+  }
+
+  pub fn render(&self, input: &str) -> String {
+    self.render_with(input, &RenderOptions::default())
+  }
+
+  pub fn render_with(&self, input: &str, options: &RenderOptions) -> String {
+    let msg = format!("Warning: {}", self.message());
+    print_err_with(input, self.span, &msg, options)
+  }
+}
+
+impl core::fmt::Display for Warning {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    write!(f, "{}", self.message())
+  }
+}
+
+pub fn render_all(errors: &[Error], input: &str) -> String {
+  render_all_with(errors, input, &RenderOptions::default())
+}
+
+pub fn render_all_with(errors: &[Error], input: &str, options: &RenderOptions) -> String {
+  errors
+    .iter()
+    .map(|err| err.render_with(input, options))
+    .collect::<Vec<String>>()
+    .join("\n\n")
+}
+
+impl core::fmt::Display for LexError {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    write!(f, "{}", self.message())
+  }
+}
+
+impl core::fmt::Display for ParseError {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    write!(f, "{}", self.message())
+  }
+}
+
+impl core::fmt::Display for RuntimeError {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    write!(f, "{}", self.message())
+  }
+}
+
+impl core::fmt::Display for TypeError {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    write!(f, "{}", self.message())
+  }
+}
+
+impl core::fmt::Display for DiffError {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    write!(f, "{}", self.message())
+  }
+}
+
+impl core::fmt::Display for ChunkError {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    write!(f, "{}", self.message())
+  }
+}
+
+impl core::fmt::Display for EnvFileError {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    write!(f, "{}", self.message())
+  }
+}
+
+#[cfg(feature = "json")]
+impl core::fmt::Display for JsonError {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    write!(f, "{}", self.message())
+  }
+}
+
+impl core::fmt::Display for Error {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    match self {
+      Error::Lex(err) => err.fmt(f),
+      Error::Parse(err) => err.fmt(f),
+      Error::Runtime(err) => err.fmt(f),
+      Error::Type(err) => err.fmt(f),
+      Error::Denied(_) => write!(f, "{}", self.message()),
+    }
+  }
+}
+
+impl core::error::Error for LexError {}
+
+impl core::error::Error for ParseError {}
+
+impl core::error::Error for RuntimeError {}
+
+impl core::error::Error for TypeError {}
+
+impl core::error::Error for Error {
+  fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+    match self {
+      Error::Lex(err) => Some(err),
+      Error::Parse(err) => Some(err),
+      Error::Runtime(err) => Some(err),
+      Error::Type(err) => Some(err),
+      Error::Denied(_) => None,
+    }
+  }
+}
+
+impl From<LexError> for Error {
+  fn from(err: LexError) -> Self {
+    Error::Lex(err)
+  }
+}
+
+impl From<ParseError> for Error {
+  fn from(err: ParseError) -> Self {
+    Error::Parse(err)
+  }
+}
+
+impl From<RuntimeError> for Error {
+  fn from(err: RuntimeError) -> Self {
+    Error::Runtime(err)
+  }
+}
+
+impl From<TypeError> for Error {
+  fn from(err: TypeError) -> Self {
+    Error::Type(err)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn line_and_column() {
+    assert_eq!(line_col("let a = 1\nlet b = a", 14), (2, 5));
+    assert_eq!(line_col("ä + 1", 3), (1, 3));
+  }
+
+  #[test]
+  fn offsets_outside_the_input_are_clamped() {
+    assert_eq!(line_col("1 + 2", 100), (1, 6));
+    // In the middle of the ä
+    assert_eq!(line_col("ä", 1), (1, 1));
+  }
+
+  #[test]
+  fn crlf_lines_are_shown_without_the_cr() {
+    let input = "let a = 1\r\nlet b = c\r\n";
+    let rendered = print_err_with(input, Span::new(19, 20), "Hmm", &RenderOptions::default());
+    assert_eq!(
+      rendered,
+      "Hmm (line 2, column 9)\n2| let b = c\n           ^"
+    );
+  }
 }
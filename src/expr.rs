@@ -1,34 +1,101 @@
 // VALUE
 
-#[derive(Debug, Copy, Clone)]
+// Comparing with == is exact, spans included and NaN unequal to itself,
+// structurally_equal is for when only the meaning matters
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
   Int(i64),
   Float(f64),
   Bool(bool),
+  Str(Rc<str>),
+  List(Rc<Vec<Value>>),
+  Function(Rc<Function>),
 }
 
 impl Value {
-  fn f64(&self) -> f64 {
+  pub fn f64(&self) -> Result<f64, RuntimeError> {
     match self {
-      Value::Float(num) => *num,
-      Value::Int(num) => *num as f64,
+      Value::Float(num) => Ok(*num),
+      Value::Int(num) => Ok(*num as f64),
       Value::Bool(b) => {
         if *b {
-          1.0
+          Ok(1.0)
         } else {
-          0.0
+          Ok(0.0)
         }
       }
+      val => Err(RuntimeError::new(RuntimeErrorKind::ExpectedNumber(
+        val.type_name(),
+      ))),
     }
   }
+
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Value::Int(_) => "int",
+      Value::Float(_) => "float",
+      Value::Bool(_) => "bool",
+      Value::Str(_) => "string",
+      Value::List(_) => "list",
+      Value::Function(_) => "function",
+    }
+  }
+}
+
+// Rounds to that many significant digits, so the last digits of an
+// inexact result like 0.1 + 0.2 don't show
+pub(crate) fn round_significant(num: f64, digits: usize) -> f64 {
+  if !num.is_finite() || digits == 0 {
+    return num;
+  }
+  format!("{:.*e}", digits - 1, num).parse().unwrap_or(num)
 }
 
-impl std::fmt::Display for Value {
-  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+// A precision like in "{:.6}" is the most significant digits floats show,
+// lists pass it on to their items
+impl core::fmt::Display for Value {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
     match self {
       Int(num) => write!(f, "{}", num),
-      Float(num) => write!(f, "{}", num),
+      Float(num) => match f.precision() {
+        Some(digits) => write!(f, "{}", round_significant(*num, digits)),
+        None => write!(f, "{}", num),
+      },
       Bool(b) => write!(f, "{}", b),
+      Str(text) => write!(f, "{}", text),
+      List(items) => {
+        // Quoted, so ["a, b"] doesn't look like two strings
+        let items = items.iter().map(|item| match (item, f.precision()) {
+          (Str(_), _) => item.to_source(),
+          (item, Some(digits)) => format!("{:.*}", digits, item),
+          (item, None) => item.to_string(),
+        });
+        let items = items.collect::<Vec<String>>();
+        write!(f, "[{}]", items.join(", "))
+      }
+      Function(function) => write!(f, "fn {}({})", function.name, function.params.join(", ")),
+    }
+  }
+}
+
+impl Value {
+  // Source text that evaluates back to exactly this value
+  pub fn to_source(&self) -> String {
+    match self {
+      Float(num) if num.is_nan() => "(0.0 / 0.0)".to_owned(),
+      Float(num) if num.is_infinite() && *num > 0.0 => "(1.0 / 0.0)".to_owned(),
+      Float(num) if num.is_infinite() => "(-1.0 / 0.0)".to_owned(),
+      // Debug formatting keeps the ".0" so the value stays a float
+      Float(num) => format!("{:?}", num),
+      Str(text) => quote(text),
+      List(items) => {
+        let items = items.iter().map(Value::to_source).collect::<Vec<String>>();
+        format!("[{}]", items.join(", "))
+      }
+      // Declarations evaluate to the declared function
+      Function(function) => format!("({})", Expr::FnDeclaration(function.clone(), NO_SPAN)),
+      val => val.to_string(),
     }
   }
 }
@@ -36,155 +103,1473 @@ impl std::fmt::Display for Value {
 use Value::*;
 
 // SCOPE
-use std::collections::HashMap;
+use crate::builtins::{self, Arity, BUILTINS};
+use crate::error::{suggest, RuntimeError, RuntimeErrorKind, Source, Span, NO_SPAN};
+#[cfg(feature = "std")]
+use crate::import::{eval_import, Imports};
+use crate::lexer::quote;
+use crate::prelude::*;
+use crate::program::eval_all;
+use crate::recording::Recording;
+use crate::rng::Rng;
+use crate::stdlib;
+use crate::symbol::{Symbol, SymbolMap};
+use alloc::rc::Rc;
+use core::convert::TryFrom;
+#[cfg(not(feature = "std"))]
+use core::fmt::Write;
+use core::ops;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+// Deep enough for reasonable recursion, shallow enough not to overflow the
+// stack of the main thread
+pub const MAX_CALL_DEPTH: usize = 200;
+
+// How deep evaluation can nest in all, counting every expression inside the
+// calls too. Function bodies can each be nested as deep as the parser
+// allows, so the call depth alone doesn't keep the stack from overflowing.
+// Debug builds need about 3 KB of stack for every level.
+pub const MAX_EVAL_DEPTH: usize = 2000;
+
+// Floats this close are equal, to make up for rounding like in 0.1 + 0.2
+pub const DEFAULT_TOLERANCE: f64 = 0.000_001;
 
 pub struct Env {
-  vars: HashMap<String, Value>,
+  vars: SymbolMap<Value>,
+  // The standard library's constants and functions, found after the globals
+  prelude: SymbolMap<Value>,
+  // Parameters and local variables of the user function calls in progress,
+  // innermost last
+  frames: Vec<SymbolMap<Value>>,
+  // Builtins that may be called, all of them when None
+  allowed_builtins: Option<HashSet<String>>,
+  // How many expressions have been evaluated in total
+  steps: u64,
+  // How many evaluations are in progress, innermost included
+  depth: usize,
+  // Results of cached builtins, keyed by cache_key
+  cache: HashMap<String, Value>,
+  // Counts changes to the globals, a cached result made before one might
+  // depend on what changed
+  globals_version: u64,
+  // Set by what a cached result couldn't repeat, printing and random numbers
+  unrepeatable: bool,
+  // Asked for variables that aren't defined and told about new globals, lets
+  // hosts keep variables somewhere else
+  backend: Option<Box<dyn Variables>>,
+  // Every evaluation step, only while recording
+  recording: Option<Recording>,
+  // How many assert statements have held so far
+  passed_asserts: u64,
+  // How close floats have to be for == to consider them equal
+  tolerance: f64,
+  // What the trigonometric builtins measure angles in
+  angle_mode: AngleMode,
+  // Used by random(), seeded from the clock unless seed() is called
+  rng: Rng,
+  // The most significant digits printed floats show, all of them if None
+  precision: Option<usize>,
+  // Where print statements write their lines
+  output: Box<dyn Write>,
+  // The files imported so far and the ones still running
+  #[cfg(feature = "std")]
+  imports: Imports,
+  // What the code running now is from, given to the functions it declares
+  pub(crate) source: Source,
+}
+
+// Without std print statements have nowhere to go until they're given a
+// place with set_output
+#[cfg(not(feature = "std"))]
+struct NoOutput;
+
+#[cfg(not(feature = "std"))]
+impl Write for NoOutput {
+  fn write_str(&mut self, _: &str) -> core::fmt::Result {
+    Ok(())
+  }
+}
+
+#[cfg(feature = "std")]
+fn default_output() -> Box<dyn Write> {
+  Box::new(io::stdout())
+}
+
+#[cfg(not(feature = "std"))]
+fn default_output() -> Box<dyn Write> {
+  Box::new(NoOutput)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AngleMode {
+  Radians,
+  Degrees,
+}
+
+impl AngleMode {
+  pub fn from_name(name: &str) -> Option<AngleMode> {
+    match name {
+      "rad" | "radians" => Some(AngleMode::Radians),
+      "deg" | "degrees" => Some(AngleMode::Degrees),
+      _ => None,
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      AngleMode::Radians => "rad",
+      AngleMode::Degrees => "deg",
+    }
+  }
+}
+
+pub type Resolver = Box<dyn Fn(&str) -> Option<Value>>;
+
+// Somewhere other than the Env that global variables can come from, like a
+// config file, a database or live sensor readings. The Env still keeps the
+// variables declared in it, a backend only has to hold what it wants to.
+pub trait Variables {
+  // Asked for variables the Env doesn't have, every time they're read, so
+  // values can change between reads
+  fn get(&self, name: &str) -> Option<Value>;
+
+  // Told about every global variable the program declares, as the Env
+  // stores it. Rolling the Env back doesn't undo what was set here.
+  fn set(&mut self, _name: &str, _val: &Value) {}
+}
+
+// A closure works as a backend that only supplies values
+impl<F: Fn(&str) -> Option<Value>> Variables for F {
+  fn get(&self, name: &str) -> Option<Value> {
+    self(name)
+  }
+}
+
+impl Default for Env {
+  fn default() -> Self {
+    Env::new()
+  }
 }
 
 impl Env {
+  // With the standard library loaded
   pub fn new() -> Self {
     Env {
-      vars: HashMap::new(),
+      prelude: stdlib::load(),
+      ..Env::without_prelude()
+    }
+  }
+
+  // For embedders that want nothing defined they didn't define themselves
+  pub fn without_prelude() -> Self {
+    Env {
+      vars: SymbolMap::new(),
+      prelude: SymbolMap::new(),
+      frames: Vec::new(),
+      allowed_builtins: None,
+      steps: 0,
+      depth: 0,
+      cache: HashMap::new(),
+      globals_version: 0,
+      unrepeatable: false,
+      backend: None,
+      recording: None,
+      passed_asserts: 0,
+      tolerance: DEFAULT_TOLERANCE,
+      angle_mode: AngleMode::Radians,
+      rng: Rng::from_time(),
+      precision: None,
+      output: default_output(),
+      #[cfg(feature = "std")]
+      imports: Imports::default(),
+      source: Source::Input,
+    }
+  }
+
+  // Looks through the innermost function call's locals, then the globals,
+  // then the prelude, then asks the backend
+  pub fn get(&self, name: &str) -> Option<Value> {
+    match Symbol::existing(name) {
+      Some(symbol) => self.get_symbol(&symbol),
+      None => self.resolve(name),
+    }
+  }
+
+  // Like get, without looking the name up in the symbol table
+  pub fn get_symbol(&self, symbol: &Symbol) -> Option<Value> {
+    self
+      .frames
+      .last()
+      .and_then(|frame| frame.get(symbol))
+      .or_else(|| self.vars.get(symbol))
+      .or_else(|| self.prelude.get(symbol))
+      .cloned()
+      .or_else(|| self.resolve(symbol))
+  }
+
+  fn resolve(&self, name: &str) -> Option<Value> {
+    self.backend.as_ref().and_then(|backend| backend.get(name))
+  }
+
+  // Values from the resolver aren't stored, so it's asked again every time
+  // the variable is used, e.g. for sensor readings:
+  // env.set_resolver(|name| sensors.read(name).map(Value::Float))
+  pub fn set_resolver<F: Fn(&str) -> Option<Value> + 'static>(&mut self, resolver: F) {
+    self.set_backend(resolver);
+  }
+
+  // Printed lines go to stdout unless they're sent somewhere else, like a
+  // buffer for tests or a text widget. Without std it's a core::fmt::Write
+  // instead of a std::io one.
+  pub fn set_output<W: Write + 'static>(&mut self, output: W) {
+    self.output = Box::new(output);
+  }
+
+  // Replaces the resolver too, there's only one of either
+  pub fn set_backend<V: Variables + 'static>(&mut self, backend: V) {
+    self.backend = Some(Box::new(backend));
+  }
+
+  // Declares a variable in the innermost function call, or globally outside
+  // of functions
+  pub fn set(&mut self, name: &str, val: Value) {
+    self.set_symbol(Symbol::new(name), val);
+  }
+
+  pub fn set_symbol(&mut self, symbol: Symbol, val: Value) {
+    if let Some(recording) = &mut self.recording {
+      let before = match self.frames.last() {
+        Some(frame) => frame.get(&symbol),
+        None => self.vars.get(&symbol),
+      };
+      recording.changed(&symbol, before.cloned(), &val);
+    }
+    match self.frames.last_mut() {
+      Some(frame) => {
+        frame.insert(symbol, val);
+      }
+      None => {
+        if let Some(backend) = &mut self.backend {
+          backend.set(&symbol, &val);
+        }
+        self.globals_version += 1;
+        self.vars.insert(symbol, val);
+      }
+    }
+  }
+
+  pub fn builtin_allowed(&self, name: &str) -> bool {
+    match &self.allowed_builtins {
+      Some(allowed) => allowed.contains(name),
+      None => true,
+    }
+  }
+
+  // Environments limited to some builtins run formulas that aren't trusted,
+  // those can't read files either
+  #[cfg(feature = "std")]
+  pub(crate) fn imports_allowed(&self) -> bool {
+    self.allowed_builtins.is_none()
+  }
+
+  #[cfg(feature = "std")]
+  pub(crate) fn imports(&mut self) -> &mut Imports {
+    &mut self.imports
+  }
+
+  pub fn builtin_names(&self) -> impl Iterator<Item = &str> {
+    BUILTINS
+      .iter()
+      .map(|builtin| builtin.name)
+      .filter(move |name| self.builtin_allowed(name))
+  }
+
+  pub fn var_names(&self) -> impl Iterator<Item = &str> {
+    self
+      .frames
+      .last()
+      .into_iter()
+      .flat_map(|frame| frame.keys())
+      .chain(self.vars.keys())
+      .map(Symbol::as_str)
+  }
+
+  // The prelude's too
+  pub fn function_names(&self) -> impl Iterator<Item = &str> {
+    self
+      .vars
+      .iter()
+      .chain(self.prelude.iter())
+      .filter(|(_, val)| matches!(val, Value::Function(_)))
+      .map(|(name, _)| name.as_str())
+  }
+
+  pub fn prelude_names(&self) -> impl Iterator<Item = &str> {
+    self.prelude.keys().map(Symbol::as_str)
+  }
+
+  pub fn vars(&self) -> impl Iterator<Item = (&Symbol, &Value)> {
+    self.vars.iter()
+  }
+
+  pub fn steps(&self) -> u64 {
+    self.steps
+  }
+
+  pub(crate) fn count_step(&mut self) {
+    self.steps += 1;
+  }
+
+  // Evaluating one level deeper, which has to be left again with `leave`
+  pub(crate) fn enter(&mut self) -> Result<(), RuntimeError> {
+    if self.depth >= MAX_EVAL_DEPTH {
+      return Err(RuntimeError::new(RuntimeErrorKind::TooMuchRecursion));
+    }
+    self.depth += 1;
+    Ok(())
+  }
+
+  pub(crate) fn leave(&mut self) {
+    self.depth -= 1;
+  }
+
+  pub(crate) fn is_recording(&self) -> bool {
+    self.recording.is_some()
+  }
+
+  pub fn passed_asserts(&self) -> u64 {
+    self.passed_asserts
+  }
+
+  pub fn tolerance(&self) -> f64 {
+    self.tolerance
+  }
+
+  pub fn set_tolerance(&mut self, tolerance: f64) {
+    self.tolerance = tolerance;
+  }
+
+  pub fn angle_mode(&self) -> AngleMode {
+    self.angle_mode
+  }
+
+  pub fn set_angle_mode(&mut self, mode: AngleMode) {
+    self.angle_mode = mode;
+  }
+
+  pub fn precision(&self) -> Option<usize> {
+    self.precision
+  }
+
+  pub fn set_precision(&mut self, precision: Option<usize>) {
+    self.precision = precision;
+  }
+
+  // How a value gets printed, with the precision applied
+  pub fn display(&self, val: &Value) -> String {
+    match self.precision {
+      Some(digits) => format!("{:.*}", digits, val),
+      None => val.to_string(),
+    }
+  }
+
+  pub fn rng(&mut self) -> &mut Rng {
+    self.unrepeatable = true;
+    &mut self.rng
+  }
+
+  // Makes random() give the same numbers every time it's seeded the same
+  pub fn seed(&mut self, seed: u64) {
+    self.rng = Rng::new(seed);
+  }
+
+  pub fn cache_len(&self) -> usize {
+    self.cache.len()
+  }
+
+  pub fn clear_cache(&mut self) {
+    self.cache.clear();
+  }
+
+  // Starts logging every evaluated expression, replacing any recording that
+  // wasn't taken yet
+  pub fn start_recording(&mut self) {
+    self.recording = Some(Recording::new());
+  }
+
+  // Stops recording and returns what was recorded
+  pub fn take_recording(&mut self) -> Option<Recording> {
+    self.recording.take()
+  }
+
+  // Remembers the global variables, so that everything done after this can
+  // be undone with `rollback`
+  pub fn snapshot(&self) -> Snapshot {
+    Snapshot {
+      vars: self.vars.clone(),
+      #[cfg(feature = "std")]
+      imports: self.imports.clone(),
+    }
+  }
+
+  pub fn rollback(&mut self, snapshot: Snapshot) {
+    self.vars = snapshot.vars;
+    #[cfg(feature = "std")]
+    {
+      self.imports = snapshot.imports;
+    }
+    self.globals_version += 1;
+    self.frames.clear();
+  }
+}
+
+// The global variables of an Env at some point in time, and which files were
+// imported by then. Values are cheap to clone since functions are shared.
+#[derive(Clone)]
+pub struct Snapshot {
+  vars: SymbolMap<Value>,
+  // A file imported after the snapshot has its declarations rolled back, so
+  // importing it again has to run it again
+  #[cfg(feature = "std")]
+  imports: Imports,
+}
+
+// Configures an Env before it's used, e.g. to expose only some builtins to
+// untrusted formulas:
+// EnvBuilder::new().allow_builtins(&["sin", "cos"]).build()
+#[derive(Default, Clone)]
+pub struct EnvBuilder {
+  allowed_builtins: Option<HashSet<String>>,
+  tolerance: Option<f64>,
+  angle_mode: Option<AngleMode>,
+  precision: Option<usize>,
+  no_prelude: bool,
+}
+
+impl EnvBuilder {
+  pub fn new() -> Self {
+    EnvBuilder::default()
+  }
+
+  pub fn allow_builtins(mut self, names: &[&str]) -> Self {
+    self.allowed_builtins = Some(names.iter().map(|&name| name.to_owned()).collect());
+    self
+  }
+
+  pub fn tolerance(mut self, tolerance: f64) -> Self {
+    self.tolerance = Some(tolerance);
+    self
+  }
+
+  pub fn angle_mode(mut self, mode: AngleMode) -> Self {
+    self.angle_mode = Some(mode);
+    self
+  }
+
+  pub fn precision(mut self, digits: usize) -> Self {
+    self.precision = Some(digits);
+    self
+  }
+
+  // Leaves out the standard library, like Env::without_prelude
+  pub fn no_prelude(mut self) -> Self {
+    self.no_prelude = true;
+    self
+  }
+
+  pub fn build(self) -> Env {
+    let env = match self.no_prelude {
+      true => Env::without_prelude(),
+      false => Env::new(),
+    };
+    Env {
+      allowed_builtins: self.allowed_builtins,
+      tolerance: self.tolerance.unwrap_or(DEFAULT_TOLERANCE),
+      angle_mode: self.angle_mode.unwrap_or(AngleMode::Radians),
+      precision: self.precision,
+      ..env
     }
   }
 }
 
 // EXPRESSION
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BinOp {
+  Pow,
+  Mul,
+  Div,
+  FloorDiv,
+  Add,
+  Sub,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  Equal,
+  NotEqual,
+  BitAnd,
+  BitOr,
+  BitXor,
+  ShiftLeft,
+  ShiftRight,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnOp {
+  Neg,
+  Sqrt,
+  Not,
+  // Written after the number, 20% is 0.2
+  Percent,
+}
+
+// Binding powers drive both parsing and printing. A higher binding power
+// binds tighter; left < right makes an operator left-associative.
+impl BinOp {
+  pub fn is_comparison(self) -> bool {
+    matches!(
+      self,
+      BinOp::Equal
+        | BinOp::NotEqual
+        | BinOp::Less
+        | BinOp::LessEqual
+        | BinOp::Greater
+        | BinOp::GreaterEqual
+    )
+  }
+
+  pub fn binding_power(self) -> (u8, u8) {
+    match self {
+      BinOp::Equal | BinOp::NotEqual => (1, 2),
+      BinOp::Less | BinOp::LessEqual | BinOp::Greater | BinOp::GreaterEqual => (3, 4),
+      // Tighter than comparisons, so "flags & 4 == 0" tests a bit
+      BinOp::BitOr => (5, 6),
+      BinOp::BitXor => (7, 8),
+      BinOp::BitAnd => (9, 10),
+      BinOp::ShiftLeft | BinOp::ShiftRight => (11, 12),
+      BinOp::Add | BinOp::Sub => (13, 14),
+      BinOp::Mul | BinOp::Div | BinOp::FloorDiv => (15, 16),
+      // Right-associative, and tighter than a prefix minus so -2^2 is -4
+      BinOp::Pow => (19, 18),
+    }
+  }
+
+  pub fn symbol(self) -> &'static str {
+    match self {
+      BinOp::Pow => "^",
+      BinOp::Mul => "*",
+      BinOp::Div => "/",
+      BinOp::FloorDiv => "//",
+      BinOp::Add => "+",
+      BinOp::Sub => "-",
+      BinOp::Less => "<",
+      BinOp::LessEqual => "<=",
+      BinOp::Greater => ">",
+      BinOp::GreaterEqual => ">=",
+      BinOp::Equal => "==",
+      BinOp::NotEqual => "!=",
+      BinOp::BitAnd => "&",
+      BinOp::BitOr => "|",
+      BinOp::BitXor => "xor",
+      BinOp::ShiftLeft => "<<",
+      BinOp::ShiftRight => ">>",
+    }
+  }
+}
+
+impl UnOp {
+  pub fn binding_power(self) -> u8 {
+    match self {
+      UnOp::Neg | UnOp::Sqrt => 17,
+      // Looser than comparisons, "not x > 1" is "not (x > 1)"
+      UnOp::Not => 1,
+      // Postfix, so this is how tightly it holds on to the left like for
+      // superscripts
+      UnOp::Percent => BinOp::Pow.binding_power().0,
+    }
+  }
+
+  pub fn symbol(self) -> &'static str {
+    match self {
+      UnOp::Neg => "-",
+      UnOp::Sqrt => "√",
+      UnOp::Not => "!",
+      UnOp::Percent => "%",
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Function {
+  pub name: String,
+  pub params: Vec<Symbol>,
+  pub body: Expr,
+  // Only for rendering errors, a function sent somewhere else is from the
+  // input there
+  #[cfg_attr(feature = "serde", serde(skip))]
+  pub source: Source,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
-  Literal(Value),
-  VarDeclaration(String, Box<Expr>),
-  Var(String),
-  Print(Box<Expr>),
-  Multiplication(Box<Expr>, Box<Expr>),
-  Division(Box<Expr>, Box<Expr>),
-  Addition(Box<Expr>, Box<Expr>),
-  Subtraction(Box<Expr>, Box<Expr>),
-  Negative(Box<Expr>),
-  Equality(Box<Expr>, Box<Expr>),
-  Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+  // Spans that aren't described are all of the node's source. Nodes that
+  // weren't parsed from anything have NO_SPAN.
+  Literal(Value, Span),
+  // The span is the name being declared
+  VarDeclaration(Symbol, Box<Expr>, Span),
+  // The span is the name
+  Var(Symbol, Span),
+  // Prints every value on one line
+  Print(Vec<Expr>, Span),
+  // Fails unless the condition is true, the span is the condition
+  Assert(Box<Expr>, Span),
+  Binary(BinOp, Box<Expr>, Box<Expr>, Span),
+  Unary(UnOp, Box<Expr>, Span),
+  Conditional(Box<Expr>, Box<Expr>, Box<Expr>, Span),
+  // The span is where the function gets called, for error traces
+  Call(String, Vec<Expr>, Span),
+  // The span is the name
+  FnDeclaration(Rc<Function>, Span),
+  List(Vec<Expr>, Span),
+  // A string with expressions in braces, the literal parts are Str literals
+  Interpolation(Vec<Expr>, Span),
+  // Like "sum(i, 1, 100, i * i)": the index, its first and last value and
+  // the term. The span is the name, like for a call.
+  Series(SeriesKind, Symbol, Box<Expr>, Box<Expr>, Box<Expr>, Span),
+  // Runs another file in the same environment, the first time only. The
+  // span is the path, written in quotes.
+  Import(String, Span),
+  // Declarations named with the module's name in front, like "geom.area".
+  // The body is stored qualified already, the printer takes the names off
+  // again. The span is the name.
+  Module(String, Vec<Expr>, Span),
+}
+
+// How a series combines its terms
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SeriesKind {
+  Sum,
+  Product,
+}
+
+impl SeriesKind {
+  // Written like a call with four arguments
+  pub fn from_name(name: &str) -> Option<SeriesKind> {
+    match name {
+      "sum" => Some(SeriesKind::Sum),
+      "product" => Some(SeriesKind::Product),
+      _ => None,
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      SeriesKind::Sum => "sum",
+      SeriesKind::Product => "product",
+    }
+  }
 }
 
 // EXPRESSION EVALUATION
 
-fn eval_multiplication(left: &Expr, right: &Expr, env: &mut Env) -> Value {
-  match (left.eval(env), right.eval(env)) {
-    (Int(left), Int(right)) => Int(left * right),
-    (left, right) => Float(left.f64() * right.f64()),
+type EvalResult = Result<Value, RuntimeError>;
+
+fn overflow() -> RuntimeError {
+  RuntimeError::new(RuntimeErrorKind::Overflow)
+}
+
+fn eval_multiplication(left: Value, right: Value) -> EvalResult {
+  match (left, right) {
+    (Int(left), Int(right)) => left.checked_mul(right).map(Int).ok_or_else(overflow),
+    (left, right) => Ok(Float(left.f64()? * right.f64()?)),
+  }
+}
+
+// Always exact as far as floats go, 7 / 2 is 3.5
+fn eval_division(left: Value, right: Value) -> EvalResult {
+  match (left, right) {
+    (Int(_), Int(0)) => Err(RuntimeError::new(RuntimeErrorKind::DivisionByZero)),
+    (left, right) => Ok(Float(left.f64()? / right.f64()?)),
   }
 }
 
-fn eval_division(left: &Expr, right: &Expr, env: &mut Env) -> Value {
-  match (left.eval(env), right.eval(env)) {
-    (Int(left), Int(right)) => Int(left / right),
-    (left, right) => Float(left.f64() / right.f64()),
+// Rounds towards negative infinity, so -7 // 2 is -4
+fn eval_floor_division(left: Value, right: Value) -> EvalResult {
+  match (left, right) {
+    (Int(_), Int(0)) => Err(RuntimeError::new(RuntimeErrorKind::DivisionByZero)),
+    (Int(left), Int(right)) => {
+      let quotient = left.checked_div(right).ok_or_else(overflow)?;
+      if left % right != 0 && (left < 0) != (right < 0) {
+        Ok(Int(quotient - 1))
+      } else {
+        Ok(Int(quotient))
+      }
+    }
+    (left, right) => Ok(Float((left.f64()? / right.f64()?).floor())),
   }
 }
 
-fn eval_addition(left: &Expr, right: &Expr, env: &mut Env) -> Value {
-  match (left.eval(env), right.eval(env)) {
-    (Int(left), Int(right)) => Int(left + right),
-    (left, right) => Float(left.f64() + right.f64()),
+fn eval_addition(left: Value, right: Value) -> EvalResult {
+  match (left, right) {
+    (Int(left), Int(right)) => left.checked_add(right).map(Int).ok_or_else(overflow),
+    (left, right) => Ok(Float(left.f64()? + right.f64()?)),
   }
 }
 
-fn eval_subtraction(left: &Expr, right: &Expr, env: &mut Env) -> Value {
-  match (left.eval(env), right.eval(env)) {
-    (Int(left), Int(right)) => Int(left - right),
-    (left, right) => Float(left.f64() - right.f64()),
+fn eval_subtraction(left: Value, right: Value) -> EvalResult {
+  match (left, right) {
+    (Int(left), Int(right)) => left.checked_sub(right).map(Int).ok_or_else(overflow),
+    (left, right) => Ok(Float(left.f64()? - right.f64()?)),
   }
 }
 
-fn eval_negative(val: &Expr, env: &mut Env) -> Value {
-  match val.eval(env) {
-    Int(num) => Int(-num),
-    Float(num) => Float(-num),
-    Bool(b) => Bool(!b),
+fn eval_negative(val: Value) -> EvalResult {
+  match val {
+    Int(num) => num.checked_neg().map(Int).ok_or_else(overflow),
+    Float(num) => Ok(Float(-num)),
+    val => Err(RuntimeError::new(RuntimeErrorKind::ExpectedNumber(
+      val.type_name(),
+    ))),
   }
 }
 
-fn eval_var_declaration(name: &str, expr: &Expr, env: &mut Env) -> Value {
-  let val = expr.eval(env);
-  env.vars.insert(name.to_owned(), val);
-  val
+fn eval_var_declaration(name: &Symbol, expr: &Expr, env: &mut Env) -> EvalResult {
+  let val = expr.eval(env)?;
+  env.set_symbol(name.clone(), val.clone());
+  Ok(val)
+}
+
+pub(crate) fn eval_var(name: &Symbol, env: &Env) -> EvalResult {
+  env.get_symbol(name).ok_or_else(|| {
+    let suggestion = suggest(name, env.var_names().chain(env.prelude_names()));
+    RuntimeError::new(RuntimeErrorKind::UndefinedVariable(
+      name.to_string(),
+      suggestion,
+    ))
+  })
 }
 
-fn eval_var(name: &str, env: &mut Env) -> Value {
-  *env.vars.get(name).unwrap()
+// Strings show their text like in interpolation, values are separated by
+// spaces
+fn eval_print(vals: &[Expr], env: &mut Env) -> EvalResult {
+  let vals = eval_args(vals, env)?;
+  let line = vals
+    .iter()
+    .map(|val| env.display(val))
+    .collect::<Vec<String>>();
+  env.unrepeatable = true;
+  writeln!(env.output, "{}", line.join(" "))
+    .map_err(|err| RuntimeError::new(RuntimeErrorKind::PrintFailed(err.to_string())))?;
+  // The last value, so "print x" is still x
+  Ok(vals.into_iter().last().unwrap_or_else(|| Str("".into())))
 }
 
-fn eval_print(val: &Expr, env: &mut Env) -> Value {
-  let val = val.eval(env);
-  println!("{}", val);
-  val
+fn eval_interpolation(parts: &[Expr], env: &mut Env) -> EvalResult {
+  let mut text = String::new();
+  for part in parts {
+    let val = part.eval(env)?;
+    text.push_str(&env.display(&val));
+  }
+  Ok(Str(text.into()))
+}
+
+fn eval_power(left: Value, right: Value) -> EvalResult {
+  match (left, right) {
+    (Int(base), Int(exponent)) if exponent >= 0 => u32::try_from(exponent)
+      .ok()
+      .and_then(|exponent| base.checked_pow(exponent))
+      .map(Int)
+      .ok_or_else(overflow),
+    (left, right) => Ok(Float(left.f64()?.powf(right.f64()?))),
+  }
 }
 
-fn eval_equality(left: &Expr, right: &Expr, env: &mut Env) -> Value {
-  Bool((left.eval(env).f64() - right.eval(env).f64()).abs() < 0.000_001)
+// Ints and bools compare exactly, anything involving a float within the
+// tolerance. Values of different types are never equal, except ints and
+// floats since both are numbers
+fn values_equal(left: &Value, right: &Value, tolerance: f64) -> bool {
+  match (left, right) {
+    (Int(left), Int(right)) => left == right,
+    (Bool(left), Bool(right)) => left == right,
+    (Str(left), Str(right)) => left == right,
+    (Int(_) | Float(_), Int(_) | Float(_)) => match (left.f64(), right.f64()) {
+      (Ok(left), Ok(right)) => (left - right).abs() <= tolerance,
+      _ => false,
+    },
+    (List(left), List(right)) => {
+      left.len() == right.len()
+        && left
+          .iter()
+          .zip(right.iter())
+          .all(|(left, right)| values_equal(left, right, tolerance))
+    }
+    // The same function, not just one that looks the same
+    (Function(left), Function(right)) => Rc::ptr_eq(left, right),
+    _ => false,
+  }
+}
+
+fn eval_equality(left: Value, right: Value, tolerance: f64) -> EvalResult {
+  Ok(Bool(values_equal(&left, &right, tolerance)))
+}
+
+fn eval_comparison(op: BinOp, left: Value, right: Value) -> EvalResult {
+  let ordering = match (left, right) {
+    (Int(left), Int(right)) => Some(left.cmp(&right)),
+    (left, right) => left.f64()?.partial_cmp(&right.f64()?),
+  };
+  // Comparisons with NaN are all false
+  let result = ordering.is_some_and(|ordering| match op {
+    BinOp::Less => ordering.is_lt(),
+    BinOp::LessEqual => ordering.is_le(),
+    BinOp::Greater => ordering.is_gt(),
+    _ => ordering.is_ge(),
+  });
+  Ok(Bool(result))
+}
+
+// Booleans work too, as logical operators that always evaluate both sides
+fn eval_bitwise(op: BinOp, left: Value, right: Value) -> EvalResult {
+  match (left, right) {
+    (Int(left), Int(right)) => Ok(Int(match op {
+      BinOp::BitAnd => left & right,
+      BinOp::BitOr => left | right,
+      _ => left ^ right,
+    })),
+    (Bool(left), Bool(right)) => Ok(Bool(match op {
+      BinOp::BitAnd => left & right,
+      BinOp::BitOr => left | right,
+      _ => left ^ right,
+    })),
+    (Int(_), val) | (val, _) => Err(RuntimeError::new(RuntimeErrorKind::ExpectedInt(
+      val.type_name(),
+    ))),
+  }
+}
+
+fn eval_shift(op: BinOp, left: Value, right: Value) -> EvalResult {
+  let (left, right) = match (left, right) {
+    (Int(left), Int(right)) => (left, right),
+    (Int(_), val) | (val, _) => {
+      return Err(RuntimeError::new(RuntimeErrorKind::ExpectedInt(
+        val.type_name(),
+      )))
+    }
+  };
+  let shifted = u32::try_from(right).ok().and_then(|bits| match op {
+    BinOp::ShiftLeft => left.checked_shl(bits),
+    _ => left.checked_shr(bits),
+  });
+  shifted
+    .map(Int)
+    .ok_or_else(|| RuntimeError::new(RuntimeErrorKind::InvalidShift(right)))
+}
+
+fn eval_sqrt(val: Value) -> EvalResult {
+  Ok(Float(val.f64()?.sqrt()))
+}
+
+fn eval_not(val: Value) -> EvalResult {
+  match val {
+    Bool(b) => Ok(Bool(!b)),
+    val => Err(RuntimeError::new(RuntimeErrorKind::ExpectedBool(
+      val.type_name(),
+    ))),
+  }
+}
+
+fn eval_percent(val: Value) -> EvalResult {
+  Ok(Float(val.f64()? / 100.0))
+}
+
+fn eval_binary(op: BinOp, left: &Expr, right: &Expr, env: &mut Env) -> EvalResult {
+  // Like on a calculator, "price + 8%" adds 8% of the price
+  if let (BinOp::Add | BinOp::Sub, Expr::Unary(UnOp::Percent, ..)) = (op, right) {
+    let (left, right) = (left.eval(env)?, right.eval(env)?);
+    return apply_percent_change(op, left, right);
+  }
+  let (left, right) = (left.eval(env)?, right.eval(env)?);
+  apply_binary(op, left, right, env)
+}
+
+// Adds or subtracts `percent` of `left`
+pub(crate) fn apply_percent_change(op: BinOp, left: Value, percent: Value) -> EvalResult {
+  let change = eval_multiplication(left.clone(), percent)?;
+  match op {
+    BinOp::Add => eval_addition(left, change),
+    _ => eval_subtraction(left, change),
+  }
 }
 
-fn eval_conditional(cond: &Expr, val_if_true: &Expr, val_if_false: &Expr, env: &mut Env) -> Value {
-  match cond.eval(env) {
+pub(crate) fn apply_binary(op: BinOp, left: Value, right: Value, env: &Env) -> EvalResult {
+  match op {
+    BinOp::Mul => eval_multiplication(left, right),
+    BinOp::Div => eval_division(left, right),
+    BinOp::FloorDiv => eval_floor_division(left, right),
+    BinOp::Add => eval_addition(left, right),
+    BinOp::Sub => eval_subtraction(left, right),
+    BinOp::Pow => eval_power(left, right),
+    BinOp::Less | BinOp::LessEqual | BinOp::Greater | BinOp::GreaterEqual => {
+      eval_comparison(op, left, right)
+    }
+    BinOp::Equal => eval_equality(left, right, env.tolerance),
+    BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor => eval_bitwise(op, left, right),
+    BinOp::ShiftLeft | BinOp::ShiftRight => eval_shift(op, left, right),
+    BinOp::NotEqual => match eval_equality(left, right, env.tolerance)? {
+      Bool(equal) => Ok(Bool(!equal)),
+      val => Ok(val),
+    },
+  }
+}
+
+// Comparisons are evaluated one side at a time, so a failure can say what
+// both sides were
+fn eval_assert(cond: &Expr, span: Span, env: &mut Env) -> EvalResult {
+  let (result, sides) = match cond {
+    Expr::Binary(op, left, right, _) if op.is_comparison() => {
+      let (left, right) = (left.eval(env)?, right.eval(env)?);
+      let sides = (left.to_source(), right.to_source());
+      (apply_binary(*op, left, right, env)?, Some(sides))
+    }
+    cond => (cond.eval(env)?, None),
+  };
+  match result {
+    Bool(true) => {
+      env.passed_asserts += 1;
+      Ok(Bool(true))
+    }
+    Bool(false) => {
+      Err(RuntimeError::new(RuntimeErrorKind::AssertionFailed(cond.to_string(), sides)).at(span))
+    }
+    val => Err(RuntimeError::new(RuntimeErrorKind::ExpectedBool(val.type_name())).at(span)),
+  }
+}
+
+fn eval_unary(op: UnOp, val: &Expr, env: &mut Env) -> EvalResult {
+  let val = val.eval(env)?;
+  apply_unary(op, val)
+}
+
+pub(crate) fn apply_unary(op: UnOp, val: Value) -> EvalResult {
+  match op {
+    UnOp::Neg => eval_negative(val),
+    UnOp::Sqrt => eval_sqrt(val),
+    UnOp::Not => eval_not(val),
+    UnOp::Percent => eval_percent(val),
+  }
+}
+
+fn eval_conditional(
+  cond: &Expr,
+  val_if_true: &Expr,
+  val_if_false: &Expr,
+  env: &mut Env,
+) -> EvalResult {
+  match cond.eval(env)? {
     Bool(true) => val_if_true.eval(env),
     _ => val_if_false.eval(env),
   }
 }
 
 impl Expr {
-  pub fn eval(&self, env: &mut Env) -> Value {
+  pub fn children(&self) -> Vec<&Expr> {
+    match self {
+      Expr::Literal(..) | Expr::Var(..) | Expr::Import(..) => Vec::new(),
+      Expr::VarDeclaration(_, val, _) | Expr::Unary(_, val, _) | Expr::Assert(val, _) => vec![val],
+      Expr::Binary(_, left, right, _) => vec![left, right],
+      Expr::Conditional(cond, val_if_true, val_if_false, _) => {
+        vec![cond, val_if_true, val_if_false]
+      }
+      Expr::Call(_, args, _)
+      | Expr::List(args, _)
+      | Expr::Print(args, _)
+      | Expr::Interpolation(args, _)
+      | Expr::Module(_, args, _) => args.iter().collect(),
+      Expr::FnDeclaration(function, _) => vec![&function.body],
+      Expr::Series(_, _, from, to, term, _) => vec![from, to, term],
+    }
+  }
+
+  pub fn span(&self) -> Span {
+    match self {
+      Expr::Literal(_, span)
+      | Expr::VarDeclaration(_, _, span)
+      | Expr::Var(_, span)
+      | Expr::Print(_, span)
+      | Expr::Assert(_, span)
+      | Expr::Binary(.., span)
+      | Expr::Unary(.., span)
+      | Expr::Conditional(.., span)
+      | Expr::Call(.., span)
+      | Expr::FnDeclaration(_, span)
+      | Expr::List(_, span)
+      | Expr::Interpolation(_, span)
+      | Expr::Series(.., span)
+      | Expr::Import(_, span)
+      | Expr::Module(.., span) => *span,
+    }
+  }
+
+  // Like children. A function body shared with other trees or values gets
+  // copied first, so changing it only changes this tree.
+  pub fn children_mut(&mut self) -> Vec<&mut Expr> {
     match self {
-      Expr::Literal(val) => *val,
-      Expr::VarDeclaration(name, expr) => eval_var_declaration(name, expr, env),
-      Expr::Var(name) => eval_var(name, env),
-      Expr::Print(val) => eval_print(val, env),
-      Expr::Multiplication(left, right) => eval_multiplication(left, right, env),
-      Expr::Division(left, right) => eval_division(left, right, env),
-      Expr::Addition(left, right) => eval_addition(left, right, env),
-      Expr::Subtraction(left, right) => eval_subtraction(left, right, env),
-      Expr::Negative(val) => eval_negative(val, env),
-      Expr::Equality(left, right) => eval_equality(left, right, env),
-      Expr::Conditional(cond, val_if_true, val_if_false) => {
+      Expr::Literal(..) | Expr::Var(..) | Expr::Import(..) => Vec::new(),
+      Expr::VarDeclaration(_, val, _) | Expr::Unary(_, val, _) | Expr::Assert(val, _) => vec![val],
+      Expr::Binary(_, left, right, _) => vec![left, right],
+      Expr::Conditional(cond, val_if_true, val_if_false, _) => {
+        vec![cond, val_if_true, val_if_false]
+      }
+      Expr::Call(_, args, _)
+      | Expr::List(args, _)
+      | Expr::Print(args, _)
+      | Expr::Interpolation(args, _)
+      | Expr::Module(_, args, _) => args.iter_mut().collect(),
+      Expr::FnDeclaration(function, _) => vec![&mut Rc::make_mut(function).body],
+      Expr::Series(_, _, from, to, term, _) => vec![from, to, term],
+    }
+  }
+
+  // Calls `visit` with this expression and every one in it, parents before
+  // their children and children in order. Analyses can match only the
+  // variants they care about and stay correct when new ones are added, e.g.
+  // expr.walk(|expr| if let Expr::Var(name, _) = expr { names.push(name) }).
+  // It doesn't recurse, so it works on arbitrarily deep trees.
+  pub fn walk<'a>(&'a self, mut visit: impl FnMut(&'a Expr)) {
+    let mut stack = vec![self];
+    while let Some(expr) = stack.pop() {
+      visit(expr);
+      stack.extend(expr.children().into_iter().rev());
+    }
+  }
+
+  // Like walk, for rewriting the tree in place. Children are visited after
+  // `visit` is done with their parent, so they're the ones it left there.
+  pub fn walk_mut(&mut self, mut visit: impl FnMut(&mut Expr)) {
+    let mut stack = vec![self];
+    while let Some(expr) = stack.pop() {
+      visit(expr);
+      stack.extend(expr.children_mut().into_iter().rev());
+    }
+  }
+
+  pub fn node_count(&self) -> usize {
+    let mut count = 0;
+    self.walk(|_| count += 1);
+    count
+  }
+}
+
+fn series_bound(bound: &Expr, env: &mut Env) -> Result<i64, RuntimeError> {
+  match bound.eval(env)? {
+    Int(num) => Ok(num),
+    val => Err(RuntimeError::new(RuntimeErrorKind::ExpectedInt(
+      val.type_name(),
+    ))),
+  }
+}
+
+fn add_terms(
+  kind: SeriesKind,
+  index: &Symbol,
+  (from, to): (i64, i64),
+  term: &Expr,
+  env: &mut Env,
+) -> EvalResult {
+  let mut total = match kind {
+    SeriesKind::Sum => Int(0),
+    SeriesKind::Product => Int(1),
+  };
+  for i in from..=to {
+    if let Some(frame) = env.frames.last_mut() {
+      frame.insert(index.clone(), Int(i));
+    }
+    let val = term.eval(env)?;
+    total = match kind {
+      SeriesKind::Sum => eval_addition(total, val)?,
+      SeriesKind::Product => eval_multiplication(total, val)?,
+    };
+  }
+  Ok(total)
+}
+
+// The index lives in a scope of its own, which sees the same variables as
+// the code around the series. An empty range gives the empty sum or
+// product, 0 or 1.
+fn eval_series(
+  kind: SeriesKind,
+  index: &Symbol,
+  from: &Expr,
+  to: &Expr,
+  term: &Expr,
+  env: &mut Env,
+) -> EvalResult {
+  let bounds = (series_bound(from, env)?, series_bound(to, env)?);
+  if env.frames.len() >= MAX_CALL_DEPTH {
+    return Err(RuntimeError::new(RuntimeErrorKind::TooMuchRecursion));
+  }
+  let scope = env.frames.last().cloned().unwrap_or_default();
+  env.frames.push(scope);
+  let result = add_terms(kind, index, bounds, term, env);
+  env.frames.pop();
+  result
+}
+
+// Without std there are no files to import
+#[cfg(not(feature = "std"))]
+fn eval_import(_: &str, span: Span, _: &mut Env) -> EvalResult {
+  Err(RuntimeError::new(RuntimeErrorKind::ImportsUnavailable).at(span))
+}
+
+// Runs like a script of its own, functions first, and gives its name
+fn eval_module(name: &str, body: &[Expr], env: &mut Env) -> EvalResult {
+  eval_all(body, env)?;
+  Ok(Str(name.into()))
+}
+
+fn eval_fn_declaration(function: &Rc<Function>, env: &mut Env) -> EvalResult {
+  let mut function = function.clone();
+  if function.source != env.source {
+    Rc::make_mut(&mut function).source = env.source.clone();
+  }
+  let val = Function(function.clone());
+  env.set(&function.name, val.clone());
+  Ok(val)
+}
+
+fn eval_args(args: &[Expr], env: &mut Env) -> Result<Vec<Value>, RuntimeError> {
+  args.iter().map(|arg| arg.eval(env)).collect()
+}
+
+fn check_call(function: &Function, args: &[Value], env: &Env) -> Result<(), RuntimeError> {
+  if args.len() != function.params.len() {
+    return Err(RuntimeError::new(RuntimeErrorKind::WrongArgumentCount(
+      function.name.clone(),
+      function.params.len(),
+      args.len(),
+    )));
+  }
+  if env.frames.len() >= MAX_CALL_DEPTH {
+    return Err(RuntimeError::new(RuntimeErrorKind::TooMuchRecursion));
+  }
+  Ok(())
+}
+
+pub fn call_function(function: &Function, args: Vec<Value>, env: &mut Env) -> EvalResult {
+  check_call(function, &args, env)?;
+  let frame = function.params.iter().cloned().zip(args).collect();
+  env.frames.push(frame);
+  let result = function.body.eval(env);
+  env.frames.pop();
+  result
+}
+
+fn eval_call(name: &str, args: &[Expr], span: Span, env: &mut Env) -> EvalResult {
+  eval_call_with(name, args.len(), span, env, |env| eval_args(args, env))
+}
+
+// Calls whatever the name refers to, with `eval_args` giving the values of
+// the `arg_count` arguments once they're needed
+pub(crate) fn eval_call_with(
+  name: &str,
+  arg_count: usize,
+  span: Span,
+  env: &mut Env,
+  eval_args: impl FnOnce(&mut Env) -> Result<Vec<Value>, RuntimeError>,
+) -> EvalResult {
+  match env.get(name) {
+    Some(Function(function)) => {
+      let args = eval_args(env)?;
+      // Problems with the call itself point at the call site, anything
+      // failing inside the body gets the call added to its trace
+      check_call(&function, &args, env).map_err(|err| err.at(span))?;
+      return call_function(&function, args, env)
+        .map_err(|err| err.called_from(name, &function.source, span));
+    }
+    Some(val) => {
+      return Err(
+        RuntimeError::new(RuntimeErrorKind::NotAFunction(
+          name.to_owned(),
+          val.type_name(),
+        ))
+        .at(span),
+      )
+    }
+    None => (),
+  }
+  let builtin = match builtins::lookup(name) {
+    Some(builtin) if env.builtin_allowed(name) => builtin,
+    Some(_) => {
+      return Err(RuntimeError::new(RuntimeErrorKind::DisabledBuiltin(name.to_owned())).at(span))
+    }
+    None => {
+      let suggestion = suggest(name, env.builtin_names().chain(env.function_names()));
+      return Err(
+        RuntimeError::new(RuntimeErrorKind::UndefinedFunction(
+          name.to_owned(),
+          suggestion,
+        ))
+        .at(span),
+      );
+    }
+  };
+  if !builtin.arity.accepts(arg_count) {
+    let kind = match builtin.arity {
+      Arity::Exact(arity) => {
+        RuntimeErrorKind::WrongArgumentCount(name.to_owned(), arity, arg_count)
+      }
+      Arity::AtLeast(arity) => RuntimeErrorKind::TooFewArguments(name.to_owned(), arity, arg_count),
+      Arity::Between(min, max) => {
+        RuntimeErrorKind::WrongArgumentRange(name.to_owned(), min, max, arg_count)
+      }
+    };
+    return Err(RuntimeError::new(kind).at(span));
+  }
+  let args = eval_args(env)?;
+  // Variables from a backend can change without the Env knowing
+  if !builtin.cached || env.backend.is_some() {
+    return (builtin.call)(&args, env).map_err(|err| err.at(span));
+  }
+  let key = cache_key(name, &args, env);
+  if let Some(val) = env.cache.get(&key) {
+    return Ok(val.clone());
+  }
+  let outer = core::mem::replace(&mut env.unrepeatable, false);
+  let result = (builtin.call)(&args, env).map_err(|err| err.at(span));
+  let unrepeatable = env.unrepeatable;
+  // A caller that's cached can't repeat what this couldn't either
+  env.unrepeatable = outer || unrepeatable;
+  let val = result?;
+  if !unrepeatable {
+    env.cache.insert(key, val.clone());
+  }
+  Ok(val)
+}
+
+// Everything a cached builtin's result depends on: the arguments, with
+// functions by their fingerprint so a redefined one doesn't match, the
+// settings builtins read and the version of the globals the functions can
+// read. Other arguments are written as source, "2" and "2.0" stay different
+// since they can give different results.
+fn cache_key(name: &str, args: &[Value], env: &Env) -> String {
+  let args = args
+    .iter()
+    .map(|arg| match arg {
+      Function(_) => format!("fn#{:016x}", arg.fingerprint()),
+      arg => arg.to_source(),
+    })
+    .collect::<Vec<String>>();
+  format!(
+    "{}({}) {} tolerance={} globals={}",
+    name,
+    args.join(", "),
+    env.angle_mode.name(),
+    env.tolerance,
+    env.globals_version
+  )
+}
+
+impl Expr {
+  pub fn eval(&self, env: &mut Env) -> EvalResult {
+    env.enter()?;
+    let result = self.eval_step(env);
+    env.leave();
+    result
+  }
+
+  fn eval_step(&self, env: &mut Env) -> EvalResult {
+    env.steps += 1;
+    let first_child = match &mut env.recording {
+      Some(recording) => recording.enter(),
+      None => return self.eval_node(env),
+    };
+    let result = self.eval_node(env);
+    if let Some(recording) = &mut env.recording {
+      recording.leave(self, first_child, &result);
+    }
+    result
+  }
+
+  fn eval_node(&self, env: &mut Env) -> EvalResult {
+    match self {
+      Expr::Literal(val, _) => Ok(val.clone()),
+      Expr::VarDeclaration(name, expr, _) => eval_var_declaration(name, expr, env),
+      Expr::Var(name, span) => eval_var(name, env).map_err(|err| err.at(*span)),
+      Expr::Print(vals, span) => eval_print(vals, env).map_err(|err| err.at(*span)),
+      Expr::Assert(cond, span) => eval_assert(cond, *span, env),
+      Expr::Binary(op, left, right, span) => {
+        eval_binary(*op, left, right, env).map_err(|err| err.at(*span))
+      }
+      Expr::Unary(op, val, span) => eval_unary(*op, val, env).map_err(|err| err.at(*span)),
+      Expr::Conditional(cond, val_if_true, val_if_false, _) => {
         eval_conditional(cond, val_if_true, val_if_false, env)
       }
+      Expr::Call(name, args, span) => eval_call(name, args, *span, env),
+      Expr::FnDeclaration(function, _) => eval_fn_declaration(function, env),
+      Expr::List(items, _) => Ok(List(Rc::new(eval_args(items, env)?))),
+      Expr::Interpolation(parts, span) => {
+        eval_interpolation(parts, env).map_err(|err| err.at(*span))
+      }
+      Expr::Series(kind, index, from, to, term, span) => {
+        eval_series(*kind, index, from, to, term, env).map_err(|err| err.at(*span))
+      }
+      Expr::Import(path, span) => eval_import(path, *span, env),
+      Expr::Module(name, body, _) => eval_module(name, body, env),
     }
   }
 }
 
 // EXPRESSION CONSTRUCTORS
 
+// For building trees in code, nothing made here has a span
+
 pub fn int(val: i64) -> Expr {
-  Expr::Literal(Value::Int(val))
+  Expr::Literal(Value::Int(val), NO_SPAN)
 }
 pub fn float(val: f64) -> Expr {
-  Expr::Literal(Value::Float(val))
-}
-pub fn negative(val: Expr) -> Expr {
-  Expr::Negative(Box::from(val))
+  Expr::Literal(Value::Float(val), NO_SPAN)
 }
-pub fn add(left: Expr, right: Expr) -> Expr {
-  Expr::Addition(Box::from(left), Box::from(right))
+pub fn unary(op: UnOp, val: Expr) -> Expr {
+  Expr::Unary(op, Box::from(val), NO_SPAN)
 }
-pub fn subtract(left: Expr, right: Expr) -> Expr {
-  Expr::Subtraction(Box::from(left), Box::from(right))
-}
-pub fn multiply(left: Expr, right: Expr) -> Expr {
-  Expr::Multiplication(Box::from(left), Box::from(right))
-}
-pub fn divide(left: Expr, right: Expr) -> Expr {
-  Expr::Division(Box::from(left), Box::from(right))
+pub fn binary(op: BinOp, left: Expr, right: Expr) -> Expr {
+  Expr::Binary(op, Box::from(left), Box::from(right), NO_SPAN)
 }
 pub fn boolean(val: bool) -> Expr {
-  Expr::Literal(Value::Bool(val))
-}
-pub fn equality(left: Expr, right: Expr) -> Expr {
-  Expr::Equality(Box::from(left), Box::from(right))
+  Expr::Literal(Value::Bool(val), NO_SPAN)
 }
 pub fn conditional(cond: Expr, val_if_true: Expr, val_if_false: Expr) -> Expr {
   Expr::Conditional(
     Box::from(cond),
     Box::from(val_if_true),
     Box::from(val_if_false),
+    NO_SPAN,
   )
 }
+
+pub fn var(name: &str) -> Expr {
+  Expr::Var(Symbol::new(name), NO_SPAN)
+}
+pub fn string(text: &str) -> Expr {
+  Expr::Literal(Value::Str(text.into()), NO_SPAN)
+}
+
+impl Expr {
+  // Like the call in the source, so "sum" with an index variable is a series
+  pub fn call(name: &str, args: impl IntoIterator<Item = Expr>) -> Expr {
+    crate::parser::call(name, args.into_iter().collect(), NO_SPAN)
+  }
+
+  pub fn list(items: impl IntoIterator<Item = Expr>) -> Expr {
+    Expr::List(items.into_iter().collect(), NO_SPAN)
+  }
+
+  // There's no operator for these
+  pub fn pow(self, exponent: impl Into<Expr>) -> Expr {
+    binary(BinOp::Pow, self, exponent.into())
+  }
+  pub fn floor_div(self, divisor: impl Into<Expr>) -> Expr {
+    binary(BinOp::FloorDiv, self, divisor.into())
+  }
+}
+
+impl From<i64> for Expr {
+  fn from(val: i64) -> Expr {
+    int(val)
+  }
+}
+impl From<f64> for Expr {
+  fn from(val: f64) -> Expr {
+    float(val)
+  }
+}
+impl From<bool> for Expr {
+  fn from(val: bool) -> Expr {
+    boolean(val)
+  }
+}
+impl From<&str> for Expr {
+  fn from(text: &str) -> Expr {
+    string(text)
+  }
+}
+
+// So var("x") + int(2) * var("y"), or var("x") + 2, builds the tree it reads
+// like. Precedence is Rust's, which agrees with ours for these operators.
+macro_rules! binary_operator {
+  ($trait:ident, $method:ident, $op:ident) => {
+    impl<T: Into<Expr>> ops::$trait<T> for Expr {
+      type Output = Expr;
+
+      fn $method(self, right: T) -> Expr {
+        binary(BinOp::$op, self, right.into())
+      }
+    }
+  };
+}
+
+binary_operator!(Add, add, Add);
+binary_operator!(Sub, sub, Sub);
+binary_operator!(Mul, mul, Mul);
+binary_operator!(Div, div, Div);
+binary_operator!(BitAnd, bitand, BitAnd);
+binary_operator!(BitOr, bitor, BitOr);
+binary_operator!(BitXor, bitxor, BitXor);
+binary_operator!(Shl, shl, ShiftLeft);
+binary_operator!(Shr, shr, ShiftRight);
+
+impl ops::Neg for Expr {
+  type Output = Expr;
+
+  fn neg(self) -> Expr {
+    unary(UnOp::Neg, self)
+  }
+}
+
+impl ops::Not for Expr {
+  type Output = Expr;
+
+  fn not(self) -> Expr {
+    unary(UnOp::Not, self)
+  }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+  use super::*;
+  use crate::error::Error;
+  use crate::program::eval_str;
+
+  // The stack the binary's main thread has, test threads get less
+  fn with_main_stack(test: impl FnOnce() + Send + 'static) {
+    let thread = std::thread::Builder::new().stack_size(8 << 20).spawn(test);
+    thread.unwrap().join().unwrap();
+  }
+
+  #[test]
+  fn deep_bodies_called_deeply_are_too_much_recursion() {
+    with_main_stack(|| {
+      let program = format!(
+        "fn f(n) = if n == 0 then 0 else 1 + {}f(n - 1)\nf(199)",
+        "-".repeat(900)
+      );
+      let err = eval_str(&program).unwrap_err();
+      assert!(matches!(err, Error::Runtime(err) if err.kind == RuntimeErrorKind::TooMuchRecursion));
+    });
+  }
+
+  #[test]
+  fn recursion_below_the_limits_runs() {
+    with_main_stack(|| {
+      let program = "fn f(n) = if n == 0 then 0 else 1 + f(n - 1)\nf(199)";
+      assert_eq!(eval_str(program), Ok(Int(199)));
+    });
+  }
+}
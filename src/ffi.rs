@@ -0,0 +1,205 @@
+// The rules for the pointers are the same for every function, they're below
+#![allow(clippy::missing_safety_doc)]
+
+use crate::expr::{Env, Value};
+use crate::program::eval_str_with_env;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_double, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+// Functions for embedding the evaluator in C and C++ programs, declared in
+// include/math_parser.h. After changing them, make the header again with
+// cbindgen --config cbindgen.toml --output include/math_parser.h
+//
+// Environments and results are owned by the caller and freed with their
+// _free function. Functions that fail return NULL (or NaN, or -1) and leave a
+// message for mp_last_error.
+//
+// Pointers are checked for NULL. Other than that they have to be what the
+// library gave out and not freed yet, strings end with a NUL.
+//
+// Panics can't unwind into C, every function catches them and fails like the
+// call went wrong.
+
+pub struct MpEnv(Env);
+
+pub struct MpResult(Value);
+
+thread_local! {
+  static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+  // Messages never have NULs in them, but just in case they're cut there
+  let message = CString::new(message).unwrap_or_else(|err| {
+    let end = err.nul_position();
+    CString::new(&err.into_vec()[..end]).unwrap_or_default()
+  });
+  LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+// What `call` returns, or `failed` after leaving the message of its panic
+fn guard<T>(failed: T, call: impl FnOnce() -> T) -> T {
+  catch_unwind(AssertUnwindSafe(call)).unwrap_or_else(|payload| {
+    let message = match (
+      payload.downcast_ref::<&str>(),
+      payload.downcast_ref::<String>(),
+    ) {
+      (Some(message), _) => message.to_string(),
+      (_, Some(message)) => message.clone(),
+      _ => "something went wrong".to_owned(),
+    };
+    set_last_error(format!("Sorry, the library panicked: {} :(", message));
+    failed
+  })
+}
+
+// The C string as UTF-8, or None after leaving an error
+unsafe fn text<'a>(text: *const c_char, what: &str) -> Option<&'a str> {
+  if text.is_null() {
+    set_last_error(format!("Hey, the {} is NULL", what));
+    return None;
+  }
+  match CStr::from_ptr(text).to_str() {
+    Ok(text) => Some(text),
+    Err(_) => {
+      set_last_error(format!("Hey, the {} isn't valid UTF-8", what));
+      None
+    }
+  }
+}
+
+#[no_mangle]
+pub extern "C" fn mp_env_new() -> *mut MpEnv {
+  guard(ptr::null_mut(), || {
+    Box::into_raw(Box::new(MpEnv(Env::new())))
+  })
+}
+
+// `env` has to come from mp_env_new, and can't be used after this
+#[no_mangle]
+pub unsafe extern "C" fn mp_env_free(env: *mut MpEnv) {
+  guard((), || {
+    if !env.is_null() {
+      drop(Box::from_raw(env));
+    }
+  })
+}
+
+// Returns 0, or -1 if something was NULL or the name isn't UTF-8
+#[no_mangle]
+pub unsafe extern "C" fn mp_env_set_double(
+  env: *mut MpEnv,
+  name: *const c_char,
+  val: c_double,
+) -> c_int {
+  guard(-1, || match (env.as_mut(), text(name, "variable name")) {
+    (Some(env), Some(name)) => {
+      env.0.set(name, Value::Float(val));
+      0
+    }
+    _ => -1,
+  })
+}
+
+// Evaluates a script and returns the value of its last statement, or NULL
+// if it didn't parse or failed. The error is rendered against the input.
+#[no_mangle]
+pub unsafe extern "C" fn mp_eval(env: *mut MpEnv, input: *const c_char) -> *mut MpResult {
+  guard(ptr::null_mut(), || {
+    let env = match env.as_mut() {
+      Some(env) => env,
+      None => {
+        set_last_error("Hey, the environment is NULL".to_owned());
+        return ptr::null_mut();
+      }
+    };
+    let input = match text(input, "input") {
+      Some(input) => input,
+      None => return ptr::null_mut(),
+    };
+    match eval_str_with_env(input, &mut env.0) {
+      Ok(val) => Box::into_raw(Box::new(MpResult(val))),
+      Err(err) => {
+        set_last_error(err.render(input));
+        ptr::null_mut()
+      }
+    }
+  })
+}
+
+// NaN if the result isn't a number, bools are 0 and 1
+#[no_mangle]
+pub unsafe extern "C" fn mp_result_as_double(result: *const MpResult) -> c_double {
+  guard(f64::NAN, || {
+    let result = match result.as_ref() {
+      Some(result) => result,
+      None => {
+        set_last_error("Hey, the result is NULL".to_owned());
+        return f64::NAN;
+      }
+    };
+    result.0.f64().unwrap_or_else(|err| {
+      set_last_error(err.message());
+      f64::NAN
+    })
+  })
+}
+
+// The result shown like in the REPL, freed with mp_string_free
+#[no_mangle]
+pub unsafe extern "C" fn mp_result_to_string(
+  env: *const MpEnv,
+  result: *const MpResult,
+) -> *mut c_char {
+  guard(ptr::null_mut(), || match (env.as_ref(), result.as_ref()) {
+    (Some(env), Some(result)) => match CString::new(env.0.display(&result.0)) {
+      Ok(text) => text.into_raw(),
+      Err(_) => {
+        set_last_error("Hey, the result has a NUL in it, so it can't be a C string".to_owned());
+        ptr::null_mut()
+      }
+    },
+    _ => {
+      set_last_error("Hey, the environment or the result is NULL".to_owned());
+      ptr::null_mut()
+    }
+  })
+}
+
+// `result` can't be used after this
+#[no_mangle]
+pub unsafe extern "C" fn mp_result_free(result: *mut MpResult) {
+  guard((), || {
+    if !result.is_null() {
+      drop(Box::from_raw(result));
+    }
+  })
+}
+
+// Only for strings this library returned
+#[no_mangle]
+pub unsafe extern "C" fn mp_string_free(text: *mut c_char) {
+  guard((), || {
+    if !text.is_null() {
+      drop(CString::from_raw(text));
+    }
+  })
+}
+
+// What the last failed call on this thread went wrong with, NULL if nothing
+// has failed yet. Owned by the library, it stays valid until the next call
+// fails.
+#[no_mangle]
+pub extern "C" fn mp_last_error() -> *const c_char {
+  guard(ptr::null(), || {
+    LAST_ERROR.with(|last| {
+      last
+        .borrow()
+        .as_ref()
+        .map_or(ptr::null(), |message| message.as_ptr())
+    })
+  })
+}
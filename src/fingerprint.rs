@@ -0,0 +1,242 @@
+use crate::error::NO_SPAN;
+use crate::expr::{Expr, Function, Value};
+use crate::prelude::*;
+
+// A structural hash of expressions that stays the same between runs, builds
+// and platforms, so it can be stored next to formulas to notice when they
+// change. Spans aren't part of it, "1+2" and "1 + 2" hash the same.
+//
+// Structural equality goes with it: structurally equal trees and values
+// have the same fingerprint.
+
+// 64-bit FNV-1a, simple and fully specified
+struct Hasher(u64);
+
+impl Hasher {
+  fn new() -> Self {
+    Hasher(0xcbf2_9ce4_8422_2325)
+  }
+
+  fn bytes(&mut self, bytes: &[u8]) {
+    for &byte in bytes {
+      self.0 ^= u64::from(byte);
+      self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+  }
+
+  fn u64(&mut self, num: u64) {
+    self.bytes(&num.to_le_bytes());
+  }
+
+  // Length first, so "ab" + "c" and "a" + "bc" differ
+  fn str(&mut self, text: &str) {
+    self.u64(text.len() as u64);
+    self.bytes(text.as_bytes());
+  }
+}
+
+impl Expr {
+  pub fn fingerprint(&self) -> u64 {
+    let mut hasher = Hasher::new();
+    // Nodes are hashed in pre-order. Every node says how many children follow
+    // it, so different trees can't produce the same sequence
+    let mut stack = vec![self];
+    while let Some(expr) = stack.pop() {
+      match expr {
+        Expr::Literal(val, _) => {
+          hasher.bytes(b"literal");
+          hash_value(&mut hasher, val, &mut stack);
+        }
+        Expr::VarDeclaration(name, ..) => {
+          hasher.bytes(b"let");
+          hasher.str(name);
+        }
+        Expr::Var(name, _) => {
+          hasher.bytes(b"var");
+          hasher.str(name);
+        }
+        Expr::Print(vals, _) => {
+          hasher.bytes(b"print");
+          // Printing a single value hashes like it did before print took
+          // several
+          if vals.len() != 1 {
+            hasher.u64(vals.len() as u64);
+          }
+        }
+        Expr::Assert(..) => hasher.bytes(b"assert"),
+        Expr::Binary(op, ..) => {
+          hasher.bytes(b"binary");
+          hasher.str(op.symbol());
+        }
+        Expr::Unary(op, _, _) => {
+          hasher.bytes(b"unary");
+          hasher.str(op.symbol());
+        }
+        Expr::Conditional(..) => hasher.bytes(b"if"),
+        Expr::Call(name, args, _) => {
+          hasher.bytes(b"call");
+          hasher.str(name);
+          hasher.u64(args.len() as u64);
+        }
+        Expr::Series(kind, index, ..) => {
+          hasher.bytes(b"series");
+          hasher.str(kind.name());
+          hasher.str(index);
+        }
+        Expr::List(items, _) => {
+          hasher.bytes(b"list");
+          hasher.u64(items.len() as u64);
+        }
+        Expr::Interpolation(parts, _) => {
+          hasher.bytes(b"interpolation");
+          hasher.u64(parts.len() as u64);
+        }
+        Expr::FnDeclaration(function, _) => {
+          hasher.bytes(b"fn");
+          hash_function(&mut hasher, function);
+        }
+        Expr::Import(path, _) => {
+          hasher.bytes(b"import");
+          hasher.str(path);
+        }
+        Expr::Module(name, body, _) => {
+          hasher.bytes(b"module");
+          hasher.str(name);
+          hasher.u64(body.len() as u64);
+        }
+      }
+      // Pushed in reverse so they pop in order
+      stack.extend(expr.children().into_iter().rev());
+    }
+    hasher.0
+  }
+}
+
+impl Value {
+  // The same as the fingerprint of a literal of the value, functions are
+  // hashed with their bodies
+  pub fn fingerprint(&self) -> u64 {
+    Expr::Literal(self.clone(), NO_SPAN).fingerprint()
+  }
+}
+
+fn hash_function(hasher: &mut Hasher, function: &Function) {
+  hasher.str(&function.name);
+  hasher.u64(function.params.len() as u64);
+  for param in &function.params {
+    hasher.str(param);
+  }
+}
+
+fn hash_value<'a>(hasher: &mut Hasher, val: &'a Value, stack: &mut Vec<&'a Expr>) {
+  match val {
+    Value::Int(num) => {
+      hasher.bytes(b"int");
+      hasher.u64(*num as u64);
+    }
+    Value::Float(num) => {
+      hasher.bytes(b"float");
+      // Every NaN is the same number as far as formulas are concerned
+      let bits = if num.is_nan() {
+        f64::NAN.to_bits()
+      } else {
+        num.to_bits()
+      };
+      hasher.u64(bits);
+    }
+    Value::Bool(b) => {
+      hasher.bytes(b"bool");
+      hasher.bytes(&[*b as u8]);
+    }
+    Value::Str(text) => {
+      hasher.bytes(b"str");
+      hasher.str(text);
+    }
+    Value::List(items) => {
+      hasher.bytes(b"list");
+      hasher.u64(items.len() as u64);
+      for item in items.iter() {
+        hash_value(hasher, item, stack);
+      }
+    }
+    Value::Function(function) => {
+      hasher.bytes(b"function");
+      hash_function(hasher, function);
+      // The body isn't one of the literal's children, so it's hashed next
+      stack.push(&function.body);
+    }
+  }
+}
+
+// Every NaN is the same number here too
+fn floats_equal(left: f64, right: f64) -> bool {
+  left.to_bits() == right.to_bits() || left.is_nan() && right.is_nan()
+}
+
+fn functions_equal(left: &Function, right: &Function) -> bool {
+  left.name == right.name
+    && left.params == right.params
+    && left.body.structurally_equal(&right.body)
+}
+
+impl Value {
+  // Like ==, except for spans in function bodies and NaN, which is equal to
+  // itself. 1 and 1.0 still differ.
+  pub fn structurally_equal(&self, other: &Value) -> bool {
+    match (self, other) {
+      (Value::Int(left), Value::Int(right)) => left == right,
+      (Value::Float(left), Value::Float(right)) => floats_equal(*left, *right),
+      (Value::Bool(left), Value::Bool(right)) => left == right,
+      (Value::Str(left), Value::Str(right)) => left == right,
+      (Value::List(left), Value::List(right)) => {
+        left.len() == right.len()
+          && left
+            .iter()
+            .zip(right.iter())
+            .all(|(left, right)| left.structurally_equal(right))
+      }
+      (Value::Function(left), Value::Function(right)) => functions_equal(left, right),
+      _ => false,
+    }
+  }
+}
+
+impl Expr {
+  // Whether both trees would print and evaluate the same, wherever in the
+  // source they came from. It doesn't recurse, so it works on arbitrarily
+  // deep trees.
+  pub fn structurally_equal(&self, other: &Expr) -> bool {
+    let mut stack = vec![(self, other)];
+    while let Some((left, right)) = stack.pop() {
+      // The nodes themselves, their children are compared after
+      let same_node = match (left, right) {
+        (Expr::Literal(left, _), Expr::Literal(right, _)) => left.structurally_equal(right),
+        (Expr::VarDeclaration(left, ..), Expr::VarDeclaration(right, ..)) => left == right,
+        (Expr::Var(left, _), Expr::Var(right, _)) => left == right,
+        (Expr::Binary(left, ..), Expr::Binary(right, ..)) => left == right,
+        (Expr::Unary(left, ..), Expr::Unary(right, ..)) => left == right,
+        (Expr::Call(left, ..), Expr::Call(right, ..)) => left == right,
+        (Expr::FnDeclaration(left, _), Expr::FnDeclaration(right, _)) => {
+          left.name == right.name && left.params == right.params
+        }
+        (Expr::Series(left, left_index, ..), Expr::Series(right, right_index, ..)) => {
+          left == right && left_index == right_index
+        }
+        (Expr::Import(left, _), Expr::Import(right, _)) => left == right,
+        (Expr::Module(left, ..), Expr::Module(right, ..)) => left == right,
+        (Expr::Print(..), Expr::Print(..))
+        | (Expr::Assert(..), Expr::Assert(..))
+        | (Expr::Conditional(..), Expr::Conditional(..))
+        | (Expr::List(..), Expr::List(..))
+        | (Expr::Interpolation(..), Expr::Interpolation(..)) => true,
+        _ => false,
+      };
+      let (left, right) = (left.children(), right.children());
+      if !same_node || left.len() != right.len() {
+        return false;
+      }
+      stack.extend(left.into_iter().zip(right));
+    }
+    true
+  }
+}
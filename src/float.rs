@@ -0,0 +1,82 @@
+// The float methods std has and core doesn't, for building without std.
+// They're named and behave like std's, so the code using them is the same
+// either way.
+pub trait FloatMath {
+  fn sqrt(self) -> f64;
+  fn powi(self, exponent: i32) -> f64;
+  fn powf(self, exponent: f64) -> f64;
+  fn exp(self) -> f64;
+  fn ln(self) -> f64;
+  fn sin(self) -> f64;
+  fn cos(self) -> f64;
+  fn tan(self) -> f64;
+  fn asin(self) -> f64;
+  fn acos(self) -> f64;
+  fn atan(self) -> f64;
+  fn floor(self) -> f64;
+  fn ceil(self) -> f64;
+  fn round(self) -> f64;
+  fn trunc(self) -> f64;
+}
+
+impl FloatMath for f64 {
+  fn sqrt(self) -> f64 {
+    libm::sqrt(self)
+  }
+
+  fn powi(self, exponent: i32) -> f64 {
+    libm::pow(self, exponent.into())
+  }
+
+  fn powf(self, exponent: f64) -> f64 {
+    libm::pow(self, exponent)
+  }
+
+  fn exp(self) -> f64 {
+    libm::exp(self)
+  }
+
+  fn ln(self) -> f64 {
+    libm::log(self)
+  }
+
+  fn sin(self) -> f64 {
+    libm::sin(self)
+  }
+
+  fn cos(self) -> f64 {
+    libm::cos(self)
+  }
+
+  fn tan(self) -> f64 {
+    libm::tan(self)
+  }
+
+  fn asin(self) -> f64 {
+    libm::asin(self)
+  }
+
+  fn acos(self) -> f64 {
+    libm::acos(self)
+  }
+
+  fn atan(self) -> f64 {
+    libm::atan(self)
+  }
+
+  fn floor(self) -> f64 {
+    libm::floor(self)
+  }
+
+  fn ceil(self) -> f64 {
+    libm::ceil(self)
+  }
+
+  fn round(self) -> f64 {
+    libm::round(self)
+  }
+
+  fn trunc(self) -> f64 {
+    libm::trunc(self)
+  }
+}
@@ -0,0 +1,89 @@
+use crate::error::{render_all, Error, RuntimeError, RuntimeErrorKind, Source, SourceFile, Span};
+use crate::expr::{Env, Value};
+use crate::parser::parse;
+use crate::program::eval_all;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// `import "lib/geometry.math"` runs another file in the same environment, so
+// scripts can share definitions. Paths are relative to the file with the
+// import in it, or to the working directory for code that isn't from a
+// file. A file only runs the first time it's imported, later imports of it
+// do nothing, so two files can both import what they need.
+
+#[derive(Debug, Clone, Default)]
+pub struct Imports {
+  // Files that ran to the end, by their canonical path
+  done: HashSet<PathBuf>,
+  // The files running right now, innermost last. Importing one of them
+  // again would never end.
+  running: Vec<PathBuf>,
+}
+
+impl Env {
+  // Where the program being run comes from, its imports are relative to it
+  pub fn set_script_path(&mut self, path: impl AsRef<Path>) {
+    let imports = self.imports();
+    imports.running.clear();
+    if let Ok(path) = path.as_ref().canonicalize() {
+      imports.running.push(path);
+    }
+  }
+}
+
+fn unreadable(path: &str, err: std::io::Error) -> RuntimeError {
+  RuntimeError::new(RuntimeErrorKind::ImportUnreadable(
+    path.to_owned(),
+    err.to_string(),
+  ))
+}
+
+fn failed(path: &str, errors: String) -> RuntimeError {
+  RuntimeError::new(RuntimeErrorKind::ImportFailed(path.to_owned(), errors))
+}
+
+// Gives the path as written, like print gives what it printed
+pub(crate) fn eval_import(path: &str, span: Span, env: &mut Env) -> Result<Value, RuntimeError> {
+  import(path, env)
+    .map(|_| Value::Str(path.into()))
+    .map_err(|err| err.at(span))
+}
+
+fn import(path: &str, env: &mut Env) -> Result<(), RuntimeError> {
+  if !env.imports_allowed() {
+    return Err(RuntimeError::new(RuntimeErrorKind::ImportsUnavailable));
+  }
+  let file = match env.imports().running.last().and_then(|file| file.parent()) {
+    Some(dir) => dir.join(path),
+    None => PathBuf::from(path),
+  };
+  let file = file.canonicalize().map_err(|err| unreadable(path, err))?;
+  let imports = env.imports();
+  if imports.running.contains(&file) {
+    return Err(RuntimeError::new(RuntimeErrorKind::ImportCycle(
+      path.to_owned(),
+    )));
+  }
+  if imports.done.contains(&file) {
+    return Ok(());
+  }
+  let source = std::fs::read_to_string(&file).map_err(|err| unreadable(path, err))?;
+  let exprs = parse(&source).map_err(|errors| failed(path, render_all(&errors, &source)))?;
+  env.imports().running.push(file);
+  // Errors in the functions it declares are shown in the file, wherever
+  // they're called from
+  let source = Rc::new(SourceFile {
+    path: path.to_owned(),
+    text: source,
+  });
+  let outer = core::mem::replace(&mut env.source, Source::File(source.clone()));
+  let result = eval_all(&exprs, env);
+  env.source = outer;
+  let imports = env.imports();
+  let file = imports.running.pop().unwrap();
+  result.map_err(|err| failed(path, Error::from(err).render(&source.text)))?;
+  // Only once it ran, a file that failed can be fixed and imported again
+  imports.done.insert(file);
+  Ok(())
+}
@@ -0,0 +1,33 @@
+use crate::error::JsonError;
+use crate::expr::{Env, Expr, Value};
+use crate::program::declare_functions;
+use serde::Deserialize;
+
+// Evaluates expression trees built somewhere else, e.g. in another language,
+// in the JSON form serde gives the AST. Spans in them don't point anywhere,
+// there's no source to point into.
+
+// Either a single expression or an array of them, like a parsed program
+pub fn parse_json(json: &str) -> Result<Vec<Expr>, JsonError> {
+  let invalid = |err: serde_json::Error| JsonError::Invalid(err.to_string());
+  let tree: serde_json::Value = serde_json::from_str(json).map_err(invalid)?;
+  if tree.is_array() {
+    Vec::deserialize(tree).map_err(invalid)
+  } else {
+    Expr::deserialize(tree)
+      .map(|expr| vec![expr])
+      .map_err(invalid)
+  }
+}
+
+// Runs the expressions like a script and returns what the last one gave,
+// None when there weren't any
+pub fn eval_json(json: &str, env: &mut Env) -> Result<Option<Value>, JsonError> {
+  let exprs = parse_json(json)?;
+  declare_functions(&exprs, env).map_err(JsonError::Runtime)?;
+  let mut last = None;
+  for expr in &exprs {
+    last = Some(expr.eval(env).map_err(JsonError::Runtime)?);
+  }
+  Ok(last)
+}
@@ -0,0 +1,224 @@
+use crate::expr::{BinOp, Expr, SeriesKind, UnOp, Value};
+use crate::lexer::quote;
+use crate::prelude::*;
+use crate::printer;
+
+// Renders expressions as LaTeX math, for pasting into papers and notes.
+// Parentheses follow the same rules as the printer, except that fractions,
+// roots and superscripts group their contents on their own.
+
+// Functions LaTeX has a command for, they're typeset upright
+const COMMANDS: &[&str] = &[
+  "sin", "cos", "tan", "exp", "ln", "log", "min", "max", "sinh", "cosh", "tanh",
+];
+
+const GREEK: &[&str] = &[
+  "alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta", "iota", "kappa", "lambda",
+  "mu", "nu", "xi", "pi", "rho", "sigma", "tau", "upsilon", "phi", "chi", "psi", "omega",
+];
+
+// Like printer::binding_power, with the things that group themselves
+fn binding_power(expr: &Expr) -> (u8, u8) {
+  match expr {
+    Expr::Binary(BinOp::Div, ..) | Expr::Binary(BinOp::FloorDiv, ..) => (u8::MAX, u8::MAX),
+    Expr::Unary(UnOp::Sqrt, _, _) => (u8::MAX, u8::MAX),
+    // Everything to the right of a sum would look like part of its term
+    Expr::Series(..) => (u8::MAX, 0),
+    expr => printer::binding_power(expr),
+  }
+}
+
+fn operand(expr: &Expr, parens: bool) -> String {
+  if parens {
+    format!("\\left({}\\right)", expr.to_latex())
+  } else {
+    expr.to_latex()
+  }
+}
+
+fn escape_text(text: &str) -> String {
+  let mut escaped = String::new();
+  for c in text.chars() {
+    match c {
+      '\\' => escaped.push_str("\\textbackslash{}"),
+      '~' => escaped.push_str("\\textasciitilde{}"),
+      '^' => escaped.push_str("\\textasciicircum{}"),
+      '{' | '}' | '$' | '&' | '#' | '_' | '%' => {
+        escaped.push('\\');
+        escaped.push(c);
+      }
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+// Single letters are variables as usual, longer names are set upright so
+// they don't read as a product of letters
+fn name(name: &str) -> String {
+  if GREEK.contains(&name) {
+    format!("\\{}", name)
+  } else if name.chars().count() == 1 {
+    name.to_owned()
+  } else {
+    format!("\\mathrm{{{}}}", escape_text(name))
+  }
+}
+
+fn items(items: &[Expr]) -> String {
+  let items: Vec<String> = items.iter().map(Expr::to_latex).collect();
+  items.join(", ")
+}
+
+fn value(val: &Value) -> String {
+  match val {
+    Value::Float(num) if num.is_nan() => "\\mathrm{NaN}".to_owned(),
+    Value::Float(num) if num.is_infinite() && *num > 0.0 => "\\infty".to_owned(),
+    Value::Float(num) if num.is_infinite() => "-\\infty".to_owned(),
+    Value::Int(_) | Value::Float(_) => val.to_string(),
+    Value::Bool(b) => format!("\\mathrm{{{}}}", b),
+    Value::Str(text) => format!("\\text{{{}}}", escape_text(text)),
+    Value::List(vals) => {
+      let vals: Vec<String> = vals.iter().map(value).collect();
+      format!("\\left[{}\\right]", vals.join(", "))
+    }
+    Value::Function(function) => name(&function.name),
+  }
+}
+
+fn call(function: &str, args: &[Expr]) -> String {
+  match (function, args) {
+    ("sqrt", [arg]) => format!("\\sqrt{{{}}}", arg.to_latex()),
+    ("abs", [arg]) => format!("\\left|{}\\right|", arg.to_latex()),
+    ("floor", [arg]) => format!("\\left\\lfloor {}\\right\\rfloor", arg.to_latex()),
+    ("ceil", [arg]) => format!("\\left\\lceil {}\\right\\rceil", arg.to_latex()),
+    (function, args) if COMMANDS.contains(&function) => {
+      format!("\\{}\\left({}\\right)", function, items(args))
+    }
+    (function, args) if function.chars().count() == 1 => {
+      format!("{}\\left({}\\right)", function, items(args))
+    }
+    (function, args) => format!(
+      "\\operatorname{{{}}}\\left({}\\right)",
+      escape_text(function),
+      items(args)
+    ),
+  }
+}
+
+fn binary(op: BinOp, left: &Expr, right: &Expr) -> String {
+  match op {
+    BinOp::Div => format!("\\frac{{{}}}{{{}}}", left.to_latex(), right.to_latex()),
+    BinOp::FloorDiv => format!(
+      "\\left\\lfloor \\frac{{{}}}{{{}}}\\right\\rfloor",
+      left.to_latex(),
+      right.to_latex()
+    ),
+    // The exponent is grouped by its braces
+    BinOp::Pow => {
+      let (left_bp, _) = op.binding_power();
+      format!(
+        "{}^{{{}}}",
+        operand(left, binding_power(left).1 <= left_bp),
+        right.to_latex()
+      )
+    }
+    op => {
+      let symbol = match op {
+        BinOp::Mul => "\\cdot",
+        BinOp::Equal => "=",
+        BinOp::NotEqual => "\\neq",
+        BinOp::LessEqual => "\\leq",
+        BinOp::GreaterEqual => "\\geq",
+        BinOp::BitAnd => "\\mathbin{\\&}",
+        BinOp::BitOr => "\\mathbin{|}",
+        BinOp::BitXor => "\\oplus",
+        BinOp::ShiftLeft => "\\ll",
+        BinOp::ShiftRight => "\\gg",
+        op => op.symbol(),
+      };
+      let (left_bp, right_bp) = op.binding_power();
+      format!(
+        "{} {} {}",
+        operand(left, binding_power(left).1 <= left_bp),
+        symbol,
+        operand(right, binding_power(right).0 < right_bp)
+      )
+    }
+  }
+}
+
+impl Expr {
+  pub fn to_latex(&self) -> String {
+    match self {
+      Expr::Literal(val, _) => value(val),
+      Expr::VarDeclaration(var, val, _) => format!("{} = {}", name(var), val.to_latex()),
+      Expr::Var(var, _) => name(var),
+      // Only the values are math
+      Expr::Print(vals, _) => items(vals),
+      Expr::Assert(cond, _) => cond.to_latex(),
+      Expr::Binary(op, left, right, _) => binary(*op, left, right),
+      Expr::Unary(UnOp::Sqrt, val, _) => format!("\\sqrt{{{}}}", val.to_latex()),
+      Expr::Unary(UnOp::Percent, val, _) => {
+        let bp = UnOp::Percent.binding_power();
+        format!("{}\\%", operand(val, binding_power(val).1 <= bp))
+      }
+      Expr::Unary(op, val, _) => {
+        let symbol = match op {
+          UnOp::Not => "\\neg ",
+          op => op.symbol(),
+        };
+        let parens = binding_power(val).0 < op.binding_power();
+        format!("{}{}", symbol, operand(val, parens))
+      }
+      Expr::Conditional(cond, val_if_true, val_if_false, _) => format!(
+        "\\begin{{cases}} {} & \\text{{if }} {} \\\\ {} & \\text{{otherwise}} \\end{{cases}}",
+        val_if_true.to_latex(),
+        cond.to_latex(),
+        val_if_false.to_latex()
+      ),
+      Expr::Call(function, args, _) => call(function, args),
+      Expr::Series(kind, index, from, to, term, _) => {
+        let symbol = match kind {
+          SeriesKind::Sum => "\\sum",
+          SeriesKind::Product => "\\prod",
+        };
+        // Terms with a + or - in them would only seem to take what's
+        // before it
+        let parens = binding_power(term).0 < BinOp::Mul.binding_power().0;
+        format!(
+          "{}_{{{} = {}}}^{{{}}} {}",
+          symbol,
+          name(index),
+          from.to_latex(),
+          to.to_latex(),
+          operand(term, parens)
+        )
+      }
+      Expr::List(vals, _) => format!("\\left[{}\\right]", items(vals)),
+      Expr::Interpolation(parts, _) => parts
+        .iter()
+        .map(|part| match part {
+          Expr::Literal(Value::Str(text), _) => format!("\\text{{{}}}", escape_text(text)),
+          part => part.to_latex(),
+        })
+        .collect(),
+      Expr::FnDeclaration(function, _) => {
+        let params: Vec<String> = function.params.iter().map(|param| name(param)).collect();
+        format!(
+          "{}\\left({}\\right) = {}",
+          name(&function.name),
+          params.join(", "),
+          function.body.to_latex()
+        )
+      }
+      Expr::Import(path, _) => format!("\\text{{import {}}}", escape_text(&quote(path))),
+      // One line for each definition, with the names as they're called
+      Expr::Module(module, body, _) => {
+        let mut lines = vec![format!("\\text{{module {}}}", escape_text(module))];
+        lines.extend(body.iter().map(Expr::to_latex));
+        lines.join(" \\\\ ")
+      }
+    }
+  }
+}
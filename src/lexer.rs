@@ -3,22 +3,56 @@ pub enum Token<'a> {
   Int(i64),
   Float(f64),
   Name(&'a str),
+  // The text between the quotes, with escapes still in it
+  Str(&'a str),
   LetKeyword,
   PrintKeyword,
+  AssertKeyword,
+  FnKeyword,
+  ImportKeyword,
+  ModuleKeyword,
   Plus,
   Minus,
   Star,
   Slash,
   LeftParen,
   RightParen,
+  Comma,
+  Semicolon,
   Equals,
-  EOF,
+  Eof,
   True,
   False,
   DoubleEquals,
+  NotEquals,
+  Less,
+  LessEquals,
+  Greater,
+  GreaterEquals,
+  Caret,
+  Sqrt,
+  // An exponent written like the ² in x², already parsed
+  Superscript(i64),
+  Percent,
   If,
   Then,
   Else,
+  Not,
+  Ampersand,
+  Pipe,
+  // "|>" and "..", lexed already for operators the grammar doesn't have
+  // yet, so they get a parse error instead of two tokens that mean
+  // something else
+  PipeRight,
+  DoubleDot,
+  Xor,
+  ShiftLeft,
+  ShiftRight,
+  DoubleSlash,
+  LeftBracket,
+  RightBracket,
+  LeftBrace,
+  RightBrace,
 }
 
 fn first(input: &str) -> char {
@@ -26,7 +60,7 @@ fn first(input: &str) -> char {
 }
 
 fn skip_char(input: &str) -> &str {
-  &input[1..]
+  &input[first(input).len_utf8()..]
 }
 
 fn eat_while(input: &str, cond: fn(&char) -> bool) -> (&str, &str) {
@@ -35,86 +69,557 @@ fn eat_while(input: &str, cond: fn(&char) -> bool) -> (&str, &str) {
 }
 
 fn skip_whitespace(input: &str) -> &str {
-  eat_while(&input, |c| c.is_ascii_whitespace()).0
+  eat_while(input, |c| c.is_ascii_whitespace()).0
 }
 
+// Underscores can separate digits, like in 1_000_000, but not come first
 fn eat_digits(input: &str) -> (&str, &str) {
-  eat_while(&input, |c| c.is_ascii_digit())
+  if !input.starts_with(|c: char| c.is_ascii_digit()) {
+    return (input, "");
+  }
+  eat_while(input, |c| c.is_ascii_digit() || *c == '_')
+}
+
+// The "e-9" in "1e-9". Without digits after it, the "e" isn't part of the
+// number, so "2e" still multiplies 2 by e
+fn eat_exponent(input: &str) -> Option<&str> {
+  let rest = input.strip_prefix(['e', 'E'])?;
+  let rest = rest.strip_prefix(['+', '-']).unwrap_or(rest);
+  let (rest, digits) = eat_digits(rest);
+  if digits.is_empty() {
+    None
+  } else {
+    Some(rest)
+  }
+}
+
+// Integers written like 0xFF, 0b1010 or 0o777
+fn radix_prefix(input: &str) -> Option<u32> {
+  let radix = match input.get(..2) {
+    Some("0x") => 16,
+    Some("0b") => 2,
+    Some("0o") => 8,
+    _ => return None,
+  };
+  // Otherwise it's a zero followed by a name, like "0x" meaning 0 * x
+  match input[2..].chars().next() {
+    Some(c) if c.is_digit(radix) => Some(radix),
+    _ => None,
+  }
+}
+
+fn eat_radix_number(input: &str, radix: u32) -> Result<(&str, Token<'_>), Err> {
+  // Letters and digits are all taken, so "0b102" is a mistake and not 0b10
+  // followed by 2
+  let (rest, digits) = eat_while(&input[2..], |c| c.is_ascii_alphanumeric() || *c == '_');
+  let len = input.len() - rest.len();
+  let digits = digits.replace('_', "");
+  if !digits.chars().all(|c| c.is_digit(radix)) {
+    return Err(Err::new(input, len, LexErrorKind::InvalidDigit));
+  }
+  let int = i64::from_str_radix(&digits, radix).map_err(|_| failed_to_parse_number(input, len))?;
+  Ok((rest, Token::Int(int)))
 }
 
-fn eat_number(input: &str) -> Result<(&str, Token), Err> {
-  let (rest, digits) = eat_digits(&input);
-  if rest.is_empty() || first(rest) != '.' {
+fn eat_number(input: &str) -> Result<(&str, Token<'_>), Err> {
+  if let Some(radix) = radix_prefix(input) {
+    return eat_radix_number(input, radix);
+  }
+  let (rest, digits) = eat_digits(input);
+  // "1..5" is 1, "..", 5 and not the float "1."
+  let (rest, fraction) = if rest.starts_with('.') && !rest.starts_with("..") {
+    (eat_digits(skip_char(rest)).0, true)
+  } else {
+    (rest, false)
+  };
+  let (rest, exponent) = match eat_exponent(rest) {
+    Some(rest) => (rest, true),
+    None => (rest, false),
+  };
+  if !fraction && !exponent {
     let int = digits
+      .replace('_', "")
       .parse::<i64>()
-      .map_err(|_| failed_to_parse_number(&input))?;
-    Ok((&rest, Token::Int(int)))
+      .map_err(|_| failed_to_parse_number(input, digits.len()))?;
+    return Ok((rest, Token::Int(int)));
+  }
+  let len = input.len() - rest.len();
+  // A huge exponent like "1e999" parses as infinity, which isn't a number
+  // anyone meant to write
+  let float = input[..len]
+    .replace('_', "")
+    .parse::<f64>()
+    .ok()
+    .filter(|float| float.is_finite())
+    .ok_or_else(|| failed_to_parse_number(input, len))?;
+  Ok((rest, Token::Float(float)))
+}
+
+// Whether a decimal literal like "12.375" or "1.5e3" can be stored in an f64
+// without rounding. That's the case when, written as a fraction in lowest
+// terms, its denominator is a power of two and its numerator fits in the
+// mantissa.
+fn is_exact_float(literal: &str) -> bool {
+  let literal = literal.replace('_', "");
+  let (number, exponent) = literal.split_once(['e', 'E']).unwrap_or((&literal, "0"));
+  let exponent = match exponent.parse::<i64>() {
+    Ok(exponent) => exponent,
+    Err(_) => return false,
+  };
+  let (int_part, fraction) = number.split_once('.').unwrap_or((number, ""));
+  let fraction = fraction.trim_end_matches('0');
+  let digits = format!("{}{}", int_part, fraction);
+  let digits = digits.trim_start_matches('0');
+  if digits.is_empty() {
+    return true;
+  }
+  let digits = match digits.parse::<u128>() {
+    Ok(digits) => digits,
+    Err(_) => return false,
+  };
+  // literal = digits * 10^-k = digits / (2^k * 5^k), where k is the number of
+  // fraction digits minus the exponent
+  let k = fraction.len() as i64 - exponent;
+  let five_to_k = match u32::try_from(k.unsigned_abs())
+    .ok()
+    .and_then(|k| 5u128.checked_pow(k))
+  {
+    Some(five_to_k) => five_to_k,
+    None => return false,
+  };
+  let mantissa = if k >= 0 {
+    if digits % five_to_k != 0 {
+      return false;
+    }
+    digits / five_to_k
   } else {
-    let rest = skip_char(&rest);
-    let (rest, _) = eat_digits(&rest);
-    let len = input.len() - rest.len();
-    let float = input[..len]
-      .parse::<f64>()
-      .map_err(|_| failed_to_parse_number(&input))?;
-    Ok((&rest, Token::Float(float)))
-  }
-}
-
-fn eat_word(input: &str) -> Result<(&str, Token), Err> {
-  let (rest, word) = eat_while(input, |c| c.is_ascii_alphabetic());
-  let token = match word {
-    "let" => Token::LetKeyword,
-    "print" => Token::PrintKeyword,
-    "true" => Token::True,
-    "false" => Token::False,
-    "if" => Token::If,
-    "then" => Token::Then,
-    "else" => Token::Else,
-    name => Token::Name(name),
+    // A positive power of ten, the power of two part only moves the
+    // exponent
+    match digits.checked_mul(five_to_k) {
+      Some(mantissa) => mantissa,
+      None => return false,
+    }
   };
-  Ok((rest, token))
+  mantissa >> mantissa.trailing_zeros() < 1 << f64::MANTISSA_DIGITS
 }
 
-fn eat_equals(input: &str) -> Result<(&str, Token), Err> {
-  let rest = skip_char(input);
-  if first(rest) == '=' {
-    Ok((skip_char(rest), Token::DoubleEquals))
-  } else {
-    Ok((rest, Token::Equals))
-  }
-}
-
-fn eat_token(input: &str) -> Result<(&str, Token), Err> {
-  let token = match first(input) {
-    '+' => Token::Plus,
-    '-' => Token::Minus,
-    '*' => Token::Star,
-    '/' => Token::Slash,
-    '(' => Token::LeftParen,
-    ')' => Token::RightParen,
-    '=' => return eat_equals(input),
-    c if c.is_ascii_alphabetic() => return eat_word(input),
-    c if c.is_ascii_digit() => return eat_number(input),
-    _ => return Err(unexpected_char(input)),
+// Every keyword of the language. The REPL completes and highlights them from
+// this table too, so new keywords only need to be added here
+pub const KEYWORDS: &[(&str, Token<'static>)] = &[
+  ("let", Token::LetKeyword),
+  ("print", Token::PrintKeyword),
+  ("assert", Token::AssertKeyword),
+  ("fn", Token::FnKeyword),
+  ("import", Token::ImportKeyword),
+  ("module", Token::ModuleKeyword),
+  ("if", Token::If),
+  ("then", Token::Then),
+  ("else", Token::Else),
+  ("true", Token::True),
+  ("false", Token::False),
+  ("not", Token::Not),
+  ("xor", Token::Xor),
+];
+
+pub fn keyword(word: &str) -> Option<Token<'static>> {
+  KEYWORDS
+    .iter()
+    .find(|(keyword, _)| *keyword == word)
+    .map(|&(_, token)| token)
+}
+
+// Names start with a letter and can go on with underscores, like
+// set_tolerance
+pub fn is_name_char(c: char) -> bool {
+  c.is_ascii_alphabetic() || c == '_'
+}
+
+// A name from a module is one name with dots in it, like geom.area. Only a
+// letter can follow a dot, so "x.5" is still a mistake.
+fn eat_word(input: &str) -> Result<(&str, Token<'_>), Err> {
+  let (mut rest, _) = eat_while(input, |&c| is_name_char(c));
+  while let Some(after) = rest.strip_prefix('.') {
+    if !after.starts_with(|c: char| c.is_ascii_alphabetic()) {
+      break;
+    }
+    rest = eat_while(after, |&c| is_name_char(c)).0;
+  }
+  let word = &input[..input.len() - rest.len()];
+  Ok((rest, keyword(word).unwrap_or(Token::Name(word))))
+}
+
+fn superscript_digit(c: char) -> Option<char> {
+  match c {
+    '⁰' => Some('0'),
+    '¹' => Some('1'),
+    '²' => Some('2'),
+    '³' => Some('3'),
+    '⁴' => Some('4'),
+    '⁵' => Some('5'),
+    '⁶' => Some('6'),
+    '⁷' => Some('7'),
+    '⁸' => Some('8'),
+    '⁹' => Some('9'),
+    _ => None,
+  }
+}
+
+fn is_superscript(c: char) -> bool {
+  c == '⁻' || superscript_digit(c).is_some()
+}
+
+// Reads exponents like "²" or "⁻¹"
+fn eat_superscript(input: &str) -> Result<(&str, Token<'_>), Err> {
+  let len = input
+    .chars()
+    .take_while(|&c| is_superscript(c))
+    .map(char::len_utf8)
+    .sum();
+  let (literal, rest) = input.split_at(len);
+  let digits = match literal.strip_prefix('⁻') {
+    Some(digits) => format!("-{}", digits),
+    None => literal.to_owned(),
   };
-  Ok((skip_char(input), token))
+  let digits = digits
+    .chars()
+    .map(|c| superscript_digit(c).unwrap_or(c))
+    .collect::<String>();
+  let exponent = digits
+    .parse::<i64>()
+    .map_err(|_| failed_to_parse_number(input, len))?;
+  Ok((rest, Token::Superscript(exponent)))
+}
+
+// Operators and punctuation. The longest one the input starts with wins, so
+// "<=" is never read as "<" followed by "="
+const OPERATORS: &[(&str, Token<'static>)] = &[
+  ("+", Token::Plus),
+  ("-", Token::Minus),
+  ("−", Token::Minus),
+  ("*", Token::Star),
+  ("×", Token::Star),
+  ("·", Token::Star),
+  ("⋅", Token::Star),
+  ("/", Token::Slash),
+  ("÷", Token::Slash),
+  ("∕", Token::Slash),
+  ("//", Token::DoubleSlash),
+  ("^", Token::Caret),
+  ("**", Token::Caret),
+  ("%", Token::Percent),
+  ("!", Token::Not),
+  ("&", Token::Ampersand),
+  ("|", Token::Pipe),
+  ("|>", Token::PipeRight),
+  ("..", Token::DoubleDot),
+  ("<<", Token::ShiftLeft),
+  (">>", Token::ShiftRight),
+  ("(", Token::LeftParen),
+  (")", Token::RightParen),
+  ("[", Token::LeftBracket),
+  ("]", Token::RightBracket),
+  ("{", Token::LeftBrace),
+  ("}", Token::RightBrace),
+  (",", Token::Comma),
+  (";", Token::Semicolon),
+  ("=", Token::Equals),
+  ("==", Token::DoubleEquals),
+  ("!=", Token::NotEquals),
+  ("≠", Token::NotEquals),
+  ("<", Token::Less),
+  ("<=", Token::LessEquals),
+  ("≤", Token::LessEquals),
+  ("⩽", Token::LessEquals),
+  (">", Token::Greater),
+  (">=", Token::GreaterEquals),
+  ("≥", Token::GreaterEquals),
+  ("⩾", Token::GreaterEquals),
+  ("√", Token::Sqrt),
+];
+
+fn eat_operator(input: &str) -> Option<(&str, Token<'_>)> {
+  OPERATORS
+    .iter()
+    .filter(|(operator, _)| input.starts_with(operator))
+    .max_by_key(|(operator, _)| operator.len())
+    .map(|&(operator, token)| (&input[operator.len()..], token))
+}
+
+// Escapes that can follow a backslash in a string, and what they stand for
+const ESCAPES: &[(char, char)] = &[('"', '"'), ('\\', '\\'), ('n', '\n'), ('t', '\t')];
+
+fn eat_string(input: &str) -> Result<(&str, Token<'_>), Err> {
+  let mut chars = input.char_indices().skip(1);
+  while let Some((i, c)) = chars.next() {
+    match c {
+      '"' => return Ok((&input[i + 1..], Token::Str(&input[1..i]))),
+      '\\' => match chars.next() {
+        Some((_, escaped)) if ESCAPES.iter().any(|&(c, _)| c == escaped) => (),
+        _ => return Err(Err::new(&input[i..], 1, LexErrorKind::InvalidEscape)),
+      },
+      _ => (),
+    }
+  }
+  Err(Err::new(input, 1, LexErrorKind::UnterminatedString))
 }
 
-pub type Tokens<'a> = Vec<(Token<'a>, usize)>;
+// Replaces the escapes in the text of a Str token
+pub fn unescape(raw: &str) -> String {
+  let mut text = String::with_capacity(raw.len());
+  let mut chars = raw.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => {
+        let escaped = chars.next().unwrap_or('\\');
+        let replacement = ESCAPES.iter().find(|&&(c, _)| c == escaped);
+        text.push(replacement.map_or(escaped, |&(_, replacement)| replacement));
+      }
+      c => text.push(c),
+    }
+  }
+  text
+}
+
+// The opposite of unescape, quotes included. Braces are doubled so they
+// don't start an interpolation
+pub fn quote(text: &str) -> String {
+  let mut quoted = String::with_capacity(text.len() + 2);
+  quoted.push('"');
+  for c in text.chars() {
+    match ESCAPES.iter().find(|&&(_, replacement)| replacement == c) {
+      Some(&(escape, _)) => {
+        quoted.push('\\');
+        quoted.push(escape);
+      }
+      None if c == '{' || c == '}' => {
+        quoted.push(c);
+        quoted.push(c);
+      }
+      None => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
 
-pub fn tokenize(input: &str) -> Result<Tokens, String> {
-  let mut result: Vec<(Token, usize)> = Vec::new();
-  let mut unprocessed = skip_whitespace(&input);
-  if unprocessed.is_empty() {
-    return Err("Didn't find any input. Give me something to parse next time!".to_owned());
+fn eat_token(input: &str) -> Result<(&str, Token<'_>), Err> {
+  match first(input) {
+    '"' => eat_string(input),
+    c if is_superscript(c) => eat_superscript(input),
+    c if c.is_ascii_alphabetic() => eat_word(input),
+    c if c.is_ascii_digit() => eat_number(input),
+    c => match eat_operator(input) {
+      Some(result) => Ok(result),
+      None if is_operator_char(c) => Err(unknown_operator(input)),
+      None => Err(unexpected_char(input)),
+    },
   }
+}
+
+pub type Tokens<'a> = Vec<(Token<'a>, Span)>;
+
+#[derive(Debug, Clone, Default)]
+pub struct LexOptions {
+  // Warn about float literals that can't be represented exactly
+  pub exact: bool,
+}
+
+pub fn tokenize(input: &str) -> Result<Tokens<'_>, LexError> {
+  tokenize_with(input, &LexOptions::default()).map(|(tokens, _)| tokens)
+}
+
+pub fn tokenize_with<'a>(
+  input: &'a str,
+  options: &LexOptions,
+) -> Result<(Tokens<'a>, Vec<Warning>), LexError> {
+  let mut lexer = Lexer::with_options(input, options);
+  let tokens = lexer.by_ref().collect::<Result<Tokens, LexError>>()?;
+  Ok((tokens, lexer.take_warnings()))
+}
+
+// Tokens one at a time as they're asked for, so big inputs don't have to be
+// held as tokens all at once. Like tokenize, the last one is Eof, and nothing
+// comes after an error.
+pub struct Lexer<'a> {
+  input: &'a str,
+  unprocessed: Option<&'a str>,
+  exact: bool,
+  warnings: Vec<Warning>,
+  finished: bool,
+}
+
+impl<'a> Lexer<'a> {
+  pub fn new(input: &'a str) -> Self {
+    Lexer::with_options(input, &LexOptions::default())
+  }
+
+  pub fn with_options(input: &'a str, options: &LexOptions) -> Self {
+    Lexer {
+      input,
+      unprocessed: None,
+      exact: options.exact,
+      warnings: Vec::new(),
+      finished: false,
+    }
+  }
+
+  // The warnings about the tokens so far, taking them out of the lexer
+  pub fn take_warnings(&mut self) -> Vec<Warning> {
+    core::mem::take(&mut self.warnings)
+  }
+
+  // The last thing it gives
+  fn finish(&mut self, last: LexResult<'a>) -> Option<LexResult<'a>> {
+    self.finished = true;
+    Some(last)
+  }
+}
+
+type LexResult<'a> = Result<(Token<'a>, Span), LexError>;
+
+impl<'a> Iterator for Lexer<'a> {
+  type Item = LexResult<'a>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.finished {
+      return None;
+    }
+    let input = self.input;
+    let unprocessed = match self.unprocessed {
+      Some(unprocessed) => unprocessed,
+      None => {
+        let unprocessed = skip_whitespace(input);
+        if unprocessed.is_empty() {
+          let err = LexError::new(LexErrorKind::EmptyInput, Span::new(0, 0));
+          return self.finish(Err(err));
+        }
+        unprocessed
+      }
+    };
+    if unprocessed.is_empty() {
+      let end = input.trim_end().len();
+      return self.finish(Ok((Token::Eof, Span::new(end, end))));
+    }
+    let (rest, token) = match eat_token(unprocessed) {
+      Ok(eaten) => eaten,
+      Err(err) => return self.finish(Err(err.locate(input))),
+    };
+    let span = Span::new(input.len() - unprocessed.len(), input.len() - rest.len());
+    if let Token::Float(_) = token {
+      if self.exact && !is_exact_float(&input[span.start..span.end]) {
+        self
+          .warnings
+          .push(Warning::new(WarningKind::InexactFloat, span));
+      }
+    }
+    self.unprocessed = Some(skip_whitespace(rest));
+    Some(Ok((token, span)))
+  }
+}
+
+// What a piece of source is, for highlighting it. The language has no
+// comments, so there's no class for them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TokenClass {
+  Keyword,
+  // Superscript exponents too
+  Number,
+  Str,
+  Identifier,
+  Operator,
+  // Parentheses, brackets, braces, commas and semicolons
+  Punctuation,
+  // What the lexer gave an error for
+  Invalid,
+}
+
+impl TokenClass {
+  pub fn name(self) -> &'static str {
+    match self {
+      TokenClass::Keyword => "keyword",
+      TokenClass::Number => "number",
+      TokenClass::Str => "string",
+      TokenClass::Identifier => "identifier",
+      TokenClass::Operator => "operator",
+      TokenClass::Punctuation => "punctuation",
+      TokenClass::Invalid => "invalid",
+    }
+  }
+}
+
+fn class(token: Token) -> TokenClass {
+  match token {
+    Token::Int(_) | Token::Float(_) | Token::Superscript(_) => TokenClass::Number,
+    Token::Str(_) => TokenClass::Str,
+    Token::Name(_) => TokenClass::Identifier,
+    Token::LeftParen
+    | Token::RightParen
+    | Token::LeftBracket
+    | Token::RightBracket
+    | Token::LeftBrace
+    | Token::RightBrace
+    | Token::Comma
+    | Token::Semicolon => TokenClass::Punctuation,
+    token if KEYWORDS.iter().any(|&(_, keyword)| keyword == token) => TokenClass::Keyword,
+    _ => TokenClass::Operator,
+  }
+}
+
+// A string with a bad escape in it goes on to its closing quote, or the end
+// of the line when it isn't closed
+fn skip_bad_string(input: &str) -> &str {
+  let mut chars = input.char_indices().skip(1);
+  while let Some((i, c)) = chars.next() {
+    match c {
+      '"' => return &input[i + 1..],
+      '\\' => {
+        chars.next();
+      }
+      '\n' => return &input[i..],
+      _ => (),
+    }
+  }
+  ""
+}
+
+// The class of every token in the input, for editors and the REPL to
+// highlight with. Unlike tokenize it goes on after errors, whatever the lexer
+// can't read is Invalid and the rest is classified like normal.
+pub fn classify(input: &str) -> Vec<(Span, TokenClass)> {
+  let mut classes = Vec::new();
+  let mut unprocessed = skip_whitespace(input);
   while !unprocessed.is_empty() {
-    let (rest, token) = eat_token(&unprocessed).map_err(|err| err.print(&input))?;
-    result.push((token, input.len() - unprocessed.len()));
-    unprocessed = skip_whitespace(&rest);
+    let (rest, class) = match eat_token(unprocessed) {
+      Ok((rest, token)) => (rest, class(token)),
+      Err(_) if unprocessed.starts_with('"') => (skip_bad_string(unprocessed), TokenClass::Invalid),
+      Err(err) => {
+        let err = err.locate(input);
+        let end = err
+          .span
+          .end
+          .max(err.span.start + first(unprocessed).len_utf8());
+        (&input[end..], TokenClass::Invalid)
+      }
+    };
+    let span = Span::new(input.len() - unprocessed.len(), input.len() - rest.len());
+    classes.push((span, class));
+    unprocessed = skip_whitespace(rest);
   }
-  result.push((Token::EOF, input.trim_end().len()));
-  Ok(result)
+  classes
+}
+
+// One line per token with its position, for debugging the grammar
+pub fn dump_tokens(input: &str, tokens: &Tokens) -> String {
+  tokens
+    .iter()
+    .map(|(token, span)| {
+      let (line, col) = line_col(input, span.start);
+      format!(
+        "{}:{}\t{}..{}\t{:?}\n",
+        line, col, span.start, span.end, token
+      )
+    })
+    .collect()
 }
 
 // -- Errors
@@ -122,32 +627,188 @@ pub fn tokenize(input: &str) -> Result<Tokens, String> {
 fn unexpected_char(remaining_input: &str) -> Err {
   Err::new(
     remaining_input,
-    "Sorry, I dont know what to do with this character :(".to_owned(),
+    first(remaining_input).len_utf8(),
+    LexErrorKind::UnexpectedChar,
   )
 }
 
-fn failed_to_parse_number(remaining_input: &str) -> Err {
-  Err::new(
-    remaining_input,
-    "Sorry, I couldn't construct this number :( Make sure that it's not too big!".to_owned(),
-  )
+// Symbols that could be part of an operator, so a run of them that isn't one
+// gets reported as a whole
+fn is_operator_char(c: char) -> bool {
+  c.is_ascii_punctuation() && !"()[]{},;\"'".contains(c)
+}
+
+fn unknown_operator(remaining_input: &str) -> Err {
+  let len = remaining_input
+    .chars()
+    .take_while(|&c| is_operator_char(c))
+    .count();
+  Err::new(remaining_input, len, LexErrorKind::UnknownOperator)
 }
 
+fn failed_to_parse_number(remaining_input: &str, len: usize) -> Err {
+  Err::new(remaining_input, len, LexErrorKind::InvalidNumber)
+}
+
+// An error positioned relative to the end of the input, which is all the
+// eat_* functions know about
 struct Err {
-  msg: String,
+  kind: LexErrorKind,
   rest_len: usize,
+  len: usize,
 }
 
-use crate::error::print_err;
+use crate::error::{line_col, LexError, LexErrorKind, Span, Warning, WarningKind};
+use crate::prelude::*;
+use core::convert::TryFrom;
 impl Err {
-  fn new(remaining_input: &str, msg: String) -> Self {
+  fn new(remaining_input: &str, len: usize, kind: LexErrorKind) -> Self {
     Err {
       rest_len: remaining_input.len(),
-      msg,
+      len,
+      kind,
     }
   }
-  fn print(&self, original_input: &str) -> String {
-    let pos = original_input.len() - self.rest_len;
-    print_err(&original_input, pos, &self.msg)
+  fn locate(&self, original_input: &str) -> LexError {
+    let start = original_input.len() - self.rest_len;
+    LexError::new(self.kind, Span::new(start, start + self.len))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn tokens(input: &str) -> Vec<Token<'_>> {
+    tokenize(input)
+      .unwrap()
+      .into_iter()
+      .map(|(token, _)| token)
+      .collect()
+  }
+
+  fn error(input: &str) -> LexError {
+    tokenize(input).unwrap_err()
+  }
+
+  #[test]
+  fn longest_operator_wins() {
+    let cases = [
+      ("<=", Token::LessEquals),
+      (">=", Token::GreaterEquals),
+      ("==", Token::DoubleEquals),
+      ("!=", Token::NotEquals),
+      ("//", Token::DoubleSlash),
+      ("**", Token::Caret),
+      ("<<", Token::ShiftLeft),
+      (">>", Token::ShiftRight),
+      ("|>", Token::PipeRight),
+      ("..", Token::DoubleDot),
+    ];
+    for (operator, token) in cases {
+      assert_eq!(tokens(operator), [token, Token::Eof], "{}", operator);
+    }
+  }
+
+  #[test]
+  fn operators_without_spaces() {
+    assert_eq!(
+      tokens("a<=b"),
+      [
+        Token::Name("a"),
+        Token::LessEquals,
+        Token::Name("b"),
+        Token::Eof
+      ]
+    );
+    assert_eq!(
+      tokens("x|>f"),
+      [
+        Token::Name("x"),
+        Token::PipeRight,
+        Token::Name("f"),
+        Token::Eof
+      ]
+    );
+    // Three of them are "//" and then "/"
+    assert_eq!(
+      tokens("6///2"),
+      [
+        Token::Int(6),
+        Token::DoubleSlash,
+        Token::Slash,
+        Token::Int(2),
+        Token::Eof
+      ]
+    );
+    assert_eq!(
+      tokens("a===b"),
+      [
+        Token::Name("a"),
+        Token::DoubleEquals,
+        Token::Equals,
+        Token::Name("b"),
+        Token::Eof
+      ]
+    );
+  }
+
+  #[test]
+  fn shorter_operators_still_lex() {
+    assert_eq!(
+      tokens("a | b < c"),
+      [
+        Token::Name("a"),
+        Token::Pipe,
+        Token::Name("b"),
+        Token::Less,
+        Token::Name("c"),
+        Token::Eof
+      ]
+    );
+    assert_eq!(
+      tokens("a = !b"),
+      [
+        Token::Name("a"),
+        Token::Equals,
+        Token::Not,
+        Token::Name("b"),
+        Token::Eof
+      ]
+    );
+  }
+
+  #[test]
+  fn double_dot_between_numbers() {
+    assert_eq!(
+      tokens("1..5"),
+      [Token::Int(1), Token::DoubleDot, Token::Int(5), Token::Eof]
+    );
+    assert_eq!(tokens("1.5"), [Token::Float(1.5), Token::Eof]);
+  }
+
+  #[test]
+  fn unknown_operator_covers_the_whole_run() {
+    let err = error("1 @@ 2");
+    assert_eq!(err.kind, LexErrorKind::UnknownOperator);
+    assert_eq!((err.span.start, err.span.end), (2, 4));
+    let err = error("a ~ b");
+    assert_eq!(err.kind, LexErrorKind::UnknownOperator);
+    assert_eq!((err.span.start, err.span.end), (2, 3));
+  }
+
+  #[test]
+  fn known_operator_before_an_unknown_one() {
+    // "<" is an operator, so only the "$" after it is unknown
+    let err = error("a <$ b");
+    assert_eq!(err.kind, LexErrorKind::UnknownOperator);
+    assert_eq!((err.span.start, err.span.end), (3, 4));
+  }
+
+  #[test]
+  fn unexpected_char_is_not_an_operator() {
+    let err = error("1 + \u{e9}");
+    assert_eq!(err.kind, LexErrorKind::UnexpectedChar);
+    assert_eq!((err.span.start, err.span.end), (4, 6));
   }
 }
@@ -0,0 +1,44 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod arena;
+pub mod batch;
+pub mod builtins;
+pub mod chunk;
+pub mod compile;
+pub mod derivative;
+pub mod error;
+pub mod expr;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fingerprint;
+#[cfg(not(feature = "std"))]
+mod float;
+#[cfg(feature = "std")]
+pub mod import;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod latex;
+pub mod lexer;
+pub mod lint;
+pub mod module;
+pub mod optimize;
+pub mod parser;
+pub mod plot;
+mod prelude;
+pub mod printer;
+pub mod program;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod recording;
+pub mod rng;
+pub mod sexpr;
+pub mod stdlib;
+pub mod symbol;
+pub mod typecheck;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use error::Error;
+pub use program::{eval_str, eval_str_with_env};
@@ -0,0 +1,389 @@
+use crate::error::{Error, Span, Warning, WarningKind};
+use crate::expr::{BinOp, Expr, Function, SeriesKind, Value};
+use crate::lexer::Token;
+use crate::optimize::optimize;
+use crate::prelude::*;
+use crate::symbol::Symbol;
+
+// Every kind of warning can be allowed, kept as a warning or turned into an
+// error, one lint at a time
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Lint {
+  InexactFloat,
+  UnusedVariable,
+  ShadowedVariable,
+  ConstantCondition,
+}
+
+pub const LINTS: &[Lint] = &[
+  Lint::InexactFloat,
+  Lint::UnusedVariable,
+  Lint::ShadowedVariable,
+  Lint::ConstantCondition,
+];
+
+impl Lint {
+  // The name used for the lint on the command line
+  pub fn name(self) -> &'static str {
+    match self {
+      Lint::InexactFloat => "inexact-float",
+      Lint::UnusedVariable => "unused-variable",
+      Lint::ShadowedVariable => "shadowed-variable",
+      Lint::ConstantCondition => "constant-condition",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Lint> {
+    LINTS.iter().copied().find(|lint| lint.name() == name)
+  }
+
+  fn default_level(self) -> Level {
+    match self {
+      // Most scripts don't care about the last digits of their floats
+      Lint::InexactFloat => Level::Allow,
+      Lint::UnusedVariable | Lint::ShadowedVariable | Lint::ConstantCondition => Level::Warn,
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Level {
+  Allow,
+  Warn,
+  Deny,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LintLevels {
+  // Lints that aren't here use their default level
+  levels: HashMap<Lint, Level>,
+  // Set by `--deny warnings`, every lint that would warn fails instead
+  warnings_are_errors: bool,
+}
+
+impl LintLevels {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn set(&mut self, lint: Lint, level: Level) {
+    self.levels.insert(lint, level);
+  }
+
+  // Changes every lint that would warn at once, like `--deny warnings`
+  pub fn set_warnings(&mut self, level: Level) {
+    match level {
+      Level::Allow => {
+        for &lint in LINTS {
+          self.set(lint, Level::Allow);
+        }
+      }
+      Level::Warn => self.warnings_are_errors = false,
+      Level::Deny => self.warnings_are_errors = true,
+    }
+  }
+
+  pub fn level(&self, lint: Lint) -> Level {
+    match self.levels.get(&lint).copied() {
+      Some(level) => level,
+      None => lint.default_level(),
+    }
+  }
+
+  fn effective_level(&self, lint: Lint) -> Level {
+    match self.level(lint) {
+      Level::Warn if self.warnings_are_errors => Level::Deny,
+      level => level,
+    }
+  }
+
+  pub fn enabled(&self, lint: Lint) -> bool {
+    self.level(lint) != Level::Allow
+  }
+
+  // Drops allowed warnings and fails with the denied ones
+  pub fn apply(&self, warnings: Vec<Warning>) -> Result<Vec<Warning>, Vec<Error>> {
+    let warnings = warnings
+      .into_iter()
+      .filter(|warning| self.enabled(warning.kind.lint()));
+    let (denied, warnings): (Vec<Warning>, Vec<Warning>) =
+      warnings.partition(|warning| self.effective_level(warning.kind.lint()) == Level::Deny);
+    if denied.is_empty() {
+      Ok(warnings)
+    } else {
+      Err(denied.into_iter().map(Error::Denied).collect())
+    }
+  }
+}
+
+// Names of every variable the program reads, calls included since they can
+// go through variables holding functions
+pub fn variable_reads<'a>(exprs: impl IntoIterator<Item = &'a Expr>) -> HashSet<&'a str> {
+  let mut reads = HashSet::new();
+  for expr in exprs {
+    expr.walk(|expr| match expr {
+      Expr::Var(name, _) => {
+        reads.insert(name.as_str());
+      }
+      Expr::Call(name, ..) => {
+        reads.insert(name.as_str());
+      }
+      _ => (),
+    });
+  }
+  reads
+}
+
+// Finds variables declared in the program that none of `reads` refers to.
+// Qualified ones are declared by modules for other files to read, they're
+// left out.
+pub fn unused_variables<'a>(
+  exprs: impl IntoIterator<Item = &'a Expr>,
+  reads: &HashSet<&str>,
+) -> Vec<Warning> {
+  let mut declarations: Vec<(&str, Span)> = Vec::new();
+  for expr in exprs {
+    expr.walk(|expr| {
+      if let Expr::VarDeclaration(name, _, span) = expr {
+        declarations.push((name, *span));
+      }
+    });
+  }
+  declarations.sort_by_key(|(_, span)| span.start);
+  declarations
+    .into_iter()
+    .filter(|(name, _)| !reads.contains(name) && !name.contains('.'))
+    .map(|(name, span)| Warning::new(WarningKind::UnusedVariable(name.to_owned()), span))
+    .collect()
+}
+
+// Parameters and series indices have no spans of their own, so they're found
+// in the tokens. Expressions are walked in the order
+// they're written, which makes each one the next match after the last.
+struct Locator<'t, 'a> {
+  tokens: &'t [(Token<'a>, Span)],
+  pos: usize,
+}
+
+impl<'t, 'a> Locator<'t, 'a> {
+  fn new(tokens: &'t [(Token<'a>, Span)]) -> Self {
+    Locator { tokens, pos: 0 }
+  }
+
+  // Index of the next token `starts` matches at, the search goes on after it
+  fn next(&mut self, starts: impl Fn(&[(Token, Span)]) -> bool) -> Option<usize> {
+    let start = (self.pos..self.tokens.len()).find(|&i| starts(&self.tokens[i..]))?;
+    self.pos = start + 1;
+    Some(start)
+  }
+
+  // The function's name in the next "fn name(", followed by its parameters.
+  // In a module the name is written without the module's in front.
+  fn function(&mut self, function: &Function) -> Vec<Span> {
+    let start = self.next(|tokens| match tokens {
+      [(Token::FnKeyword, _), (Token::Name(name), _), ..] => function
+        .name
+        .strip_suffix(name)
+        .is_some_and(|rest| rest.is_empty() || rest.ends_with('.')),
+      _ => false,
+    });
+    let header = start.map_or(&[][..], |start| &self.tokens[start..]);
+    header
+      .iter()
+      .take_while(|(token, _)| *token != Token::RightParen)
+      .filter(|(token, _)| matches!(token, Token::Name(_)))
+      .map(|(_, span)| *span)
+      .collect()
+  }
+
+  // The index in the next "sum(i,"
+  fn index(&mut self, kind: SeriesKind, index: &Symbol) -> Option<Span> {
+    let start = self.next(|tokens| match tokens {
+      [(Token::Name(name), _), (Token::LeftParen, _), (Token::Name(i), _), (Token::Comma, _), ..] => {
+        *name == kind.name() && index == i
+      }
+      _ => false,
+    })?;
+    Some(self.tokens[start + 2].1)
+  }
+}
+
+// Walks the program keeping track of the names every part of it can see
+struct Shadows<'e, 't, 'a> {
+  locator: Locator<'t, 'a>,
+  // Globals declared so far
+  globals: HashSet<&'e str>,
+  warnings: Vec<Warning>,
+}
+
+impl<'e> Shadows<'e, '_, '_> {
+  // Warns about `name` if anything in `scopes` or the globals has it
+  fn check(&mut self, name: &str, span: Option<Span>, scopes: &[Vec<&str>]) {
+    let hides = self.globals.contains(name) || scopes.iter().any(|scope| scope.contains(&name));
+    if let (true, Some(span)) = (hides, span) {
+      let kind = WarningKind::ShadowedVariable(name.to_owned());
+      self.warnings.push(Warning::new(kind, span));
+    }
+  }
+
+  // Declaring a name the innermost scope already has changes it instead,
+  // like a let at the top level does to a global
+  fn declare(&mut self, name: &'e str, span: Option<Span>, scopes: &mut [Vec<&'e str>]) {
+    match scopes.split_last_mut() {
+      Some((scope, _)) if scope.contains(&name) => (),
+      Some((scope, outer)) => {
+        self.check(name, span, outer);
+        scope.push(name);
+      }
+      None => {
+        self.globals.insert(name);
+      }
+    }
+  }
+
+  // `scopes` are the ones around the expression, innermost last
+  fn visit(&mut self, expr: &'e Expr, scopes: &mut Vec<Vec<&'e str>>) {
+    match expr {
+      Expr::VarDeclaration(name, val, span) => {
+        self.visit(val, scopes);
+        self.declare(name, Some(*span), scopes);
+      }
+      // A function's body only sees its parameters and the globals, not the
+      // variables around where it's declared
+      Expr::FnDeclaration(function, _) => {
+        let spans = self.locator.function(function);
+        self.declare(&function.name, spans.first().copied(), scopes);
+        for (i, param) in function.params.iter().enumerate() {
+          self.check(param, spans.get(i + 1).copied(), &[]);
+        }
+        let params = function.params.iter().map(Symbol::as_str).collect();
+        self.visit(&function.body, &mut vec![params]);
+      }
+      Expr::Series(kind, index, from, to, term, _) => {
+        let span = self.locator.index(*kind, index);
+        self.visit(from, scopes);
+        self.visit(to, scopes);
+        self.check(index, span, scopes);
+        scopes.push(vec![index.as_str()]);
+        self.visit(term, scopes);
+        scopes.pop();
+      }
+      _ => {
+        for child in expr.children() {
+          self.visit(child, scopes);
+        }
+      }
+    }
+  }
+}
+
+// Finds parameters, series indices and variables declared inside them that
+// hide a variable declared before them. Declaring a global again is how
+// globals are changed, so that's no warning.
+pub fn shadowed_variables<'a>(
+  exprs: impl IntoIterator<Item = &'a Expr>,
+  tokens: &[(Token, Span)],
+) -> Vec<Warning> {
+  let mut shadows = Shadows {
+    locator: Locator::new(tokens),
+    globals: HashSet::new(),
+    warnings: Vec::new(),
+  };
+  for expr in exprs {
+    shadows.visit(expr, &mut Vec::new());
+  }
+  shadows.warnings
+}
+
+// Whether two literals are equal, for the types where that can't depend on
+// the tolerance
+fn literals_equal(left: &Expr, right: &Expr) -> Option<bool> {
+  match (left, right) {
+    (Expr::Literal(Value::Int(a), _), Expr::Literal(Value::Int(b), _)) => Some(a == b),
+    (Expr::Literal(Value::Bool(a), _), Expr::Literal(Value::Bool(b), _)) => Some(a == b),
+    (Expr::Literal(Value::Str(a), _), Expr::Literal(Value::Str(b), _)) => Some(a == b),
+    _ => None,
+  }
+}
+
+// Replaces == and != of literals with what they give, returns whether any
+// were
+fn fold_equalities(expr: &mut Expr) -> bool {
+  let mut folded = false;
+  for child in expr.children_mut() {
+    folded |= fold_equalities(child);
+  }
+  if let Expr::Binary(op @ (BinOp::Equal | BinOp::NotEqual), left, right, span) = expr {
+    if let Some(equal) = literals_equal(left, right) {
+      *expr = Expr::Literal(Value::Bool(equal == (*op == BinOp::Equal)), *span);
+      folded = true;
+    }
+  }
+  folded
+}
+
+// What the condition always is, if it can be worked out without running
+// anything. Unlike optimize this folds == and != too, the tolerance and the
+// angle mode don't matter between ints, bools and strings.
+fn constant_condition(cond: &Expr) -> Option<bool> {
+  let mut cond = optimize(cond);
+  while fold_equalities(&mut cond) {
+    cond = optimize(&cond);
+  }
+  match cond {
+    Expr::Literal(Value::Bool(val), _) => Some(val),
+    _ => None,
+  }
+}
+
+// Finds conditions that fold to true or false, where one branch can never
+// be taken
+pub fn constant_conditions<'a>(exprs: impl IntoIterator<Item = &'a Expr>) -> Vec<Warning> {
+  let mut warnings = Vec::new();
+  for expr in exprs {
+    expr.walk(|expr| {
+      if let Expr::Conditional(cond, ..) = expr {
+        if let Some(val) = constant_condition(cond) {
+          warnings.push(Warning::new(
+            WarningKind::ConstantCondition(val),
+            cond.span(),
+          ));
+        }
+      }
+    });
+  }
+  warnings
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::parse;
+
+  fn constant(input: &str) -> Vec<bool> {
+    let exprs = parse(input).unwrap();
+    constant_conditions(&exprs)
+      .into_iter()
+      .map(|warning| match warning.kind {
+        WarningKind::ConstantCondition(val) => val,
+        kind => panic!("not a constant condition: {:?}", kind),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn equalities_of_literals_are_constant() {
+    assert_eq!(constant("if 1 == 1 then 2 else 3"), [true]);
+    assert_eq!(constant("if true != false then 2 else 3"), [true]);
+    assert_eq!(constant("if \"a\" == \"b\" then 2 else 3"), [false]);
+    assert_eq!(constant("if (1 + 1 == 2) == true then 2 else 3"), [true]);
+    assert_eq!(constant("if !(2 != 2) then 2 else 3"), [true]);
+  }
+
+  #[test]
+  fn equalities_that_can_depend_on_settings_are_not() {
+    assert!(constant("if 0.1 + 0.2 == 0.3 then 2 else 3").is_empty());
+    assert!(constant("if 1 == 1.0 then 2 else 3").is_empty());
+    assert!(constant("let x = 1\nif x == 1 then 2 else 3").is_empty());
+  }
+}
@@ -0,0 +1,247 @@
+use crate::analysis::{definition, diagnostics, hover, Severity};
+use math_parser::error::Span;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+// `math-parser lsp`, a language server for editors, speaking JSON-RPC over
+// stdin and stdout. Documents are sent whole on every change, and every
+// change publishes their diagnostics again.
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const PARSE_ERROR: i64 = -32700;
+
+// None at the end of the input, when the editor is gone
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+  let mut length = None;
+  loop {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+      return Ok(None);
+    }
+    let line = line.trim_end();
+    if line.is_empty() {
+      break;
+    }
+    if let Some((name, val)) = line.split_once(':') {
+      if name.eq_ignore_ascii_case("Content-Length") {
+        length = val.trim().parse::<usize>().ok();
+      }
+    }
+  }
+  let length = length.ok_or_else(|| {
+    io::Error::new(
+      io::ErrorKind::InvalidData,
+      "Hey, a message came without a Content-Length",
+    )
+  })?;
+  let mut body = vec![0; length];
+  input.read_exact(&mut body)?;
+  Ok(Some(body))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+  let body = message.to_string();
+  write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+  output.flush()
+}
+
+// Positions are lines and UTF-16 code units into the line, like JavaScript
+// strings count
+fn position(text: &str, offset: usize) -> Value {
+  let before = &text[..offset];
+  let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+  let line = before.matches('\n').count();
+  let character = before[line_start..].encode_utf16().count();
+  json!({ "line": line, "character": character })
+}
+
+fn range(text: &str, span: Span) -> Value {
+  json!({ "start": position(text, span.start), "end": position(text, span.end) })
+}
+
+// Past the end of a line is the end of the line, past the last line the end
+// of the text
+fn offset(text: &str, position: &Value) -> Option<usize> {
+  let line = position["line"].as_u64()? as usize;
+  let character = position["character"].as_u64()? as usize;
+  let line_start = match line {
+    0 => 0,
+    _ => match text.match_indices('\n').nth(line - 1) {
+      Some((i, _)) => i + 1,
+      None => return Some(text.len()),
+    },
+  };
+  let mut units = 0;
+  for (i, c) in text[line_start..].char_indices() {
+    if units >= character || c == '\n' {
+      return Some(line_start + i);
+    }
+    units += c.len_utf16();
+  }
+  Some(text.len())
+}
+
+struct Server {
+  // The text of every open document, by its URI
+  documents: HashMap<String, String>,
+  shut_down: bool,
+}
+
+impl Server {
+  fn publish_diagnostics(&self, uri: &str) -> Value {
+    let text = self.documents.get(uri).map_or("", String::as_str);
+    let diagnostics = diagnostics(text)
+      .into_iter()
+      .map(|diagnostic| {
+        let severity = match diagnostic.severity {
+          Severity::Error => 1,
+          Severity::Warning => 2,
+        };
+        json!({
+          "range": range(text, diagnostic.span),
+          "severity": severity,
+          "source": "math-parser",
+          "message": diagnostic.message,
+        })
+      })
+      .collect::<Vec<Value>>();
+    json!({
+      "jsonrpc": "2.0",
+      "method": "textDocument/publishDiagnostics",
+      "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+  }
+
+  // The document and the offset a hover or definition request is about
+  fn cursor<'a>(&'a self, params: &Value) -> Option<(&'a str, &'a str, usize)> {
+    let uri = params["textDocument"]["uri"].as_str()?;
+    let (uri, text) = self.documents.get_key_value(uri)?;
+    Some((uri, text, offset(text, &params["position"])?))
+  }
+
+  fn hover(&self, params: &Value) -> Value {
+    let (text, (span, contents)) = match self.cursor(params) {
+      Some((_, text, offset)) => match hover(text, offset) {
+        Some(hover) => (text, hover),
+        None => return Value::Null,
+      },
+      None => return Value::Null,
+    };
+    json!({
+      "contents": { "kind": "markdown", "value": format!("```\n{}\n```", contents) },
+      "range": range(text, span),
+    })
+  }
+
+  fn definition(&self, params: &Value) -> Value {
+    match self.cursor(params) {
+      Some((uri, text, offset)) => match definition(text, offset) {
+        Some(span) => json!({ "uri": uri, "range": range(text, span) }),
+        None => Value::Null,
+      },
+      None => Value::Null,
+    }
+  }
+
+  // The result of a request, or the code and message of an error
+  fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+    match method {
+      "initialize" => Ok(json!({
+        "capabilities": {
+          // Whole documents on every change
+          "textDocumentSync": 1,
+          "hoverProvider": true,
+          "definitionProvider": true,
+        },
+        "serverInfo": { "name": "math-parser", "version": env!("CARGO_PKG_VERSION") },
+      })),
+      "shutdown" => {
+        self.shut_down = true;
+        Ok(Value::Null)
+      }
+      "textDocument/hover" => Ok(self.hover(params)),
+      "textDocument/definition" => Ok(self.definition(params)),
+      _ => Err((
+        METHOD_NOT_FOUND,
+        format!("Hmm, I don't know the {} method", method),
+      )),
+    }
+  }
+
+  // The messages to send back, diagnostics for documents that changed
+  fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+    let uri = match params["textDocument"]["uri"].as_str() {
+      Some(uri) => uri.to_owned(),
+      None => return Vec::new(),
+    };
+    let text = match method {
+      "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+      "textDocument/didChange" => params["contentChanges"]
+        .as_array()
+        .and_then(|changes| changes.last())
+        .and_then(|change| change["text"].as_str()),
+      "textDocument/didClose" => {
+        self.documents.remove(&uri);
+        // Clears what was shown for the document
+        return vec![self.publish_diagnostics(&uri)];
+      }
+      _ => None,
+    };
+    match text {
+      Some(text) => {
+        self.documents.insert(uri.clone(), text.to_owned());
+        vec![self.publish_diagnostics(&uri)]
+      }
+      None => Vec::new(),
+    }
+  }
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+  json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+// Serves until the editor says to exit or closes stdin. Exiting without
+// being shut down first is an error, like the protocol says.
+pub fn serve() -> Result<(), String> {
+  let stdin = io::stdin();
+  let mut input = stdin.lock();
+  let stdout = io::stdout();
+  let mut output = stdout.lock();
+  let mut server = Server {
+    documents: HashMap::new(),
+    shut_down: false,
+  };
+  let failed = |err: io::Error| format!("The editor connection broke: {} :(", err);
+  while let Some(body) = read_message(&mut input).map_err(failed)? {
+    let message = match serde_json::from_slice::<Value>(&body) {
+      Ok(message) => message,
+      Err(err) => {
+        let message = format!("Hey, that isn't JSON: {}", err);
+        write_message(&mut output, &error(Value::Null, PARSE_ERROR, &message)).map_err(failed)?;
+        continue;
+      }
+    };
+    let method = message["method"].as_str().unwrap_or_default();
+    let params = &message["params"];
+    if method == "exit" {
+      return match server.shut_down {
+        true => Ok(()),
+        false => Err("Hey, the editor told me to exit without shutting down first".to_owned()),
+      };
+    }
+    let replies = match message.get("id") {
+      Some(id) if !method.is_empty() => match server.request(method, params) {
+        Ok(result) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+        Err((code, message)) => vec![error(id.clone(), code, &message)],
+      },
+      // Answers have an id too, but the server never asks the editor anything
+      _ => server.notification(method, params),
+    };
+    for reply in replies {
+      write_message(&mut output, &reply).map_err(failed)?;
+    }
+  }
+  Ok(())
+}
@@ -1,70 +1,621 @@
-mod error;
-mod expr;
-mod lexer;
-mod parser;
-use expr::Env;
-use parser::parse;
-use std::io::{self, Write};
-
-fn interact(env: &mut Env) -> io::Result<()> {
-    print!("math> ");
-    io::stdout().flush()?;
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    match parse(&input) {
-        Ok(exprs) => {
-            for expr in exprs {
-                println!("{}", expr.eval(env))
+#[cfg(feature = "json")]
+mod analysis;
+mod bench;
+mod cli;
+mod completion;
+#[cfg(feature = "json")]
+mod lsp;
+mod repl;
+#[cfg(feature = "json")]
+mod report;
+mod session;
+mod tester;
+mod timing;
+use cli::{parse_args, usage, Args, AstFormat, Command, Format};
+use math_parser::chunk;
+#[cfg(feature = "json")]
+use math_parser::error::JsonError;
+use math_parser::error::Warning;
+use math_parser::error::{render_all_with, RenderOptions};
+use math_parser::expr::{Env, EnvBuilder, Expr, Value};
+use math_parser::lexer::{dump_tokens, tokenize};
+use math_parser::lint::{unused_variables, variable_reads, Level, Lint};
+use math_parser::parser::{parse, parse_statements, parse_with, ParseOptions, Statement};
+use math_parser::printer::format_script;
+use math_parser::program::{declare_functions, eval_all_with_progress};
+use math_parser::Error;
+use repl::Repl;
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use timing::Timings;
+
+// How often to report progress of long-running scripts
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+// What went wrong decides the exit code, so shell scripts and Makefiles can
+// tell failures apart
+enum Failure {
+    Usage(String),
+    Io(String),
+    // Syntax errors and denied lints, nothing has been evaluated yet
+    Parse(String),
+    Runtime(String),
+    // fmt --check found files that aren't formatted
+    Unformatted(String),
+    // lint found warnings
+    Warned(String),
+}
+
+impl Failure {
+    fn from_errors(errors: &[Error], rendered: String) -> Failure {
+        if errors.iter().any(|err| matches!(err, Error::Runtime(_))) {
+            Failure::Runtime(rendered)
+        } else {
+            Failure::Parse(rendered)
+        }
+    }
+
+    fn exit_code(&self) -> u8 {
+        match self {
+            Failure::Usage(_) => 2,
+            Failure::Io(_) => 3,
+            Failure::Parse(_) => 4,
+            Failure::Runtime(_) => 5,
+            Failure::Unformatted(_) | Failure::Warned(_) => 1,
+        }
+    }
+
+    // With --json, failures that aren't about a script become records too,
+    // the others already are
+    fn in_format(self, format: Format) -> Failure {
+        match format {
+            #[cfg(feature = "json")]
+            Format::Json => match self {
+                Failure::Io(message) => Failure::Io(report::failure("io", &message)),
+                failure => failure,
+            },
+            Format::Human => self,
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Failure::Usage(message)
+            | Failure::Io(message)
+            | Failure::Parse(message)
+            | Failure::Runtime(message)
+            | Failure::Unformatted(message)
+            | Failure::Warned(message) => message,
+        }
+    }
+}
+
+// A program to run, with the name it's reported under
+struct Script {
+    name: String,
+    // The file it was read from, its imports are relative to it
+    path: Option<String>,
+    input: String,
+    // Already parsed when the script was compiled, the input is only there
+    // for error messages then
+    compiled: Option<Vec<Expr>>,
+}
+
+// Diagnostics only need to say which script they come from when there's
+// more than one
+fn in_script(script: Option<&str>, text: String) -> String {
+    match script {
+        Some(script) => format!("In {}:\n{}", script, text),
+        None => text,
+    }
+}
+
+fn render_errors(format: Format, errors: &[Error], input: &str, script: Option<&str>) -> String {
+    match format {
+        Format::Human => in_script(
+            script,
+            render_all_with(errors, input, &RenderOptions::terminal()),
+        ),
+        #[cfg(feature = "json")]
+        Format::Json => report::errors(errors, input, script),
+    }
+}
+
+fn render_warning(format: Format, warning: &Warning, input: &str, script: Option<&str>) -> String {
+    match format {
+        Format::Human => in_script(
+            script,
+            warning.render_with(input, &RenderOptions::terminal()),
+        ),
+        #[cfg(feature = "json")]
+        Format::Json => report::warning(warning, input, script),
+    }
+}
+
+fn render_result(format: Format, val: &Value, env: &Env) -> String {
+    match format {
+        Format::Human => env.display(val),
+        #[cfg(feature = "json")]
+        Format::Json => report::result(val, env),
+    }
+}
+
+// With --json, what print statements print becomes records too
+fn program_env(env: EnvBuilder, format: Format) -> Env {
+    match format {
+        Format::Human => env.build(),
+        #[cfg(feature = "json")]
+        Format::Json => {
+            let mut env = env.build();
+            env.set_output(report::PrintRecords::default());
+            env
+        }
+    }
+}
+
+// Runs the scripts one after another in a single environment, so later ones
+// can use what earlier ones defined
+// With `time`, how long every statement took is reported after each script
+fn run_scripts(
+    scripts: &[Script],
+    options: &ParseOptions,
+    mut env: Env,
+    time: bool,
+    format: Format,
+) -> Result<(), Failure> {
+    let name = |script: &Script| match scripts.len() {
+        1 => None,
+        _ => Some(script.name.clone()),
+    };
+    let render = |script: &Script, errors: &[Error]| {
+        let rendered = render_errors(format, errors, &script.input, name(script).as_deref());
+        Failure::from_errors(errors, rendered)
+    };
+    // Variables can be used by any of the scripts, so unused ones are only
+    // looked for once all of them are parsed
+    let mut parse_options = options.clone();
+    parse_options.lints.set(Lint::UnusedVariable, Level::Allow);
+    let mut programs = Vec::new();
+    for script in scripts {
+        let (exprs, warnings) = match &script.compiled {
+            Some(exprs) => (exprs.clone(), Vec::new()),
+            None => parse_with(&script.input, &parse_options)
+                .map_err(|errors| render(script, &errors))?,
+        };
+        programs.push((script, exprs, warnings));
+    }
+    let reads = variable_reads(programs.iter().flat_map(|(_, exprs, _)| exprs));
+    for (script, exprs, warnings) in &programs {
+        let mut warnings = warnings.clone();
+        if options.lints.enabled(Lint::UnusedVariable) {
+            warnings.extend(unused_variables(exprs, &reads));
+            warnings.sort_by_key(|warning| warning.span.start);
+        }
+        let warnings = options
+            .lints
+            .apply(warnings)
+            .map_err(|errors| render(script, &errors))?;
+        for warning in warnings {
+            let name = name(script);
+            println!(
+                "{}",
+                render_warning(format, &warning, &script.input, name.as_deref())
+            );
+        }
+        if let Some(path) = &script.path {
+            env.set_script_path(path);
+        }
+        if time {
+            let mut timings = Timings::new();
+            declare_functions(exprs, &mut env)
+                .and_then(|_| {
+                    exprs
+                        .iter()
+                        .try_for_each(|expr| timings.eval(expr, &mut env).map(drop))
+                })
+                .map_err(|err| render(script, &[err.into()]))?;
+            eprintln!(
+                "{}",
+                in_script(name(script).as_deref(), timings.to_string())
+            );
+            continue;
+        }
+        eval_all_with_progress(exprs, &mut env, PROGRESS_INTERVAL, |progress| {
+            eprintln!(
+                "Still working... expression {} of {}, {:.1}s elapsed",
+                progress.index + 1,
+                progress.total,
+                progress.elapsed.as_secs_f64()
+            )
+        })
+        .map_err(|err| render(script, &[err.into()]))?;
+    }
+    Ok(())
+}
+
+fn run_files(
+    paths: &[&str],
+    options: &ParseOptions,
+    env: Env,
+    time: bool,
+    format: Format,
+) -> Result<(), Failure> {
+    let scripts = paths
+        .iter()
+        .map(|&path| read_script(path))
+        .collect::<Result<Vec<Script>, Failure>>()?;
+    run_scripts(&scripts, options, env, time, format)
+}
+
+// Compiled scripts are told apart by their first bytes, whatever they're
+// called
+fn read_script(path: &str) -> Result<Script, Failure> {
+    let bytes = read_bytes(path).map_err(Failure::Io)?;
+    if chunk::is_chunk(&bytes) {
+        let chunk =
+            chunk::load(&bytes).map_err(|err| Failure::Io(format!("In {}:\n{}", path, err)))?;
+        return Ok(Script {
+            name: path.to_owned(),
+            path: Some(path.to_owned()),
+            input: chunk.source,
+            compiled: Some(chunk.exprs),
+        });
+    }
+    Ok(Script {
+        name: path.to_owned(),
+        path: Some(path.to_owned()),
+        input: utf8(bytes).map_err(Failure::Io)?,
+        compiled: None,
+    })
+}
+
+fn compile_file(path: &str, output: Option<&str>, options: &ParseOptions) -> Result<(), Failure> {
+    let input = read_file(path).map_err(Failure::Io)?;
+    let (exprs, warnings) = parse_with(&input, options).map_err(|errors| {
+        Failure::Parse(render_all_with(&errors, &input, &RenderOptions::terminal()))
+    })?;
+    for warning in warnings {
+        println!(
+            "{}",
+            warning.render_with(&input, &RenderOptions::terminal())
+        );
+    }
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(path).with_extension(chunk::EXTENSION),
+    };
+    std::fs::write(&output, chunk::compile(&input, &exprs)).map_err(|_| {
+        Failure::Io(format!(
+            "I couldn't write the compiled program to {} :(",
+            output.display()
+        ))
+    })
+}
+
+// Rewrites files that aren't in the canonical layout, leaving the others
+// untouched. With `check` nothing is written, the files that would change
+// are listed and make it fail.
+fn fmt_files(paths: &[String], check: bool) -> Result<(), Failure> {
+    let mut unformatted = Vec::new();
+    for path in paths {
+        let input = read_file(path).map_err(Failure::Io)?;
+        let formatted = format_script(&input).map_err(|errors| {
+            Failure::Parse(format!(
+                "In {}:\n{}",
+                path,
+                render_all_with(&errors, &input, &RenderOptions::terminal())
+            ))
+        })?;
+        if formatted == input {
+            continue;
+        }
+        if check {
+            unformatted.push(path.as_str());
+            continue;
+        }
+        std::fs::write(path, formatted).map_err(|_| {
+            Failure::Io(format!(
+                "I couldn't write the formatted program to {} :(",
+                path
+            ))
+        })?;
+    }
+    match unformatted.is_empty() {
+        true => Ok(()),
+        false => Err(Failure::Unformatted(format!(
+            "Hmm, these files aren't formatted, fmt would change them:\n{}",
+            unformatted.join("\n")
+        ))),
+    }
+}
+
+// Every file is checked on its own, a variable only another file reads
+// counts as unused
+fn lint_files(paths: &[String], options: &ParseOptions) -> Result<(), Failure> {
+    let mut count = 0;
+    for path in paths {
+        let input = read_file(path).map_err(Failure::Io)?;
+        let script = Some(path.as_str()).filter(|_| paths.len() > 1);
+        let (_, warnings) = parse_with(&input, options).map_err(|errors| {
+            Failure::from_errors(
+                &errors,
+                in_script(
+                    script,
+                    render_all_with(&errors, &input, &RenderOptions::terminal()),
+                ),
+            )
+        })?;
+        for warning in &warnings {
+            println!(
+                "{}",
+                in_script(
+                    script,
+                    warning.render_with(&input, &RenderOptions::terminal())
+                )
+            );
+        }
+        count += warnings.len();
+    }
+    match count {
+        0 => Ok(()),
+        1 => Err(Failure::Warned("Hmm, I found a warning".to_owned())),
+        _ => Err(Failure::Warned(format!("Hmm, I found {} warnings", count))),
+    }
+}
+
+fn fmt_stdin(check: bool) -> Result<(), Failure> {
+    let input = read_stdin().map_err(Failure::Io)?;
+    let formatted = format_script(&input).map_err(|errors| {
+        Failure::Parse(render_all_with(&errors, &input, &RenderOptions::terminal()))
+    })?;
+    if !check {
+        print!("{}", formatted);
+    } else if formatted != input {
+        return Err(Failure::Unformatted(
+            "Hmm, that isn't formatted, fmt would change it".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+// Evaluates code given on the command line, printing every result like the
+// REPL does
+fn eval_arg(
+    input: &str,
+    options: &ParseOptions,
+    mut env: Env,
+    time: bool,
+    format: Format,
+) -> Result<(), Failure> {
+    let run = |env: &mut Env| -> Result<(), Vec<Error>> {
+        let (statements, _) = parse_statements(input, options)?;
+        let exprs = statements.iter().map(|statement| &statement.expr);
+        declare_functions(exprs, env).map_err(|err| vec![err.into()])?;
+        let mut timings = Timings::new();
+        for Statement {
+            expr, terminated, ..
+        } in statements
+        {
+            let val = timings.eval(&expr, env).map_err(|err| vec![err.into()])?;
+            if !terminated {
+                println!("{}", render_result(format, &val, env));
             }
         }
-        Err(err) => println!("{}", err),
+        if time {
+            eprintln!("{}", timings);
+        }
+        Ok(())
+    };
+    run(&mut env).map_err(|errors| {
+        Failure::from_errors(&errors, render_errors(format, &errors, input, None))
+    })
+}
+
+// Evaluates an expression tree some other program serialized, printing
+// what it gave
+#[cfg(feature = "json")]
+fn eval_json(path: Option<&str>, mut env: Env) -> Result<(), Failure> {
+    let input = match path {
+        Some(path) => read_file(path),
+        None => read_stdin(),
+    }
+    .map_err(Failure::Io)?;
+    match math_parser::json::eval_json(&input, &mut env) {
+        Ok(Some(val)) => println!("{}", env.display(&val)),
+        Ok(None) => (),
+        Err(err @ JsonError::Invalid(_)) => return Err(Failure::Parse(err.message())),
+        Err(err) => return Err(Failure::Runtime(err.message())),
     }
     Ok(())
 }
 
-fn run_file(path: &str) -> Result<(), String> {
-    let bytes = std::fs::read(path).map_err(|_| {
+#[cfg(not(feature = "json"))]
+fn eval_json(_: Option<&str>, _: Env) -> Result<(), Failure> {
+    Err(Failure::Usage(
+        "Sorry, I was built without JSON support, it needs the json feature :(".to_owned(),
+    ))
+}
+
+#[cfg(feature = "json")]
+fn serve_lsp() -> Result<(), Failure> {
+    lsp::serve().map_err(Failure::Io)
+}
+
+#[cfg(not(feature = "json"))]
+fn serve_lsp() -> Result<(), Failure> {
+    Err(Failure::Usage(
+        "Sorry, I was built without JSON support, the language server needs the json feature :("
+            .to_owned(),
+    ))
+}
+
+// Parses the file once, then times evaluating it over and over
+fn bench_file(path: &str, iterations: usize, env: &EnvBuilder) -> Result<(), Failure> {
+    let input = read_file(path).map_err(Failure::Io)?;
+    let render = |errors: &[Error]| {
+        Failure::from_errors(
+            errors,
+            render_all_with(errors, &input, &RenderOptions::terminal()),
+        )
+    };
+    let exprs = parse(&input).map_err(|errors| render(&errors))?;
+    let report = bench::bench(&exprs, iterations, env).map_err(|err| render(&[err.into()]))?;
+    println!("{}", report);
+    Ok(())
+}
+
+// Runs every test file, printing each one's result as it finishes
+fn run_tests(path: &str, options: &ParseOptions, env: &EnvBuilder) -> Result<(), Failure> {
+    let paths = tester::discover(Path::new(path)).map_err(Failure::Io)?;
+    if paths.is_empty() {
+        return Err(Failure::Io(format!(
+            "Hmm, I didn't find any files ending in {} in {}",
+            tester::TEST_SUFFIX,
+            path
+        )));
+    }
+    let mut files = Vec::new();
+    for path in paths {
+        let input = read_file(&path.to_string_lossy()).map_err(Failure::Io)?;
+        let result = tester::run_file(&path, &input, options, env);
+        println!("{}", result);
+        files.push(result);
+    }
+    let summary = tester::Summary { files };
+    if summary.failed() > 0 {
+        return Err(Failure::Runtime(summary.to_string()));
+    }
+    println!("{}", summary);
+    Ok(())
+}
+
+fn read_bytes(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|_| {
         format!(
             "I failed to read the input file :(\nIs the path below correct?\n{}\n",
             path
         )
+    })
+}
+
+fn utf8(bytes: Vec<u8>) -> Result<String, String> {
+    String::from_utf8(bytes)
+        .map_err(|_| "The input file doesn't seem to be valid utf-8 :(".to_owned())
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    utf8(read_bytes(path)?)
+}
+
+fn read_stdin() -> Result<String, String> {
+    let mut input = String::new();
+    std::io::stdin()
+        .read_to_string(&mut input)
+        .map_err(|_| "I failed to read the program from stdin :(".to_owned())?;
+    Ok(input)
+}
+
+fn print_tokens(path: &str) -> Result<(), Failure> {
+    let input = read_file(path).map_err(Failure::Io)?;
+    let tokens = tokenize(&input).map_err(|err| {
+        Failure::Parse(Error::from(err).render_with(&input, &RenderOptions::terminal()))
+    })?;
+    print!("{}", dump_tokens(&input, &tokens));
+    Ok(())
+}
+
+fn print_ast(path: &str, format: AstFormat, options: &ParseOptions) -> Result<(), Failure> {
+    let input = read_file(path).map_err(Failure::Io)?;
+    let (exprs, _) = parse_with(&input, options).map_err(|errors| {
+        Failure::Parse(render_all_with(&errors, &input, &RenderOptions::terminal()))
     })?;
-    let input = std::str::from_utf8(&bytes)
-        .map_err(|_| "The input file doesn't seem to be valid utf-8 :(")?;
-    let mut env = expr::Env::new();
-    let exprs = parse(input)?;
     for expr in exprs {
-        expr.eval(&mut env);
+        match format {
+            AstFormat::Debug => println!("{:#?}", expr),
+            AstFormat::Sexpr => println!("{}", expr.to_sexpr()),
+        }
     }
     Ok(())
 }
 
-fn run() -> Result<(), String> {
-    let args = std::env::args().collect::<Vec<String>>();
-    match args.len() {
-        // Run a file
-        2 => run_file(args.get(1).unwrap())?,
-        // Run in interactive mode
-        1 => {
-            let mut env = expr::Env::new();
-            loop {
-                interact(&mut env).map_err(|_| "An unexpected io error occured :(")?;
-            }
+fn run() -> Result<(), Failure> {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let Args {
+        command,
+        options,
+        env,
+        time,
+        format,
+    } = parse_args(args.iter().map(String::as_str))
+        .map_err(|err| Failure::Usage(format!("{}\nTry --help to see what I can do", err)))?;
+    run_command(command, &options, env, time, format).map_err(|failure| failure.in_format(format))
+}
+
+fn run_command(
+    command: Command,
+    options: &ParseOptions,
+    env: EnvBuilder,
+    time: bool,
+    format: Format,
+) -> Result<(), Failure> {
+    match command {
+        // A program piped into stdin runs like a file instead of waiting at
+        // the prompt
+        Command::Default if !std::io::stdin().is_terminal() => {
+            let script = Script {
+                name: "stdin".to_owned(),
+                path: None,
+                input: read_stdin().map_err(Failure::Io)?,
+                compiled: None,
+            };
+            run_scripts(&[script], options, program_env(env, format), time, format)?
         }
-        // Fuck
-        other => {
-            return Err(format!(
-                "I dont know what to do with {} arguments :(",
-                other
+        Command::Default if format != Format::Human => {
+            return Err(Failure::Usage(
+                "Hey, --json only works when running programs, not in the REPL".to_owned(),
             ))
         }
+        // The REPL times single lines with :time instead
+        Command::Default if time => {
+            return Err(Failure::Usage(
+                "Hey, --time only works when running programs, in the REPL try :time".to_owned(),
+            ))
+        }
+        Command::Default => Repl::new(None, env.build(), options)
+            .and_then(|mut repl| repl.run())
+            .map_err(Failure::Io)?,
+        Command::Repl { session } => Repl::new(session.as_deref(), env.build(), options)
+            .and_then(|mut repl| repl.run())
+            .map_err(Failure::Io)?,
+        Command::Run { paths } => {
+            let paths = paths.iter().map(String::as_str).collect::<Vec<&str>>();
+            run_files(&paths, options, program_env(env, format), time, format)?
+        }
+        Command::Eval { code } => eval_arg(&code, options, program_env(env, format), time, format)?,
+        Command::Tokens { path } => print_tokens(&path)?,
+        Command::Ast { path, format } => print_ast(&path, format, options)?,
+        Command::FromJson { path } => eval_json(path.as_deref(), env.build())?,
+        Command::Bench { path, iterations } => bench_file(&path, iterations, &env)?,
+        Command::Test { path } => run_tests(&path, options, &env)?,
+        Command::Compile { path, output } => compile_file(&path, output.as_deref(), options)?,
+        Command::Fmt { paths, check } if paths.is_empty() => fmt_stdin(check)?,
+        Command::Fmt { paths, check } => fmt_files(&paths, check)?,
+        Command::Lint { paths } => lint_files(&paths, options)?,
+        Command::Lsp => serve_lsp()?,
+        Command::Help => println!("{}", usage()),
+        Command::Version => println!("math-parser {}", env!("CARGO_PKG_VERSION")),
     };
     Ok(())
 }
 
-fn main() {
+fn main() -> ExitCode {
     match run() {
-        Ok(()) => (),
-        Err(err) => println!("{}", err),
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            println!("{}", failure.message());
+            ExitCode::from(failure.exit_code())
+        }
     }
 }
@@ -0,0 +1,176 @@
+use crate::error::Span;
+use crate::expr::Expr;
+use crate::prelude::*;
+use crate::symbol::Symbol;
+use alloc::rc::Rc;
+
+// `module geom { fn area(r) = pi * r ^ 2 }` declares "geom.area" instead of
+// "area", so files wrapped in modules can be imported together without one
+// replacing what another declared. The body is qualified once when it's
+// parsed: what it declares and every use of that in it get the module's
+// name in front, unless a parameter or index with the same name hides it.
+// A module runs where it's written, like a let, so it has to come before
+// anything that uses it.
+
+// The names a module's body declares for everyone, not counting locals of
+// functions and series. Of a qualified name only the first part counts,
+// "shapes" for a "shapes.circle" from a module inside.
+fn members(body: &[Expr], strip: Option<&str>) -> HashSet<String> {
+  let mut members = HashSet::new();
+  let mut add = |name: &str| {
+    let name = match strip {
+      Some(prefix) => name.strip_prefix(prefix).unwrap_or(name),
+      None => name,
+    };
+    let first = name.split('.').next().unwrap_or(name);
+    members.insert(first.to_owned());
+  };
+  let mut stack = body.iter().collect::<Vec<&Expr>>();
+  while let Some(expr) = stack.pop() {
+    match expr {
+      Expr::VarDeclaration(name, ..) => add(name),
+      Expr::FnDeclaration(function, _) => {
+        add(&function.name);
+        continue;
+      }
+      Expr::Module(name, _, _) => {
+        add(name);
+        continue;
+      }
+      Expr::Series(_, _, from, to, _, _) => {
+        stack.push(from);
+        stack.push(to);
+        continue;
+      }
+      _ => (),
+    }
+    stack.extend(expr.children());
+  }
+  members
+}
+
+// Every let in a function body, the locals that hide members
+fn locals(body: &Expr) -> Vec<String> {
+  let mut locals = Vec::new();
+  body.walk(|expr| {
+    if let Expr::VarDeclaration(name, ..) = expr {
+      locals.push(name.to_string());
+    }
+  });
+  locals
+}
+
+struct Renamer {
+  // "geom."
+  prefix: String,
+  members: HashSet<String>,
+  // Adding the prefix, or taking it away again for printing
+  qualify: bool,
+}
+
+impl Renamer {
+  fn rename(&self, name: &str, hidden: &[String]) -> Option<String> {
+    let unqualified = match self.qualify {
+      true => name,
+      false => name.strip_prefix(&self.prefix)?,
+    };
+    let first = unqualified.split('.').next().unwrap_or(unqualified);
+    if !self.members.contains(first) || hidden.iter().any(|local| local == first) {
+      return None;
+    }
+    Some(match self.qualify {
+      true => format!("{}{}", self.prefix, name),
+      false => unqualified.to_owned(),
+    })
+  }
+
+  fn rename_symbol(&self, symbol: &mut Symbol, hidden: &[String]) {
+    if let Some(name) = self.rename(symbol, hidden) {
+      *symbol = Symbol::new(&name);
+    }
+  }
+
+  fn rename_string(&self, text: &mut String, hidden: &[String]) {
+    if let Some(name) = self.rename(text, hidden) {
+      *text = name;
+    }
+  }
+
+  // Declarations are only the module's at its top level, not in the body of
+  // a function or the term of a series
+  fn visit(&self, expr: &mut Expr, top: bool, hidden: &mut Vec<String>) {
+    match expr {
+      Expr::VarDeclaration(name, val, _) => {
+        self.visit(val, top, hidden);
+        if top {
+          self.rename_symbol(name, hidden);
+        }
+      }
+      Expr::Var(name, _) => self.rename_symbol(name, hidden),
+      Expr::Call(name, args, _) => {
+        self.rename_string(name, hidden);
+        for arg in args {
+          self.visit(arg, top, hidden);
+        }
+      }
+      Expr::FnDeclaration(function, _) => {
+        let function = Rc::make_mut(function);
+        if top {
+          self.rename_string(&mut function.name, hidden);
+        }
+        let mut inner = hidden.clone();
+        inner.extend(function.params.iter().map(Symbol::to_string));
+        inner.extend(locals(&function.body));
+        self.visit(&mut function.body, false, &mut inner);
+      }
+      Expr::Module(name, body, _) => {
+        if top {
+          self.rename_string(name, hidden);
+        }
+        for statement in body {
+          self.visit(statement, top, hidden);
+        }
+      }
+      Expr::Series(_, index, from, to, term, _) => {
+        self.visit(from, top, hidden);
+        self.visit(to, top, hidden);
+        hidden.push(index.to_string());
+        self.visit(term, false, hidden);
+        hidden.pop();
+      }
+      _ => {
+        for child in expr.children_mut() {
+          self.visit(child, top, hidden);
+        }
+      }
+    }
+  }
+
+  fn all(&self, mut body: Vec<Expr>) -> Vec<Expr> {
+    for statement in &mut body {
+      self.visit(statement, true, &mut Vec::new());
+    }
+    body
+  }
+}
+
+// The module as it's parsed, with its body qualified
+pub fn module(name: &str, body: Vec<Expr>, span: Span) -> Expr {
+  let renamer = Renamer {
+    prefix: format!("{}.", name),
+    members: members(&body, None),
+    qualify: true,
+  };
+  Expr::Module(name.to_owned(), renamer.all(body), span)
+}
+
+// The body as it was written, for printing it back
+pub fn unqualified(name: &str, body: &[Expr]) -> Vec<Expr> {
+  let prefix = format!("{}.", name);
+  let renamer = Renamer {
+    members: members(body, Some(&prefix)),
+    prefix,
+    qualify: false,
+  };
+  renamer.all(body.to_vec())
+}
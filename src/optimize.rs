@@ -0,0 +1,226 @@
+use crate::expr::{BinOp, Env, Expr, Function, UnOp, Value};
+use crate::prelude::*;
+use alloc::rc::Rc;
+
+// Constant folding: subtrees that don't depend on anything are evaluated
+// once, ahead of time, so "2 * 3 + x" runs as "6 + x". Only operators are
+// folded. Calls and == can depend on settings like the angle mode or the
+// tolerance, which can change while the program runs. Anything that fails
+// is left alone to fail at runtime, where the error has a location.
+//
+// Simplifying goes further and applies identities like "x * 1 = x". Those
+// assume that variables hold finite numbers, "x * 0" is 0 even when x would
+// have been NaN, so it's meant for expressions that are read rather than run.
+
+fn is_literal(expr: &Expr) -> bool {
+  matches!(expr, Expr::Literal(..))
+}
+
+fn foldable(op: BinOp) -> bool {
+  !matches!(op, BinOp::Equal | BinOp::NotEqual)
+}
+
+fn is_num(expr: &Expr, num: i64) -> bool {
+  match expr {
+    Expr::Literal(Value::Int(int), _) => *int == num,
+    Expr::Literal(Value::Float(float), _) => *float == num as f64,
+    _ => false,
+  }
+}
+
+// Builtins that give the same result for the same arguments and don't
+// change anything
+pub(crate) const PURE_BUILTINS: &[&str] = &[
+  "sin", "cos", "tan", "asin", "acos", "atan", "sqrt", "exp", "ln", "abs", "round", "trunc",
+  "floor", "ceil", "min", "max",
+];
+
+// Whether an expression can be dropped or evaluated once instead of twice
+// without anyone noticing
+fn is_pure(expr: &Expr) -> bool {
+  let pure_node = match expr {
+    Expr::Print(..) | Expr::Assert(..) | Expr::VarDeclaration(..) | Expr::FnDeclaration(..) => {
+      false
+    }
+    Expr::Call(name, ..) => PURE_BUILTINS.contains(&name.as_str()),
+    _ => true,
+  };
+  pure_node && expr.children().into_iter().all(is_pure)
+}
+
+// Whether an expression gives the same value wherever it is, like
+// "2 * sqrt(9)", for editors to show. Series are left out, they can take
+// arbitrarily long.
+pub fn is_constant(expr: &Expr) -> bool {
+  let mut constant = is_pure(expr);
+  expr.walk(|expr| constant &= !matches!(expr, Expr::Var(..) | Expr::Series(..)));
+  constant
+}
+
+// The same tree, spans aside
+fn same(left: &Expr, right: &Expr) -> bool {
+  left.to_string() == right.to_string()
+}
+
+// Rewrites an operator whose operands have already been simplified
+fn apply_identities(expr: Expr) -> Expr {
+  let (op, left, right, span) = match expr {
+    Expr::Unary(UnOp::Neg, val, span) => {
+      return match *val {
+        Expr::Unary(UnOp::Neg, val, _) => *val,
+        val => Expr::Unary(UnOp::Neg, Box::new(val), span),
+      }
+    }
+    Expr::Binary(op, left, right, span) => (op, left, right, span),
+    expr => return expr,
+  };
+  match op {
+    BinOp::Add if is_num(&right, 0) => *left,
+    BinOp::Add if is_num(&left, 0) => *right,
+    BinOp::Sub if is_num(&right, 0) => *left,
+    BinOp::Sub if is_num(&left, 0) => apply_identities(Expr::Unary(UnOp::Neg, right, span)),
+    BinOp::Sub if is_pure(&left) && same(&left, &right) => Expr::Literal(Value::Int(0), span),
+    // "a + -b" reads better as "a - b"
+    BinOp::Add | BinOp::Sub if matches!(*right, Expr::Unary(UnOp::Neg, ..)) => {
+      let flipped = match op {
+        BinOp::Add => BinOp::Sub,
+        _ => BinOp::Add,
+      };
+      match *right {
+        Expr::Unary(_, right, _) => apply_identities(Expr::Binary(flipped, left, right, span)),
+        _ => unreachable!(),
+      }
+    }
+    BinOp::Mul if is_num(&right, 1) => *left,
+    BinOp::Mul if is_num(&left, 1) => *right,
+    BinOp::Mul if is_num(&right, 0) && is_pure(&left) => *right,
+    BinOp::Mul if is_num(&left, 0) && is_pure(&right) => *left,
+    BinOp::Div if is_num(&right, 1) => *left,
+    BinOp::Pow if is_num(&right, 1) => *left,
+    BinOp::Pow if is_num(&right, 0) && is_pure(&left) => Expr::Literal(Value::Int(1), span),
+    op => Expr::Binary(op, left, right, span),
+  }
+}
+
+struct Folder {
+  // Evaluates the constant parts
+  env: Env,
+  simplify: bool,
+}
+
+impl Folder {
+  // Evaluates an operator whose operands are all literals, or simplifies it.
+  // The literal it's folded into spans the operator's source.
+  fn finish(&mut self, expr: Expr, constant: bool) -> Expr {
+    if constant {
+      match expr.eval(&mut self.env) {
+        Ok(Value::Function(_)) | Err(_) => (),
+        Ok(val) => return Expr::Literal(val, expr.span()),
+      }
+    }
+    match self.simplify {
+      true => apply_identities(expr),
+      false => expr,
+    }
+  }
+
+  fn all(&mut self, exprs: &[Expr]) -> Vec<Expr> {
+    exprs.iter().map(|expr| self.fold(expr)).collect()
+  }
+
+  fn boxed(&mut self, expr: &Expr) -> Box<Expr> {
+    Box::new(self.fold(expr))
+  }
+
+  fn fold(&mut self, expr: &Expr) -> Expr {
+    match expr {
+      Expr::Literal(..) | Expr::Var(..) | Expr::Import(..) => expr.clone(),
+      Expr::VarDeclaration(name, val, span) => {
+        Expr::VarDeclaration(name.clone(), self.boxed(val), *span)
+      }
+      Expr::Print(vals, span) => Expr::Print(self.all(vals), *span),
+      Expr::Assert(cond, span) => Expr::Assert(self.boxed(cond), *span),
+      // "x + 8%" adds 8% of x, so the percent can only be folded together
+      // with the addition
+      Expr::Binary(op @ (BinOp::Add | BinOp::Sub), left, right, span)
+        if matches!(**right, Expr::Unary(UnOp::Percent, ..)) =>
+      {
+        let (percent, percent_span) = match &**right {
+          Expr::Unary(_, percent, percent_span) => (self.boxed(percent), *percent_span),
+          _ => unreachable!(),
+        };
+        let left = self.boxed(left);
+        let constant = is_literal(&left) && is_literal(&percent);
+        let percent = Expr::Unary(UnOp::Percent, percent, percent_span);
+        let expr = Expr::Binary(*op, left, Box::new(percent), *span);
+        match constant {
+          true => self.finish(expr, true),
+          // Identities like "x + 0" don't hold here
+          false => expr,
+        }
+      }
+      Expr::Binary(op, left, right, span) => {
+        let (left, right) = (self.boxed(left), self.boxed(right));
+        let constant = foldable(*op) && is_literal(&left) && is_literal(&right);
+        self.finish(Expr::Binary(*op, left, right, *span), constant)
+      }
+      Expr::Unary(op, val, span) => {
+        let val = self.boxed(val);
+        let constant = is_literal(&val);
+        self.finish(Expr::Unary(*op, val, *span), constant)
+      }
+      // Only the branch that would be taken is left
+      Expr::Conditional(cond, val_if_true, val_if_false, span) => match self.fold(cond) {
+        Expr::Literal(Value::Bool(true), _) => self.fold(val_if_true),
+        Expr::Literal(Value::Bool(false), _) => self.fold(val_if_false),
+        cond => Expr::Conditional(
+          Box::new(cond),
+          self.boxed(val_if_true),
+          self.boxed(val_if_false),
+          *span,
+        ),
+      },
+      Expr::Call(name, args, span) => Expr::Call(name.clone(), self.all(args), *span),
+      Expr::FnDeclaration(function, span) => {
+        let function = Function {
+          name: function.name.clone(),
+          params: function.params.clone(),
+          body: self.fold(&function.body),
+          source: function.source.clone(),
+        };
+        Expr::FnDeclaration(Rc::new(function), *span)
+      }
+      Expr::Series(kind, index, from, to, term, span) => Expr::Series(
+        *kind,
+        index.clone(),
+        self.boxed(from),
+        self.boxed(to),
+        self.boxed(term),
+        *span,
+      ),
+      Expr::List(items, span) => Expr::List(self.all(items), *span),
+      Expr::Interpolation(parts, span) => Expr::Interpolation(self.all(parts), *span),
+      Expr::Module(name, body, span) => Expr::Module(name.clone(), self.all(body), *span),
+    }
+  }
+}
+
+// Returns an expression that evaluates to the same thing, with its constant
+// parts folded
+pub fn optimize(expr: &Expr) -> Expr {
+  let mut folder = Folder {
+    env: Env::without_prelude(),
+    simplify: false,
+  };
+  folder.fold(expr)
+}
+
+// Folds constants and applies algebraic identities, e.g. to clean up the
+// output of differentiate
+pub fn simplify(expr: &Expr) -> Expr {
+  let mut folder = Folder {
+    env: Env::without_prelude(),
+    simplify: true,
+  };
+  folder.fold(expr)
+}
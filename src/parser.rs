@@ -1,152 +1,963 @@
-use crate::error::print_err;
-use crate::expr::{
-  add, boolean, conditional, divide, equality, float, int, multiply, negative, subtract, Expr,
+use crate::error::{line_col, Error, ParseError, ParseErrorKind, Source, Span, Warning};
+use crate::expr::{BinOp, Expr, Function, SeriesKind, UnOp, Value};
+use crate::lexer::{tokenize, unescape, LexOptions, Lexer, Token};
+use crate::lint::{
+  constant_conditions, shadowed_variables, unused_variables, variable_reads, Lint, LintLevels,
 };
-use crate::lexer::{tokenize, Token};
+use crate::module::module;
+use crate::optimize::optimize;
+use crate::prelude::*;
+use crate::symbol::Symbol;
+use crate::typecheck::typecheck;
+use alloc::rc::Rc;
 
-type WrappedToken<'a> = (Token<'a>, usize);
-type Tokens<'a> = [WrappedToken<'a>];
-type ParseError<'a> = (WrappedToken<'a>, &'a str);
-type ParseResult<'a> = Result<(&'a Tokens<'a>, Expr), ParseError<'a>>;
+type WrappedToken<'a> = (Token<'a>, Span);
+type ParseResult = Result<Expr, ParseError>;
 
-fn first<'a>(tokens: &'a Tokens) -> WrappedToken<'a> {
-  tokens[0]
+// The tokens of the input, lexed one at a time as the parser gets to them,
+// so only the next one is held. After the last token comes Token::Eof and
+// the stream never moves past it, so looking ahead is always safe. A lex
+// error, or going over the limit on tokens, ends the stream like Eof would
+// and is kept for the parser to report instead of what it expected there.
+struct Stream<'a> {
+  lexer: Lexer<'a>,
+  peeked: WrappedToken<'a>,
+  // The last token skipped, and how many were
+  last: Option<WrappedToken<'a>>,
+  consumed: usize,
+  // Added to every span, for input that's part of a bigger one
+  offset: usize,
+  // Tokens lexed so far, not counting Eof
+  lexed: usize,
+  limits: Limits,
+  error: Option<Error>,
 }
 
-fn eat_one<'a>(tokens: &'a Tokens) -> (&'a Tokens<'a>, WrappedToken<'a>) {
-  (&tokens[1..], tokens[0])
+// A position in the stream, for what was skipped since
+#[derive(Copy, Clone)]
+struct Mark {
+  consumed: usize,
+  // Of the token that was next
+  span: Span,
 }
 
-fn skip_one<'a>(tokens: &'a Tokens) -> &'a Tokens<'a> {
-  &tokens[1..]
-}
+impl<'a> Stream<'a> {
+  fn new(lexer: Lexer<'a>, offset: usize, limits: Limits) -> Self {
+    let mut stream = Stream {
+      lexer,
+      peeked: (Token::Eof, Span::new(offset, offset)),
+      last: None,
+      consumed: 0,
+      offset,
+      lexed: 0,
+      limits,
+      error: None,
+    };
+    stream.peeked = stream.lex();
+    stream
+  }
 
-fn parse_conditional<'a>(tokens: &'a Tokens) -> ParseResult<'a> {
-  let (mut tokens, mut expr) = parse_additive(&tokens)?;
-  loop {
-    match first(&tokens) {
-      (Token::DoubleEquals, _) => {
-        let (rest, _) = eat_one(&tokens);
-        let (rest, other) = parse_additive(&rest)?;
-        expr = equality(expr, other);
-        tokens = rest
+  fn lex(&mut self) -> WrappedToken<'a> {
+    let eof = |span: Span| (Token::Eof, span);
+    if self.error.is_some() {
+      return eof(self.peeked.1);
+    }
+    let (token, span) = match self.lexer.next() {
+      Some(Ok((token, span))) => (token, shift(span, self.offset)),
+      Some(Err(mut err)) => {
+        err.span = shift(err.span, self.offset);
+        let span = err.span;
+        self.error = Some(err.into());
+        return eof(span);
+      }
+      None => return self.peeked,
+    };
+    if token != Token::Eof {
+      self.lexed += 1;
+      if self.limits.max_tokens.is_some_and(|max| self.lexed > max) {
+        self.error = Some(ParseError::new(ParseErrorKind::TooManyTokens, span).into());
+        return eof(span);
       }
-      _ => return Ok((&tokens, expr)),
     }
+    (token, span)
+  }
+
+  fn peek(&self) -> WrappedToken<'a> {
+    self.peeked
+  }
+
+  fn at_eof(&self) -> bool {
+    self.peek().0 == Token::Eof
+  }
+
+  fn skip(&mut self) {
+    if !self.at_eof() {
+      self.last = Some(self.peeked);
+      self.consumed += 1;
+      self.peeked = self.lex();
+    }
+  }
+
+  fn next(&mut self) -> WrappedToken<'a> {
+    let token = self.peek();
+    self.skip();
+    token
+  }
+
+  fn expect(&mut self, token: Token, kind: ParseErrorKind) -> Result<(), ParseError> {
+    if self.peek().0 == token {
+      self.skip();
+      Ok(())
+    } else {
+      Err(self.error(kind))
+    }
+  }
+
+  fn mark(&self) -> Mark {
+    Mark {
+      consumed: self.consumed,
+      span: self.peek().1,
+    }
+  }
+
+  // The last token skipped, if it was after `start`
+  fn previous(&self, start: Mark) -> Option<WrappedToken<'a>> {
+    match self.consumed > start.consumed {
+      true => self.last,
+      false => None,
+    }
+  }
+
+  // Expects the ")" matching the "(" at `open`
+  fn expect_closing(&mut self, open: Span) -> Result<(), ParseError> {
+    self
+      .expect(Token::RightParen, ParseErrorKind::ExpectedClosingParen)
+      .map_err(|err| err.with_label(open, "to match this one"))
+  }
+
+  fn error(&self, kind: ParseErrorKind) -> ParseError {
+    ParseError::new(kind, self.peek().1)
+  }
+
+  // The lex error or the limit the stream ended at, if it ended early
+  fn take_error(&mut self) -> Option<Error> {
+    self.error.take()
   }
 }
 
-fn parse_additive<'a>(tokens: &'a Tokens) -> ParseResult<'a> {
-  let (mut tokens, mut expr) = parse_multiplicative(&tokens)?;
-  loop {
-    match first(&tokens) {
-      (Token::Plus, _) => {
-        let (rest, _) = eat_one(&tokens);
-        let (rest, other) = parse_multiplicative(&rest)?;
-        expr = add(expr, other);
-        tokens = rest;
-      }
-      (Token::Minus, _) => {
-        let (rest, _) = eat_one(&tokens);
-        let (rest, other) = parse_multiplicative(&rest)?;
-        expr = subtract(expr, other);
-        tokens = rest;
-      }
-      _ => return Ok((&tokens, expr)),
-    };
+// Operator tables, binding powers are defined on the operators themselves
+const INFIX_OPERATORS: &[(Token, BinOp)] = &[
+  (Token::DoubleEquals, BinOp::Equal),
+  (Token::NotEquals, BinOp::NotEqual),
+  (Token::Less, BinOp::Less),
+  (Token::LessEquals, BinOp::LessEqual),
+  (Token::Greater, BinOp::Greater),
+  (Token::GreaterEquals, BinOp::GreaterEqual),
+  (Token::Caret, BinOp::Pow),
+  (Token::Plus, BinOp::Add),
+  (Token::Minus, BinOp::Sub),
+  (Token::Star, BinOp::Mul),
+  (Token::Slash, BinOp::Div),
+  (Token::DoubleSlash, BinOp::FloorDiv),
+  (Token::Ampersand, BinOp::BitAnd),
+  (Token::Pipe, BinOp::BitOr),
+  (Token::Xor, BinOp::BitXor),
+  (Token::ShiftLeft, BinOp::ShiftLeft),
+  (Token::ShiftRight, BinOp::ShiftRight),
+];
+
+const PREFIX_OPERATORS: &[(Token, UnOp)] = &[
+  (Token::Minus, UnOp::Neg),
+  (Token::Sqrt, UnOp::Sqrt),
+  (Token::Not, UnOp::Not),
+];
+
+fn infix_operator(token: Token) -> Option<(BinOp, u8, u8)> {
+  INFIX_OPERATORS
+    .iter()
+    .find(|(t, _)| *t == token)
+    .map(|&(_, op)| {
+      let (left_bp, right_bp) = op.binding_power();
+      (op, left_bp, right_bp)
+    })
+}
+
+fn prefix_operator(token: Token) -> Option<(UnOp, u8)> {
+  PREFIX_OPERATORS
+    .iter()
+    .find(|(t, _)| *t == token)
+    .map(|&(_, op)| (op, op.binding_power()))
+}
+
+// Math written like "2x", "2(x + 1)" or "(a)(b)" multiplies. Only a number
+// or ")" directly followed by a name, "(" or "√" counts, so "2 x" on one line
+// stays two statements
+fn implicit_multiplication(start: Mark, tokens: &Stream) -> bool {
+  let (next, next_span) = tokens.peek();
+  match tokens.previous(start) {
+    Some((previous, previous_span)) => {
+      matches!(
+        previous,
+        Token::Int(_) | Token::Float(_) | Token::RightParen
+      ) && matches!(next, Token::Name(_) | Token::LeftParen | Token::Sqrt)
+        && previous_span.end == next_span.start
+    }
+    None => false,
+  }
+}
+
+// Where a parse_expr call is: the mark it started at, how tightly the
+// operators it takes have to bind, and how deeply it's nested, which is
+// limited to keep the passes after parsing from overflowing the stack
+#[derive(Copy, Clone)]
+struct Level {
+  start: Mark,
+  min_bp: u8,
+  depth: usize,
+}
+
+impl Level {
+  // A whole expression nested in this one, like in parentheses
+  fn nested(self, tokens: &Stream) -> Level {
+    Level {
+      start: tokens.mark(),
+      min_bp: 0,
+      depth: self.depth + 1,
+    }
+  }
+
+  // From where the level starts to the last token skipped, which is all of
+  // the operations parsed at this level
+  fn span(self, tokens: &Stream) -> Span {
+    let start = self.start.span.start;
+    let end = tokens
+      .previous(self.start)
+      .map_or(start, |(_, span)| span.end);
+    Span::new(start, end)
   }
 }
 
-fn parse_multiplicative<'a>(tokens: &'a Tokens) -> ParseResult<'a> {
-  let (mut tokens, mut expr) = parse_primary(&tokens)?;
+// Expressions waiting for one of their parts to be parsed, with the level
+// they were started at to continue with when it is
+enum Pending<'a> {
+  Unary(UnOp, Level),
+  // The right side of a binary operator
+  Binary(BinOp, Expr, Level),
+  // The "(" the expression has to be closed for
+  Parens(Span, Level),
+  // The "[" and the items before this one
+  List(Span, Vec<Expr>, Level),
+  // The name of a call, its span, the "(" and the arguments before this one
+  Args(&'a str, Span, Span, Vec<Expr>, Level),
+  Let(&'a str, Span, Level),
+  Print(Vec<Expr>, Level),
+  // Where the condition starts
+  Assert(Mark, Level),
+  Condition(Level),
+  ValIfTrue(Expr, Level),
+  ValIfFalse(Expr, Expr, Level),
+  // The name, its span and the parameters
+  FnBody(&'a str, Span, Vec<Symbol>, Level),
+}
+
+enum State {
+  // An operand starts at the next token
+  Operand(Level),
+  // An operand ends here, operators after it might take it
+  Operators(Expr, Level),
+  // The expression of a level ends here
+  Done(Expr),
+}
+
+// Nested expressions are kept on a stack instead of recursing, so deep
+// input can't overflow the stack while it's being parsed
+fn parse_expr(tokens: &mut Stream, min_bp: u8, depth: usize) -> ParseResult {
+  let mut pending = Vec::new();
+  let level = Level {
+    start: tokens.mark(),
+    min_bp,
+    depth,
+  };
+  let mut state = State::Operand(level);
   loop {
-    match first(&tokens) {
-      (Token::Star, _) => {
-        let (rest, _) = eat_one(&tokens);
-        let (rest, other) = parse_primary(&rest)?;
-        expr = multiply(expr, other);
-        tokens = rest;
-      }
-      (Token::Slash, _) => {
-        let (rest, _) = eat_one(&tokens);
-        let (rest, other) = parse_primary(&rest)?;
-        expr = divide(expr, other);
-        tokens = rest;
-      }
-      _ => return Ok((&tokens, expr)),
-    };
+    state = match state {
+      State::Operand(level) => parse_operand(tokens, level, &mut pending)?,
+      State::Operators(expr, level) => parse_operator(tokens, expr, level, &mut pending)?,
+      State::Done(expr) => match pending.pop() {
+        Some(waiting) => resume(waiting, tokens, expr, &mut pending)?,
+        None => return Ok(expr),
+      },
+    }
+  }
+}
+
+fn parse_operand<'a>(
+  tokens: &mut Stream<'a>,
+  level: Level,
+  pending: &mut Vec<Pending<'a>>,
+) -> Result<State, ParseError> {
+  if tokens.limits.max_depth.is_some_and(|max| level.depth > max) {
+    return Err(tokens.error(ParseErrorKind::TooDeep));
+  }
+  match prefix_operator(tokens.peek().0) {
+    Some((op, right_bp)) => {
+      pending.push(Pending::Unary(op, level));
+      tokens.skip();
+      let operand = Level {
+        start: tokens.mark(),
+        min_bp: right_bp,
+        depth: level.depth + 1,
+      };
+      Ok(State::Operand(operand))
+    }
+    None => parse_primary(tokens, level, pending),
+  }
+}
+
+// Applies the next operator to `expr`, if it binds tightly enough
+fn parse_operator<'a>(
+  tokens: &mut Stream<'a>,
+  expr: Expr,
+  mut level: Level,
+  pending: &mut Vec<Pending<'a>>,
+) -> Result<State, ParseError> {
+  // Every operator in a chain like "1 + 2 + 3" nests the tree one level
+  // deeper
+  let deeper = |tokens: &Stream, depth: usize| match tokens.limits.max_depth {
+    Some(max) if depth + 1 > max => Err(tokens.error(ParseErrorKind::TooDeep)),
+    _ => Ok(depth + 1),
+  };
+  // A superscript like in x² is a power with the exponent already parsed
+  if let (Token::Superscript(exponent), exponent_span) = tokens.peek() {
+    if BinOp::Pow.binding_power().0 < level.min_bp {
+      return Ok(State::Done(expr));
+    }
+    level.depth = deeper(tokens, level.depth)?;
+    tokens.skip();
+    let expr = Expr::Binary(
+      BinOp::Pow,
+      Box::new(expr),
+      Box::new(Expr::Literal(Value::Int(exponent), exponent_span)),
+      level.span(tokens),
+    );
+    return Ok(State::Operators(expr, level));
+  }
+  if tokens.peek().0 == Token::Percent {
+    if UnOp::Percent.binding_power() < level.min_bp {
+      return Ok(State::Done(expr));
+    }
+    level.depth = deeper(tokens, level.depth)?;
+    tokens.skip();
+    let expr = Expr::Unary(UnOp::Percent, Box::new(expr), level.span(tokens));
+    return Ok(State::Operators(expr, level));
+  }
+  let implicit = implicit_multiplication(level.start, tokens);
+  let (op, right_bp) = match infix_operator(tokens.peek().0) {
+    Some((op, left_bp, right_bp)) if left_bp >= level.min_bp => (op, right_bp),
+    None if implicit && BinOp::Mul.binding_power().0 >= level.min_bp => {
+      (BinOp::Mul, BinOp::Mul.binding_power().1)
+    }
+    _ => return Ok(State::Done(expr)),
+  };
+  level.depth = deeper(tokens, level.depth)?;
+  // There's no operator token to skip when multiplying implicitly
+  if !implicit {
+    tokens.skip();
   }
+  let right = Level {
+    start: tokens.mark(),
+    min_bp: right_bp,
+    depth: level.depth,
+  };
+  pending.push(Pending::Binary(op, expr, level));
+  Ok(State::Operand(right))
 }
 
-fn parse_primary<'a>(tokens: &'a Tokens) -> ParseResult<'a> {
-  let (tokens, token) = eat_one(&tokens);
-  match token {
-    (Token::LeftParen, _) => {
-      let (tokens, expr) = parse_conditional(&tokens)?;
-      if first(tokens).0 != Token::RightParen {
-        return Err((first(tokens), "Hey, I expected a closing parenthesis here"));
+// Picks up an expression that was waiting for `expr`, which ends before the
+// next token
+fn resume<'a>(
+  waiting: Pending<'a>,
+  tokens: &mut Stream<'a>,
+  expr: Expr,
+  pending: &mut Vec<Pending<'a>>,
+) -> Result<State, ParseError> {
+  let state = match waiting {
+    // The operator is where the level starts
+    Pending::Unary(op, level) => {
+      let expr = Expr::Unary(op, Box::new(expr), level.span(tokens));
+      State::Operators(expr, level)
+    }
+    Pending::Binary(op, left, level) => {
+      let expr = Expr::Binary(op, Box::new(left), Box::new(expr), level.span(tokens));
+      State::Operators(expr, level)
+    }
+    Pending::Parens(open, level) => {
+      tokens.expect_closing(open)?;
+      State::Operators(expr, level)
+    }
+    Pending::List(open, mut items, level) => {
+      items.push(expr);
+      if tokens.peek().0 == Token::Comma {
+        tokens.skip();
+        pending.push(Pending::List(open, items, level));
+        return Ok(State::Operand(level.nested(tokens)));
+      }
+      tokens
+        .expect(Token::RightBracket, ParseErrorKind::ExpectedClosingBracket)
+        .map_err(|err| err.with_label(open, "to match this one"))?;
+      State::Operators(Expr::List(items, level.span(tokens)), level)
+    }
+    Pending::Args(name, span, open, mut args, level) => {
+      args.push(expr);
+      if tokens.peek().0 == Token::Comma {
+        tokens.skip();
+        pending.push(Pending::Args(name, span, open, args, level));
+        return Ok(State::Operand(level.nested(tokens)));
       }
-      let tokens = skip_one(&tokens);
-      Ok((&tokens, expr))
+      tokens.expect_closing(open)?;
+      State::Operators(call(name, args, span), level)
     }
-    (Token::Int(num), _) => Ok((&tokens, int(num))),
-    (Token::Float(num), _) => Ok((&tokens, float(num))),
-    (Token::Minus, _) => {
-      let (tokens, expr) = parse_primary(&tokens)?;
-      Ok((&tokens, negative(expr)))
+    Pending::Let(name, span, level) => {
+      let expr = Expr::VarDeclaration(Symbol::new(name), Box::from(expr), span);
+      State::Operators(expr, level)
     }
-    (Token::LetKeyword, _) => match first(tokens) {
-      (Token::Name(name), _) => {
-        let tokens = skip_one(tokens);
-        if first(tokens).0 != Token::Equals {
-          return Err((first(tokens), "Hey, I expected \"=\" right here"));
+    Pending::Print(mut vals, level) => {
+      vals.push(expr);
+      if tokens.peek().0 == Token::Comma {
+        tokens.skip();
+        pending.push(Pending::Print(vals, level));
+        return Ok(State::Operand(level.nested(tokens)));
+      }
+      State::Operators(Expr::Print(vals, level.span(tokens)), level)
+    }
+    Pending::Assert(cond_start, level) => {
+      let start = cond_start.span.start;
+      let end = tokens
+        .previous(cond_start)
+        .map_or(start, |(_, span)| span.end);
+      let expr = Expr::Assert(Box::from(expr), Span::new(start, end));
+      State::Operators(expr, level)
+    }
+    Pending::Condition(level) => {
+      tokens.expect(Token::Then, ParseErrorKind::ExpectedThen)?;
+      pending.push(Pending::ValIfTrue(expr, level));
+      State::Operand(level.nested(tokens))
+    }
+    Pending::ValIfTrue(cond, level) => {
+      tokens.expect(Token::Else, ParseErrorKind::ExpectedElse)?;
+      pending.push(Pending::ValIfFalse(cond, expr, level));
+      State::Operand(level.nested(tokens))
+    }
+    Pending::ValIfFalse(cond, val_if_true, level) => {
+      let expr = Expr::Conditional(
+        Box::new(cond),
+        Box::new(val_if_true),
+        Box::new(expr),
+        level.span(tokens),
+      );
+      State::Operators(expr, level)
+    }
+    Pending::FnBody(name, span, params, level) => {
+      let function = Function {
+        name: name.to_owned(),
+        params,
+        body: expr,
+        source: Source::Input,
+      };
+      State::Operators(Expr::FnDeclaration(Rc::new(function), span), level)
+    }
+  };
+  Ok(state)
+}
+
+// A string literal, with the expressions in braces parsed like they were
+// written outside of it. "{{" and "}}" are literal braces. The literal parts
+// of an interpolation span their text, without the quotes.
+fn parse_string(limits: &Limits, raw: &str, span: Span, depth: usize) -> ParseResult {
+  let str_literal = |text: &str, span| Expr::Literal(Value::Str(unescape(text).into()), span);
+  if !raw.contains(['{', '}']) {
+    return Ok(str_literal(raw, span));
+  }
+  // Where raw starts in the input, after the opening quote
+  let offset = span.start + 1;
+  let mut parts = Vec::new();
+  let mut text = String::new();
+  let mut text_start = 0;
+  let mut chars = raw.char_indices().peekable();
+  while let Some((i, c)) = chars.next() {
+    match c {
+      // Escapes are kept for unescape, "\\{" is a backslash then a brace
+      '\\' => {
+        text.push(c);
+        if let Some((_, escaped)) = chars.next() {
+          text.push(escaped);
         }
-        let tokens = skip_one(tokens);
-        let (tokens, expr) = parse_conditional(tokens)?;
-        Ok((
-          tokens,
-          Expr::VarDeclaration(name.to_owned(), Box::from(expr)),
-        ))
-      }
-      token => Err((token, "Hey, I expected a name of a variable right here")),
+      }
+      '{' | '}' if chars.peek().map(|&(_, next)| next) == Some(c) => {
+        chars.next();
+        text.push(c);
+      }
+      '}' => {
+        let span = Span::new(offset + i, offset + i + 1);
+        return Err(ParseError::new(ParseErrorKind::UnmatchedBrace, span));
+      }
+      _ if c != '{' => text.push(c),
+      _ => {
+        let start = i + 1;
+        let end = match raw[start..].find('}') {
+          Some(len) => start + len,
+          None => {
+            let span = Span::new(offset + i, offset + i + 1);
+            return Err(ParseError::new(ParseErrorKind::UnclosedBrace, span));
+          }
+        };
+        if !text.is_empty() {
+          let text_span = Span::new(offset + text_start, offset + i);
+          parts.push(str_literal(&core::mem::take(&mut text), text_span));
+        }
+        parts.push(parse_interpolated(
+          limits,
+          &raw[start..end],
+          offset + start,
+          depth,
+        )?);
+        while chars.peek().is_some_and(|&(j, _)| j <= end) {
+          chars.next();
+        }
+        text_start = end + 1;
+      }
+    }
+  }
+  if parts.is_empty() {
+    return Ok(str_literal(&text, span));
+  }
+  if !text.is_empty() {
+    parts.push(str_literal(
+      &text,
+      Span::new(offset + text_start, offset + raw.len()),
+    ));
+  }
+  Ok(Expr::Interpolation(parts, span))
+}
+
+fn shift(span: Span, by: usize) -> Span {
+  Span::new(span.start + by, span.end + by)
+}
+
+// The expression between the braces of an interpolation, `start` is where
+// it begins in the input
+fn parse_interpolated(limits: &Limits, source: &str, start: usize, depth: usize) -> ParseResult {
+  // Its tokens were never counted against the limit
+  let limits = Limits {
+    max_tokens: None,
+    ..limits.clone()
+  };
+  let mut inner = Stream::new(Lexer::new(source), start, limits);
+  let result = parse_expr(&mut inner, 0, depth + 1);
+  // A lex error comes before whatever the parser made of the tokens up to it
+  if let Some(err) = inner.take_error() {
+    let span = err.span().unwrap_or_else(|| Span::new(start, start));
+    return Err(ParseError::new(ParseErrorKind::InvalidInterpolation, span));
+  }
+  let expr = result?;
+  if !inner.at_eof() {
+    return Err(inner.error(ParseErrorKind::UnexpectedToken));
+  }
+  Ok(expr)
+}
+
+// Parses "name(a, b) =", after the "fn" keyword. The body comes next.
+fn parse_fn_header<'a>(
+  tokens: &mut Stream<'a>,
+) -> Result<(&'a str, Span, Vec<Symbol>), ParseError> {
+  let (name, span) = match tokens.peek() {
+    (Token::Name(name), span) => (name, span),
+    _ => return Err(tokens.error(ParseErrorKind::ExpectedFnName)),
+  };
+  tokens.skip();
+  let open = tokens.peek().1;
+  tokens.expect(Token::LeftParen, ParseErrorKind::ExpectedParams)?;
+  let mut params = Vec::new();
+  if tokens.peek().0 == Token::RightParen {
+    tokens.skip();
+  } else {
+    loop {
+      match tokens.peek() {
+        (Token::Name(param), _) => params.push(Symbol::new(param)),
+        _ => return Err(tokens.error(ParseErrorKind::ExpectedParamName)),
+      }
+      tokens.skip();
+      match tokens.peek().0 {
+        Token::Comma => tokens.skip(),
+        _ => {
+          tokens.expect_closing(open)?;
+          break;
+        }
+      }
+    }
+  }
+  tokens.expect(Token::Equals, ParseErrorKind::ExpectedEquals)?;
+  Ok((name, span, params))
+}
+
+// Parses "name { statements }", after the "module" keyword. Every statement
+// is parsed on its own like at the top level, with the module's depth.
+fn parse_module(tokens: &mut Stream, depth: usize) -> ParseResult {
+  let (name, span) = match tokens.peek() {
+    (Token::Name(name), span) => (name, span),
+    _ => return Err(tokens.error(ParseErrorKind::ExpectedModuleName)),
+  };
+  tokens.skip();
+  let open = tokens.peek().1;
+  tokens.expect(Token::LeftBrace, ParseErrorKind::ExpectedModuleBody)?;
+  let mut body = Vec::new();
+  loop {
+    skip_semicolons(tokens);
+    match tokens.peek().0 {
+      Token::RightBrace => {
+        tokens.skip();
+        return Ok(module(name, body, span));
+      }
+      Token::Eof => {
+        let err = tokens.error(ParseErrorKind::ExpectedClosingBrace);
+        return Err(err.with_label(open, "to match this one"));
+      }
+      _ => body.push(parse_expr(tokens, 0, depth + 1)?),
+    }
+  }
+}
+
+// "sum(i, 1, 100, i * i)" looks like a call, but the term can't be
+// evaluated before the index is set
+fn series(kind: SeriesKind, args: Vec<Expr>, span: Span) -> Expr {
+  let mut args = args.into_iter();
+  let mut next = || Box::new(args.next().unwrap());
+  let index = match *next() {
+    Expr::Var(index, _) => index,
+    _ => unreachable!("the index is checked before"),
+  };
+  let (from, to, term) = (next(), next(), next());
+  Expr::Series(kind, index, from, to, term, span)
+}
+
+// Calls named like a series with an index variable are series
+pub(crate) fn call(name: &str, args: Vec<Expr>, span: Span) -> Expr {
+  match (SeriesKind::from_name(name), args.as_slice()) {
+    (Some(kind), [Expr::Var(..), _, _, _]) => series(kind, args, span),
+    _ => Expr::Call(name.to_owned(), args, span),
+  }
+}
+
+// Parses a whole primary if it's a single token, otherwise starts it and
+// leaves it pending until its parts are parsed
+fn parse_primary<'a>(
+  tokens: &mut Stream<'a>,
+  level: Level,
+  pending: &mut Vec<Pending<'a>>,
+) -> Result<State, ParseError> {
+  let done = |expr: Expr| Ok(State::Operators(expr, level));
+  let mut nested = |tokens: &Stream, waiting: Pending<'a>| {
+    pending.push(waiting);
+    Ok(State::Operand(level.nested(tokens)))
+  };
+  match tokens.next() {
+    (Token::LeftParen, open) => nested(tokens, Pending::Parens(open, level)),
+    (Token::Int(num), span) => done(Expr::Literal(Value::Int(num), span)),
+    (Token::Float(num), span) => done(Expr::Literal(Value::Float(num), span)),
+    (Token::LeftBracket, _) if tokens.peek().0 == Token::RightBracket => {
+      tokens.skip();
+      done(Expr::List(Vec::new(), level.span(tokens)))
+    }
+    (Token::LeftBracket, open) => nested(tokens, Pending::List(open, Vec::new(), level)),
+    (Token::Str(raw), span) => done(parse_string(&tokens.limits, raw, span, level.depth)?),
+    (Token::LetKeyword, _) => match tokens.peek() {
+      (Token::Name(name), span) => {
+        tokens.skip();
+        tokens.expect(Token::Equals, ParseErrorKind::ExpectedEquals)?;
+        nested(tokens, Pending::Let(name, span, level))
+      }
+      _ => Err(tokens.error(ParseErrorKind::ExpectedVarName)),
+    },
+    (Token::FnKeyword, _) => {
+      let (name, span, params) = parse_fn_header(tokens)?;
+      nested(tokens, Pending::FnBody(name, span, params, level))
+    }
+    (Token::PrintKeyword, _) => nested(tokens, Pending::Print(Vec::new(), level)),
+    (Token::AssertKeyword, _) => nested(tokens, Pending::Assert(tokens.mark(), level)),
+    (Token::ImportKeyword, _) => match tokens.peek() {
+      (Token::Str(raw), span) => {
+        tokens.skip();
+        done(Expr::Import(unescape(raw), span))
+      }
+      _ => Err(tokens.error(ParseErrorKind::ExpectedImportPath)),
     },
-    (Token::PrintKeyword, _) => {
-      let (tokens, expr) = parse_conditional(tokens)?;
-      Ok((tokens, Expr::Print(Box::from(expr))))
-    }
-    (Token::Name(name), _) => Ok((tokens, Expr::Var(name.to_owned()))),
-    (Token::True, _) => Ok((tokens, boolean(true))),
-    (Token::False, _) => Ok((tokens, boolean(false))),
-    (Token::If, _) => {
-      let (tokens, cond) = parse_conditional(&tokens)?;
-      if first(tokens).0 != Token::Then {
-        return Err((first(tokens), "Hey, I expected a \"then\" keyword right here (conditional expressions look like this: if *condition* then *value* else *value*)"));
-      }
-      let tokens = skip_one(&tokens); // eat "then"
-      let (tokens, val_if_true) = parse_conditional(&tokens)?;
-      if first(tokens).0 != Token::Else {
-        return Err((
-          first(tokens),
-          "Hey, I expected an \"else\" keyword right here",
-        ));
-      }
-      let tokens = skip_one(&tokens); // eat "else"
-      let (tokens, val_if_false) = parse_conditional(&tokens)?;
-      Ok((tokens, conditional(cond, val_if_true, val_if_false)))
-    }
-    (Token::EOF, _) => Err((token, "Hey, I didn't expect the input to end right here")),
-    token => Err((token, "Hey, I didn't expect this thing right here")),
-  }
-}
-
-pub fn parse(input: &str) -> Result<Vec<Expr>, String> {
-  let mut tokens = &tokenize(&input)?[..];
-  let mut expressions = Vec::new();
-  while first(tokens).0 != Token::EOF {
-    let (unparsed, expr) =
-      parse_conditional(&tokens).map_err(|err| print_err(input, (err.0).1, err.1))?;
-    expressions.push(expr);
-    tokens = unparsed;
-  }
-  Ok(expressions)
+    (Token::ModuleKeyword, _) => done(parse_module(tokens, level.depth)?),
+    (Token::Name(name), span) if tokens.peek().0 == Token::LeftParen => {
+      let (_, open) = tokens.next();
+      if tokens.peek().0 == Token::RightParen {
+        tokens.skip();
+        return done(call(name, Vec::new(), span));
+      }
+      nested(tokens, Pending::Args(name, span, open, Vec::new(), level))
+    }
+    (Token::Name(name), span) => done(Expr::Var(Symbol::new(name), span)),
+    (Token::True, span) => done(Expr::Literal(Value::Bool(true), span)),
+    (Token::False, span) => done(Expr::Literal(Value::Bool(false), span)),
+    (Token::If, _) => nested(tokens, Pending::Condition(level)),
+    (Token::Eof, span) => Err(ParseError::new(ParseErrorKind::UnexpectedEof, span)),
+    (_, span) => Err(ParseError::new(ParseErrorKind::UnexpectedToken, span)),
+  }
+}
+
+// After an error, skips to the start of the next statement: a "let",
+// "print", "assert", "fn", "import" or "module" keyword, the token after a
+// ";" or the first token on a later line than the error
+fn synchronize(tokens: &mut Stream, statement: Mark, err: &ParseError, input: &str) {
+  while !tokens.at_eof() && tokens.peek().1.start < err.span.start {
+    tokens.skip();
+  }
+  if tokens.consumed == statement.consumed {
+    tokens.skip();
+  }
+  let error_line = line_col(input, err.span.start).0;
+  while !tokens.at_eof() {
+    let (token, span) = tokens.peek();
+    let starts_statement = matches!(
+      token,
+      Token::LetKeyword
+        | Token::PrintKeyword
+        | Token::AssertKeyword
+        | Token::FnKeyword
+        | Token::ImportKeyword
+        | Token::ModuleKeyword
+    );
+    if starts_statement || line_col(input, span.start).0 > error_line {
+      break;
+    }
+    if token == Token::Semicolon {
+      skip_semicolons(tokens);
+      return;
+    }
+    tokens.skip();
+  }
+}
+
+// Limits on the size of the input, so that servers and embedded hosts can
+// reject pathological formulas before evaluating anything
+#[derive(Debug, Clone)]
+pub struct Limits {
+  pub max_tokens: Option<usize>,
+  // How deeply expressions may be nested
+  pub max_depth: Option<usize>,
+  // Maximum number of AST nodes in the whole input
+  pub max_nodes: Option<usize>,
+}
+
+impl Default for Limits {
+  fn default() -> Self {
+    Limits {
+      max_tokens: None,
+      max_depth: Some(DEFAULT_MAX_DEPTH),
+      max_nodes: None,
+    }
+  }
+}
+
+// Deep enough for any hand written formula, shallow enough not to overflow
+// the stack of the main thread
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+  pub lex: LexOptions,
+  pub limits: Limits,
+  pub lints: LintLevels,
+  // Runs the type checker on the parsed program
+  pub typecheck: bool,
+  // Folds the constant parts of every statement
+  pub optimize: bool,
+}
+
+// A top-level expression, and whether it was ended with a ";" to say its
+// result isn't interesting
+pub struct Statement {
+  pub expr: Expr,
+  pub terminated: bool,
+  // From its first token to its last, the ";" left out
+  pub span: Span,
+}
+
+// Returns whether there was any ";" to skip
+fn skip_semicolons(tokens: &mut Stream) -> bool {
+  let mut skipped = false;
+  while tokens.peek().0 == Token::Semicolon {
+    tokens.skip();
+    skipped = true;
+  }
+  skipped
+}
+
+// Parses one statement at a time, so a script can start running before the
+// rest of it is parsed. Syntax errors are recovered from like when parsing
+// everything at once, the statement after a failed one parses normally.
+// The input is lexed as it's parsed too, so a lex error only comes when the
+// parser gets to it and ends the statements.
+//
+// Only the lints of single tokens are checked. Unused variables, types and
+// optimizing need the whole program, those options are ignored here.
+pub struct Parser<'a> {
+  input: &'a str,
+  tokens: Stream<'a>,
+  nodes: usize,
+  finished: bool,
+}
+
+impl<'a> Parser<'a> {
+  pub fn new(input: &'a str) -> Result<Self, Error> {
+    Parser::with_options(input, &ParseOptions::default())
+  }
+
+  // Fails when the first token can't be lexed, like when the input is empty
+  pub fn with_options(input: &'a str, options: &ParseOptions) -> Result<Self, Error> {
+    let mut lex = options.lex.clone();
+    lex.exact |= options.lints.enabled(Lint::InexactFloat);
+    let mut tokens = Stream::new(Lexer::with_options(input, &lex), 0, options.limits.clone());
+    if let Some(err) = tokens.take_error() {
+      return Err(err);
+    }
+    skip_semicolons(&mut tokens);
+    Ok(Parser {
+      input,
+      tokens,
+      nodes: 0,
+      finished: false,
+    })
+  }
+
+  pub fn next_statement(&mut self) -> Option<Result<Expr, Error>> {
+    self
+      .next()
+      .map(|statement| statement.map(|statement| statement.expr))
+  }
+
+  // Warnings from lexing so far, before their lint levels are applied
+  pub fn take_warnings(&mut self) -> Vec<Warning> {
+    self.tokens.lexer.take_warnings()
+  }
+
+  fn limits(&self) -> &Limits {
+    &self.tokens.limits
+  }
+
+  fn fail(&mut self, err: Error) -> Option<Result<Statement, Error>> {
+    self.finished = true;
+    Some(Err(err))
+  }
+}
+
+impl<'a> Iterator for Parser<'a> {
+  type Item = Result<Statement, Error>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.finished {
+      return None;
+    }
+    if self.tokens.at_eof() {
+      self.finished = true;
+      return self.tokens.take_error().map(Err);
+    }
+    let start = self.tokens.mark();
+    let result = parse_expr(&mut self.tokens, 0, 0);
+    // The statement might have gone on after where the stream ended, so
+    // it's dropped instead of run cut short
+    if let Some(err) = self.tokens.take_error() {
+      return self.fail(err);
+    }
+    let expr = match result {
+      Ok(expr) => expr,
+      Err(err) => {
+        synchronize(&mut self.tokens, start, &err, self.input);
+        return Some(Err(err.into()));
+      }
+    };
+    let nodes = self.nodes + expr.node_count();
+    if self.limits().max_nodes.is_some_and(|max| nodes > max) {
+      let err = ParseError::new(ParseErrorKind::TooManyNodes, start.span);
+      return self.fail(err.into());
+    }
+    self.nodes = nodes;
+    let end = self
+      .tokens
+      .previous(start)
+      .map_or(start.span.start, |(_, span)| span.end);
+    let terminated = skip_semicolons(&mut self.tokens);
+    Some(Ok(Statement {
+      expr,
+      terminated,
+      span: Span::new(start.span.start, end),
+    }))
+  }
+}
+
+pub fn parse(input: &str) -> Result<Vec<Expr>, Vec<Error>> {
+  parse_with(input, &ParseOptions::default()).map(|(exprs, _)| exprs)
+}
+
+pub fn parse_with(
+  input: &str,
+  options: &ParseOptions,
+) -> Result<(Vec<Expr>, Vec<Warning>), Vec<Error>> {
+  let (statements, warnings) = parse_statements(input, options)?;
+  let exprs = statements
+    .into_iter()
+    .map(|statement| statement.expr)
+    .collect();
+  Ok((exprs, warnings))
+}
+
+// Parses every statement in the input, recovering from syntax errors so that
+// all of them get reported at once
+pub fn parse_statements(
+  input: &str,
+  options: &ParseOptions,
+) -> Result<(Vec<Statement>, Vec<Warning>), Vec<Error>> {
+  let mut parser = Parser::with_options(input, options).map_err(|err| vec![err])?;
+  let mut statements = Vec::new();
+  let mut errors = Vec::new();
+  for result in &mut parser {
+    match result {
+      Ok(statement) => statements.push(statement),
+      Err(err) => errors.push(err),
+    }
+  }
+  let mut warnings = parser.take_warnings();
+  if !errors.is_empty() {
+    return Err(errors);
+  }
+  let exprs = || statements.iter().map(|statement| &statement.expr);
+  if options.lints.enabled(Lint::UnusedVariable) {
+    warnings.extend(unused_variables(exprs(), &variable_reads(exprs())));
+  }
+  if options.lints.enabled(Lint::ConstantCondition) {
+    warnings.extend(constant_conditions(exprs()));
+  }
+  // This finds parameters in the tokens, which the parser didn't keep. The
+  // input lexed without errors already.
+  if options.lints.enabled(Lint::ShadowedVariable) {
+    let tokens = tokenize(input).map_err(|err| vec![Error::from(err)])?;
+    warnings.extend(shadowed_variables(exprs(), &tokens));
+  }
+  // In the order they're found in the input, not lint by lint
+  warnings.sort_by_key(|warning| warning.span.start);
+  let warnings = options.lints.apply(warnings)?;
+  if options.typecheck {
+    typecheck(statements.iter().map(|statement| &statement.expr))
+      .map_err(|errors| errors.into_iter().map(Error::from).collect::<Vec<Error>>())?;
+  }
+  if options.optimize {
+    for statement in &mut statements {
+      statement.expr = optimize(&statement.expr);
+    }
+  }
+  Ok((statements, warnings))
 }
@@ -0,0 +1,78 @@
+use crate::expr::Value;
+use crate::prelude::*;
+
+// Rough graphs drawn with characters, to get a feel for the shape of a
+// function right in the terminal
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 16;
+
+// The x of every column of a plot from a to b, both ends included
+pub fn sample_points(a: f64, b: f64) -> Vec<f64> {
+  (0..WIDTH)
+    .map(|column| a + (b - a) * column as f64 / (WIDTH - 1) as f64)
+    .collect()
+}
+
+// Short labels for the axes
+fn label(num: f64) -> String {
+  format!("{:.4}", Value::Float(num))
+}
+
+// Draws one point per column, ys that aren't finite numbers are left out.
+// None when there's no point to draw at all.
+pub fn render(xs: &[f64], ys: &[f64]) -> Option<String> {
+  let finite = ys.iter().copied().filter(|y| y.is_finite());
+  let (mut low, mut high) = finite.fold(None, |range, y| match range {
+    None => Some((y, y)),
+    Some((low, high)) => Some((y.min(low), y.max(high))),
+  })?;
+  // A flat line goes through the middle
+  if low == high {
+    low -= 1.0;
+    high += 1.0;
+  }
+  let row_of = |y: f64| ((high - y) / (high - low) * (HEIGHT - 1) as f64).round() as usize;
+  let mut grid = vec![vec![' '; xs.len()]; HEIGHT];
+  // The axes, where they're in sight
+  if low < 0.0 && 0.0 < high {
+    grid[row_of(0.0)].iter_mut().for_each(|cell| *cell = '-');
+  }
+  let (first, last) = (xs[0], xs[xs.len() - 1]);
+  if first < 0.0 && 0.0 < last {
+    let column = (-first / (last - first) * (xs.len() - 1) as f64).round() as usize;
+    for row in &mut grid {
+      row[column] = '|';
+    }
+  }
+  for (column, &y) in ys.iter().enumerate() {
+    if y.is_finite() {
+      grid[row_of(y)][column] = '*';
+    }
+  }
+  let (top, bottom) = (label(high), label(low));
+  let gutter = top.len().max(bottom.len());
+  let mut lines: Vec<String> = grid
+    .into_iter()
+    .enumerate()
+    .map(|(row, cells)| {
+      let name = match row {
+        0 => top.as_str(),
+        row if row == HEIGHT - 1 => bottom.as_str(),
+        _ => "",
+      };
+      let cells: String = cells.into_iter().collect();
+      format!("{:>gutter$} |{}", name, cells.trim_end(), gutter = gutter)
+    })
+    .collect();
+  lines.push(format!("{} +{}", " ".repeat(gutter), "-".repeat(xs.len())));
+  let (start, end) = (label(first), label(last));
+  lines.push(format!(
+    "{}  {}{:>width$}",
+    " ".repeat(gutter),
+    start,
+    end,
+    width = xs.len().saturating_sub(start.len()),
+  ));
+  Some(lines.join("\n"))
+}
@@ -0,0 +1,18 @@
+// What the std prelude has that no_std code has to import, used by every
+// module with `use crate::prelude::*`. Building with std these are the same
+// items the std prelude has.
+pub use alloc::borrow::ToOwned;
+pub use alloc::boxed::Box;
+pub use alloc::string::{String, ToString};
+pub use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+pub use alloc::{format, vec};
+
+// Float methods like sin() come from std, without it they're libm functions
+#[cfg(not(feature = "std"))]
+pub use crate::float::FloatMath;
+
+#[cfg(not(feature = "std"))]
+pub use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "std")]
+pub use std::collections::{HashMap, HashSet};
@@ -0,0 +1,188 @@
+use crate::error::Error;
+use crate::expr::{Expr, UnOp, Value};
+use crate::lexer::quote;
+use crate::module::unqualified;
+use crate::parser::{parse_statements, ParseOptions};
+use crate::prelude::*;
+use core::fmt;
+
+// Converts expressions back to source text, adding only the parentheses that
+// are needed to parse back into the same tree.
+
+// How tightly an expression holds together when it's an operand: (left, right)
+// binding power, like for operators
+pub(crate) fn binding_power(expr: &Expr) -> (u8, u8) {
+  match expr {
+    Expr::Binary(op, ..) => op.binding_power(),
+    Expr::Unary(UnOp::Percent, _, _) => (UnOp::Percent.binding_power(), u8::MAX),
+    Expr::Unary(op, _, _) => (u8::MAX, op.binding_power()),
+    // Values like "(1.0 / 0.0)" come with their own parentheses, but negative
+    // numbers are read back as a prefix minus
+    Expr::Literal(Value::Int(num), _) if *num < 0 => (u8::MAX, UnOp::Neg.binding_power()),
+    Expr::Literal(Value::Float(num), _) if *num < 0.0 => (u8::MAX, UnOp::Neg.binding_power()),
+    Expr::Literal(..)
+    | Expr::Var(..)
+    | Expr::Call(..)
+    | Expr::List(..)
+    | Expr::Interpolation(..)
+    | Expr::Series(..)
+    | Expr::Import(..)
+    | Expr::Module(..) => (u8::MAX, u8::MAX),
+    // These extend as far to the right as they can
+    Expr::VarDeclaration(..)
+    | Expr::Print(..)
+    | Expr::Assert(..)
+    | Expr::Conditional(..)
+    | Expr::FnDeclaration(..) => (0, 0),
+  }
+}
+
+// Whether a print ends the expression, it would take the items after it
+// along unless it's in parentheses
+fn ends_with_print(expr: &Expr) -> bool {
+  match expr {
+    Expr::Print(..) => true,
+    Expr::VarDeclaration(_, val, _) | Expr::Assert(val, _) => ends_with_print(val),
+    Expr::Conditional(_, _, val_if_false, _) => ends_with_print(val_if_false),
+    Expr::FnDeclaration(function, _) => ends_with_print(&function.body),
+    _ => false,
+  }
+}
+
+// Comma separated, like arguments or list items
+fn write_items(f: &mut fmt::Formatter, items: &[Expr]) -> fmt::Result {
+  for (i, item) in items.iter().enumerate() {
+    if i > 0 {
+      write!(f, ", ")?;
+    }
+    write_operand(f, item, i + 1 < items.len() && ends_with_print(item))?;
+  }
+  Ok(())
+}
+
+fn write_operand(f: &mut fmt::Formatter, expr: &Expr, parens: bool) -> fmt::Result {
+  if parens {
+    write!(f, "({})", expr)
+  } else {
+    write!(f, "{}", expr)
+  }
+}
+
+impl fmt::Display for Expr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Expr::Literal(val, _) => write!(f, "{}", val.to_source()),
+      Expr::VarDeclaration(name, val, _) => write!(f, "let {} = {}", name, val),
+      Expr::Var(name, _) => write!(f, "{}", name),
+      Expr::Print(vals, _) => {
+        write!(f, "print ")?;
+        write_items(f, vals)
+      }
+      Expr::Assert(cond, _) => write!(f, "assert {}", cond),
+      Expr::Import(path, _) => write!(f, "import {}", quote(path)),
+      Expr::Binary(op, left, right, _) => {
+        let (left_bp, right_bp) = op.binding_power();
+        // The left operand would swallow the operator if it reached as far
+        // right, the right operand must not stop before its own operator
+        write_operand(f, left, binding_power(left).1 <= left_bp)?;
+        write!(f, " {} ", op.symbol())?;
+        write_operand(f, right, binding_power(right).0 < right_bp)
+      }
+      Expr::Unary(UnOp::Percent, val, _) => {
+        let bp = UnOp::Percent.binding_power();
+        write_operand(f, val, binding_power(val).1 <= bp)?;
+        write!(f, "%")
+      }
+      Expr::Unary(op, val, _) => {
+        write!(f, "{}", op.symbol())?;
+        write_operand(f, val, binding_power(val).0 < op.binding_power())
+      }
+      Expr::Conditional(cond, val_if_true, val_if_false, _) => {
+        write!(f, "if {} then {} else {}", cond, val_if_true, val_if_false)
+      }
+      Expr::Call(name, args, _) => {
+        write!(f, "{}(", name)?;
+        write_items(f, args)?;
+        write!(f, ")")
+      }
+      Expr::Series(kind, index, from, to, term, _) => {
+        write!(f, "{}({}, ", kind.name(), index)?;
+        write_operand(f, from, ends_with_print(from))?;
+        write!(f, ", ")?;
+        write_operand(f, to, ends_with_print(to))?;
+        write!(f, ", {})", term)
+      }
+      Expr::List(items, _) => {
+        write!(f, "[")?;
+        write_items(f, items)?;
+        write!(f, "]")
+      }
+      Expr::Interpolation(parts, _) => {
+        write!(f, "\"")?;
+        for part in parts {
+          match part {
+            Expr::Literal(Value::Str(text), _) => {
+              let quoted = quote(text);
+              write!(f, "{}", &quoted[1..quoted.len() - 1])?;
+            }
+            part => write!(f, "{{{}}}", part)?,
+          }
+        }
+        write!(f, "\"")
+      }
+      Expr::FnDeclaration(function, _) => write!(
+        f,
+        "fn {}({}) = {}",
+        function.name,
+        function.params.join(", "),
+        function.body
+      ),
+      // A statement on every line, indented, with the names written like
+      // inside the module
+      Expr::Module(name, body, _) if body.is_empty() => write!(f, "module {} {{}}", name),
+      Expr::Module(name, body, _) => {
+        writeln!(f, "module {} {{", name)?;
+        for statement in unqualified(name, body) {
+          for line in statement.to_string().lines() {
+            writeln!(f, "  {}", line)?;
+          }
+        }
+        write!(f, "}}")
+      }
+    }
+  }
+}
+
+impl Expr {
+  pub fn to_source(&self) -> String {
+    self.to_string()
+  }
+}
+
+// A whole script laid out the canonical way: every statement printed like
+// to_source does, on a line of its own, keeping its ";". Where statements
+// were separated by blank lines, one blank line is kept, so the groups they
+// were written in stay apart.
+pub fn format_script(input: &str) -> Result<String, Vec<Error>> {
+  if input.trim().is_empty() {
+    return Ok(String::new());
+  }
+  let (statements, _) = parse_statements(input, &ParseOptions::default())?;
+  let mut formatted = String::with_capacity(input.len());
+  let mut end = None;
+  for statement in statements {
+    if let Some(end) = end {
+      let between = &input[end..statement.span.start];
+      if between.matches('\n').count() > 1 {
+        formatted.push('\n');
+      }
+    }
+    formatted.push_str(&statement.expr.to_source());
+    if statement.terminated {
+      formatted.push(';');
+    }
+    formatted.push('\n');
+    end = Some(statement.span.end);
+  }
+  Ok(formatted)
+}
@@ -0,0 +1,163 @@
+#[cfg(feature = "std")]
+use crate::error::EnvFileError;
+use crate::error::{Error, ParseError, ParseErrorKind, RuntimeError, Span};
+use crate::expr::{Env, Expr, Value};
+use crate::parser::parse;
+use crate::prelude::*;
+use crate::symbol::Symbol;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::path::Path;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Progress {
+  // Index of the top-level expression that's about to be evaluated
+  pub index: usize,
+  pub total: usize,
+  pub elapsed: Duration,
+}
+
+// Top-level functions are declared before anything else runs, so scripts can
+// call functions that are defined further down
+pub fn declare_functions<'a>(
+  exprs: impl IntoIterator<Item = &'a Expr>,
+  env: &mut Env,
+) -> Result<(), RuntimeError> {
+  for expr in exprs {
+    if let Expr::FnDeclaration(..) = expr {
+      expr.eval(env)?;
+    }
+  }
+  Ok(())
+}
+
+pub fn eval_all(exprs: &[Expr], env: &mut Env) -> Result<(), RuntimeError> {
+  declare_functions(exprs, env)?;
+  for expr in exprs {
+    expr.eval(env)?;
+  }
+  Ok(())
+}
+
+// Like eval_all, but if anything fails the environment is left exactly as it
+// was before, instead of keeping whatever ran before the error
+pub fn eval_transactional(exprs: &[Expr], env: &mut Env) -> Result<(), RuntimeError> {
+  let snapshot = env.snapshot();
+  eval_all(exprs, env).inspect_err(|_| env.rollback(snapshot))
+}
+
+// Evaluates the expressions in order, calling `on_progress` between them once
+// evaluation has been running for `interval`, and then every `interval` after
+#[cfg(feature = "std")]
+pub fn eval_all_with_progress<F: FnMut(Progress)>(
+  exprs: &[Expr],
+  env: &mut Env,
+  interval: Duration,
+  mut on_progress: F,
+) -> Result<(), RuntimeError> {
+  declare_functions(exprs, env)?;
+  let start = Instant::now();
+  let mut next_report = interval;
+  for (index, expr) in exprs.iter().enumerate() {
+    let elapsed = start.elapsed();
+    if elapsed >= next_report {
+      on_progress(Progress {
+        index,
+        total: exprs.len(),
+        elapsed,
+      });
+      next_report = elapsed + interval;
+    }
+    expr.eval(env)?;
+  }
+  Ok(())
+}
+
+// For programs embedding the language: parses the input and evaluates it in
+// a fresh environment, giving the value of the last statement. Only the first
+// error is returned.
+pub fn eval_str(input: &str) -> Result<Value, Error> {
+  eval_str_with_env(input, &mut Env::new())
+}
+
+// Like eval_str, in an environment that can have variables set beforehand
+// and keeps whatever the input declares
+pub fn eval_str_with_env(input: &str, env: &mut Env) -> Result<Value, Error> {
+  let exprs = parse(input).map_err(|mut errors| errors.remove(0))?;
+  let (last, init) = match exprs.split_last() {
+    Some(split) => split,
+    None => {
+      let end = Span::new(input.len(), input.len());
+      return Err(ParseError::new(ParseErrorKind::UnexpectedEof, end).into());
+    }
+  };
+  declare_functions(&exprs, env)?;
+  for expr in init {
+    expr.eval(env)?;
+  }
+  Ok(last.eval(env)?)
+}
+
+// Environments are saved as a script of declarations, one per variable, so
+// a saved file can be read, edited and run like any other script
+impl Env {
+  // Sorted by name, so saving the same variables always gives the same text.
+  // Settings like the angle mode aren't part of it.
+  pub fn to_script(&self) -> String {
+    let mut vars: Vec<(&Symbol, &Value)> = self.vars().collect();
+    vars.sort_by_key(|(name, _)| name.as_str());
+    vars
+      .iter()
+      .map(|(name, val)| format!("let {} = {}\n", name, val.to_source()))
+      .collect()
+  }
+
+  // Runs a script saved with to_script. If any of it fails, none of the
+  // variables are set.
+  pub fn load_script(&mut self, script: &str) -> Result<(), Vec<Error>> {
+    if script.trim().is_empty() {
+      return Ok(());
+    }
+    eval_transactional(&parse(script)?, self).map_err(|err| vec![err.into()])
+  }
+
+  #[cfg(feature = "std")]
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<(), EnvFileError> {
+    std::fs::write(path, self.to_script()).map_err(|err| EnvFileError::Io(err.to_string()))
+  }
+
+  // Adds the variables saved in the file, replacing any with the same names
+  #[cfg(feature = "std")]
+  pub fn load(&mut self, path: impl AsRef<Path>) -> Result<(), EnvFileError> {
+    let script = std::fs::read_to_string(path).map_err(|err| EnvFileError::Io(err.to_string()))?;
+    self
+      .load_script(&script)
+      .map_err(|errors| EnvFileError::Invalid { script, errors })
+  }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+  use super::*;
+  use crate::parser::parse;
+
+  #[test]
+  fn failed_imports_can_be_imported_again() {
+    let dir = std::env::temp_dir().join(format!("math-parser-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let lib = dir.join("lib.math");
+    std::fs::write(&lib, "let from_lib = 5\n").unwrap();
+    let import = format!("import \"{}\"", lib.display());
+
+    let mut env = Env::new();
+    let failing = parse(&format!("{}\noops", import)).unwrap();
+    assert!(eval_transactional(&failing, &mut env).is_err());
+    assert_eq!(env.get("from_lib"), None);
+
+    eval_transactional(&parse(&import).unwrap(), &mut env).unwrap();
+    assert_eq!(env.get("from_lib"), Some(Value::Int(5)));
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,279 @@
+use crate::error::{render_all, with_article, Error};
+use crate::expr::{AngleMode, Env, Expr, Value};
+use crate::parser::parse;
+use crate::program::eval_str_with_env;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyKeyError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyList, PyString, PyTuple};
+use pyo3::IntoPyObjectExt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// The math_parser module for Python, so notebooks can use the same syntax
+// as the command line program:
+//
+//   import math_parser
+//   env = math_parser.Env()
+//   env["x"] = 2
+//   math_parser.eval("x ^ 10", env)  # 1024
+//
+// Numbers, bools, strings and lists become the Python values they look
+// like, functions come back as the text they're shown as. Errors are raised
+// as MathError, rendered against the input like in the REPL.
+
+create_exception!(math_parser, MathError, PyException);
+
+fn to_python(py: Python, val: &Value, env: &Env) -> PyResult<PyObject> {
+  match val {
+    Value::Int(num) => num.into_py_any(py),
+    Value::Float(num) => num.into_py_any(py),
+    Value::Bool(b) => b.into_py_any(py),
+    Value::Str(text) => (**text).into_py_any(py),
+    Value::List(vals) => {
+      let vals = vals
+        .iter()
+        .map(|val| to_python(py, val, env))
+        .collect::<PyResult<Vec<_>>>()?;
+      PyList::new(py, vals)?.into_py_any(py)
+    }
+    Value::Function(_) => env.display(val).into_py_any(py),
+  }
+}
+
+// Anything with __index__ or __float__ works as a number, so numpy scalars
+// can be passed in as they are
+fn from_python(obj: &Bound<PyAny>) -> PyResult<Value> {
+  if let Ok(b) = obj.downcast::<PyBool>() {
+    return Ok(Value::Bool(b.is_true()));
+  }
+  if let Ok(text) = obj.downcast::<PyString>() {
+    return Ok(Value::Str(text.to_str()?.into()));
+  }
+  if obj.is_instance_of::<PyList>() || obj.is_instance_of::<PyTuple>() {
+    let vals = obj
+      .try_iter()?
+      .map(|item| from_python(&item?))
+      .collect::<PyResult<Vec<_>>>()?;
+    return Ok(Value::List(Rc::new(vals)));
+  }
+  if let Ok(num) = obj.extract::<i64>() {
+    return Ok(Value::Int(num));
+  }
+  if let Ok(num) = obj.extract::<f64>() {
+    return Ok(Value::Float(num));
+  }
+  Err(PyTypeError::new_err(format!(
+    "Hey, {} can't be a value, only numbers, bools, strings and lists of them",
+    with_article(&obj.get_type().name()?.to_string())
+  )))
+}
+
+fn math_error(err: &Error, input: &str) -> PyErr {
+  MathError::new_err(err.render(input))
+}
+
+// Print statements write to sys.stdout, which is where notebooks show output
+struct PythonStdout;
+
+impl Write for PythonStdout {
+  fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+    let text = String::from_utf8_lossy(bytes);
+    Python::with_gil(|py| {
+      let stdout = py.import("sys")?.getattr("stdout")?;
+      stdout.call_method1("write", (text,)).map(drop)
+    })
+    .map_err(|err| io::Error::other(err.to_string()))?;
+    Ok(bytes.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+fn fresh_env() -> Env {
+  let mut env = Env::new();
+  env.set_output(PythonStdout);
+  env
+}
+
+// Called PyEnv here, Python sees it as Env. Variables are read and set like
+// a dict's items.
+#[pyclass(name = "Env", unsendable)]
+pub struct PyEnv {
+  env: Env,
+}
+
+#[pymethods]
+impl PyEnv {
+  #[new]
+  fn new() -> PyEnv {
+    PyEnv { env: fresh_env() }
+  }
+
+  fn __getitem__(&self, py: Python, name: &str) -> PyResult<PyObject> {
+    match self.env.get(name) {
+      Some(val) => to_python(py, &val, &self.env),
+      None => Err(PyKeyError::new_err(name.to_owned())),
+    }
+  }
+
+  fn __setitem__(&mut self, name: &str, val: &Bound<PyAny>) -> PyResult<()> {
+    self.env.set(name, from_python(val)?);
+    Ok(())
+  }
+
+  fn __contains__(&self, name: &str) -> bool {
+    self.env.get(name).is_some()
+  }
+
+  fn __len__(&self) -> usize {
+    self.env.var_names().count()
+  }
+
+  #[pyo3(signature = (name, default=None))]
+  fn get(&self, py: Python, name: &str, default: Option<PyObject>) -> PyResult<PyObject> {
+    match self.env.get(name) {
+      Some(val) => to_python(py, &val, &self.env),
+      None => Ok(default.unwrap_or_else(|| py.None())),
+    }
+  }
+
+  fn keys(&self) -> Vec<String> {
+    self.env.var_names().map(str::to_owned).collect()
+  }
+
+  // Forgets every variable, settings like the angle mode stay
+  fn clear(&mut self) {
+    let mut env = fresh_env();
+    env.set_angle_mode(self.env.angle_mode());
+    env.set_precision(self.env.precision());
+    self.env = env;
+  }
+
+  // "deg" or "rad"
+  #[getter]
+  fn angle_mode(&self) -> &'static str {
+    self.env.angle_mode().name()
+  }
+
+  #[setter]
+  fn set_angle_mode(&mut self, name: &str) -> PyResult<()> {
+    let mode = AngleMode::from_name(name)
+      .ok_or_else(|| PyValueError::new_err(format!("Hey, \"{}\" isn't deg or rad", name)))?;
+    self.env.set_angle_mode(mode);
+    Ok(())
+  }
+
+  // Significant digits results are shown with, None for all of them
+  #[getter]
+  fn precision(&self) -> Option<usize> {
+    self.env.precision()
+  }
+
+  #[setter]
+  fn set_precision(&mut self, digits: Option<usize>) {
+    self.env.set_precision(digits);
+  }
+
+  fn eval(&mut self, py: Python, input: &str) -> PyResult<PyObject> {
+    match eval_str_with_env(input, &mut self.env) {
+      Ok(val) => to_python(py, &val, &self.env),
+      Err(err) => Err(math_error(&err, input)),
+    }
+  }
+
+  // The script of declarations that makes these variables again
+  fn to_script(&self) -> String {
+    self.env.to_script()
+  }
+}
+
+// One parsed statement. It keeps the whole input it came from, so errors
+// while evaluating it can point into it.
+#[pyclass(name = "Expr", unsendable)]
+pub struct PyExpr {
+  expr: Expr,
+  input: Rc<str>,
+}
+
+#[pymethods]
+impl PyExpr {
+  // Looks like the input, with the parentheses it needs and no others
+  fn __str__(&self) -> String {
+    self.expr.to_string()
+  }
+
+  fn __repr__(&self) -> String {
+    format!("Expr({:?})", self.expr.to_string())
+  }
+
+  fn __eq__(&self, other: &PyExpr) -> bool {
+    self.expr.structurally_equal(&other.expr)
+  }
+
+  fn to_sexpr(&self) -> String {
+    self.expr.to_sexpr()
+  }
+
+  fn to_latex(&self) -> String {
+    self.expr.to_latex()
+  }
+
+  // In a new environment unless one is given
+  #[pyo3(signature = (env=None))]
+  fn eval(&self, py: Python, env: Option<PyRefMut<PyEnv>>) -> PyResult<PyObject> {
+    match env {
+      Some(mut env) => self.eval_in(py, &mut env.env),
+      None => self.eval_in(py, &mut fresh_env()),
+    }
+  }
+}
+
+impl PyExpr {
+  fn eval_in(&self, py: Python, env: &mut Env) -> PyResult<PyObject> {
+    let val = self
+      .expr
+      .eval(env)
+      .map_err(|err| math_error(&err.into(), &self.input))?;
+    to_python(py, &val, env)
+  }
+}
+
+// The statements in the input, raising every error found in it at once
+#[pyfunction(name = "parse")]
+fn py_parse(input: &str) -> PyResult<Vec<PyExpr>> {
+  let exprs = parse(input).map_err(|errors| MathError::new_err(render_all(&errors, input)))?;
+  let shared: Rc<str> = input.into();
+  Ok(
+    exprs
+      .into_iter()
+      .map(|expr| PyExpr {
+        expr,
+        input: shared.clone(),
+      })
+      .collect(),
+  )
+}
+
+// Runs the input and returns the value of its last statement. Anything it
+// declares stays in the env, if there's one.
+#[pyfunction(name = "eval")]
+#[pyo3(signature = (input, env=None))]
+fn py_eval(py: Python, input: &str, env: Option<PyRefMut<PyEnv>>) -> PyResult<PyObject> {
+  match env {
+    Some(mut env) => env.eval(py, input),
+    None => PyEnv::new().eval(py, input),
+  }
+}
+
+#[pymodule]
+fn math_parser(module: &Bound<PyModule>) -> PyResult<()> {
+  module.add_function(wrap_pyfunction!(py_parse, module)?)?;
+  module.add_function(wrap_pyfunction!(py_eval, module)?)?;
+  module.add_class::<PyEnv>()?;
+  module.add_class::<PyExpr>()?;
+  module.add("MathError", module.py().get_type::<MathError>())?;
+  Ok(())
+}
@@ -0,0 +1,123 @@
+use crate::error::RuntimeError;
+use crate::expr::{Expr, Value};
+use crate::prelude::*;
+use core::fmt;
+
+// A log of every expression an Env evaluated while recording, to go back
+// through afterwards and see where a surprising number came from
+
+// Long loops of recursion would otherwise eat all the memory
+pub const MAX_RECORDED_STEPS: usize = 100_000;
+
+// A variable set by a step
+#[derive(Debug, Clone)]
+pub struct Change {
+  pub name: String,
+  // None when the variable didn't exist before
+  pub before: Option<Value>,
+  pub after: Value,
+}
+
+// One evaluated expression, recorded when it finished
+#[derive(Debug, Clone)]
+pub struct Step {
+  // The expression written as source
+  pub expr: String,
+  // How many expressions it's nested in, 0 for whole statements
+  pub depth: usize,
+  // What its direct subexpressions evaluated to, in evaluation order
+  pub inputs: Vec<Value>,
+  pub output: Result<Value, String>,
+  pub changes: Vec<Change>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+  // In the order they finished, so subexpressions come before their parents
+  steps: Vec<Step>,
+  depth: usize,
+  // Changes made since the last step finished, they belong to the next one
+  changes: Vec<Change>,
+  truncated: bool,
+}
+
+impl Recording {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn steps(&self) -> &[Step] {
+    &self.steps
+  }
+
+  // Whether steps were dropped after reaching MAX_RECORDED_STEPS
+  pub fn truncated(&self) -> bool {
+    self.truncated
+  }
+
+  // Called before an expression is evaluated, returns where its
+  // subexpressions' steps will start
+  pub(crate) fn enter(&mut self) -> usize {
+    self.depth += 1;
+    self.steps.len()
+  }
+
+  pub(crate) fn leave(
+    &mut self,
+    expr: &Expr,
+    first_child: usize,
+    result: &Result<Value, RuntimeError>,
+  ) {
+    self.depth -= 1;
+    let changes = core::mem::take(&mut self.changes);
+    if self.steps.len() >= MAX_RECORDED_STEPS {
+      self.truncated = true;
+      return;
+    }
+    let inputs = self.steps[first_child.min(self.steps.len())..]
+      .iter()
+      .filter(|step| step.depth == self.depth + 1)
+      .filter_map(|step| step.output.clone().ok())
+      .collect();
+    self.steps.push(Step {
+      expr: expr.to_string(),
+      depth: self.depth,
+      inputs,
+      output: result.clone().map_err(|err| err.message()),
+      changes,
+    });
+  }
+
+  pub(crate) fn changed(&mut self, name: &str, before: Option<Value>, after: &Value) {
+    self.changes.push(Change {
+      name: name.to_owned(),
+      before,
+      after: after.clone(),
+    });
+  }
+}
+
+impl fmt::Display for Step {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.expr)?;
+    if !self.inputs.is_empty() {
+      let inputs = self.inputs.iter().map(Value::to_string);
+      write!(f, "  with {}", inputs.collect::<Vec<_>>().join(", "))?;
+    }
+    match &self.output {
+      Ok(val) => write!(f, "  gave {}", val)?,
+      Err(message) => write!(f, "  failed: {}", message)?,
+    }
+    for change in &self.changes {
+      match &change.before {
+        Some(before) => write!(
+          f,
+          "\n  {} went from {} to {}",
+          change.name, before, change.after
+        )?,
+        None => write!(f, "\n  {} is now {}", change.name, change.after)?,
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,422 @@
+use crate::completion::Completion;
+use crate::session::Session;
+use math_parser::batch::eval_batch;
+use math_parser::builtins::interval;
+use math_parser::derivative::differentiate;
+use math_parser::error::{render_all_with, RenderOptions};
+use math_parser::expr::{AngleMode, Env, Value};
+use math_parser::lexer::{dump_tokens, tokenize};
+use math_parser::lint::{Level, Lint};
+use math_parser::optimize::simplify;
+use math_parser::parser::{parse, parse_statements, ParseOptions, Statement};
+use math_parser::plot;
+use math_parser::recording::Recording;
+use math_parser::rng::Rng;
+use math_parser::Error;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+type LineEditor = Editor<Completion, DefaultHistory>;
+
+pub struct Repl {
+  editor: LineEditor,
+  env: Env,
+  last_input: String,
+  // Named buffers opened with ":edit <name>"
+  buffers: HashMap<String, String>,
+  session: Option<Session>,
+  // Set with ":record on", every input then gets recorded for ":back"
+  recording: bool,
+  // The last recorded input and the step being looked at
+  timeline: Option<(Recording, usize)>,
+  // Lints, --check and --optimize from the command line, for every input
+  options: ParseOptions,
+}
+
+impl Repl {
+  pub fn new(session: Option<&str>, mut env: Env, options: &ParseOptions) -> Result<Self, String> {
+    let session = session.map(Session::open).transpose()?;
+    let mut editor = LineEditor::new().map_err(|_| "I couldn't start the line editor :(")?;
+    if let Some(session) = &session {
+      session.restore_env(&mut env)?;
+      // There's no history yet the first time a session is used
+      let _ = editor.load_history(&session.history_path());
+    }
+    editor.set_helper(Some(Completion::new(&env)));
+    // A variable is usually used by a later input, not the one declaring it
+    let mut options = options.clone();
+    options.lints.set(Lint::UnusedVariable, Level::Allow);
+    Ok(Repl {
+      editor,
+      env,
+      last_input: String::new(),
+      buffers: HashMap::new(),
+      session,
+      recording: false,
+      timeline: None,
+      options,
+    })
+  }
+
+  pub fn run(&mut self) -> Result<(), String> {
+    let result = loop {
+      match self.interact() {
+        Ok(()) => (),
+        Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => break Ok(()),
+        Err(_) => break Err("An unexpected io error occured :(".to_owned()),
+      }
+    };
+    self.save_session()?;
+    result
+  }
+
+  fn save_session(&mut self) -> Result<(), String> {
+    if let Some(session) = &self.session {
+      session.save_env(&self.env)?;
+      self
+        .editor
+        .save_history(&session.history_path())
+        .map_err(|_| "I couldn't save the session history :(")?;
+    }
+    Ok(())
+  }
+
+  fn interact(&mut self) -> rustyline::Result<()> {
+    let input = self.editor.readline("math> ")?;
+    self.editor.add_history_entry(input.as_str())?;
+    match input.trim().strip_prefix(':') {
+      Some(command) => self.command(command),
+      None => {
+        self.eval(&input);
+        self.last_input = input;
+      }
+    }
+    if let Some(completion) = self.editor.helper_mut() {
+      completion.refresh(&self.env);
+    }
+    Ok(())
+  }
+
+  fn eval(&mut self, input: &str) {
+    if self.recording {
+      self.env.start_recording();
+    }
+    if let Err(errors) = self.eval_and_print(input) {
+      println!(
+        "{}",
+        render_all_with(&errors, input, &RenderOptions::terminal())
+      );
+    }
+    if let Some(recording) = self.env.take_recording() {
+      // Looking back usually starts from the result
+      let last = recording.steps().len().saturating_sub(1);
+      self.timeline = Some((recording, last));
+    }
+  }
+
+  // Variables are only changed if the whole input evaluates, so an error
+  // halfway through doesn't leave the session half updated
+  fn eval_and_print(&mut self, input: &str) -> Result<(), Vec<Error>> {
+    let (statements, warnings) = parse_statements(input, &self.options)?;
+    for warning in warnings {
+      println!("{}", warning.render_with(input, &RenderOptions::terminal()));
+    }
+    let snapshot = self.env.snapshot();
+    for Statement {
+      expr, terminated, ..
+    } in statements
+    {
+      match expr.eval(&mut self.env) {
+        // A ";" at the end hides the result
+        Ok(_) if terminated => (),
+        Ok(val) => println!("{}", self.env.display(&val)),
+        Err(err) => {
+          self.env.rollback(snapshot);
+          return Err(vec![err.into()]);
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn command(&mut self, command: &str) {
+    let (name, rest) = command.split_once(' ').unwrap_or((command, ""));
+    let mut words = rest.split_whitespace();
+    match (name, words.next()) {
+      ("edit", buffer) => self.edit(buffer),
+      ("cache", Some("clear")) => {
+        self.env.clear_cache();
+        println!("Forgot every cached result");
+      }
+      ("cache", None) => println!("{} cached result(s)", self.env.cache_len()),
+      ("cache", Some(other)) => println!("I can only \":cache clear\", not \"{}\"", other),
+      ("record", Some("on")) => {
+        self.recording = true;
+        println!("Recording every step, go through them with \":back\" and \":step\"");
+      }
+      ("record", Some("off")) => {
+        self.recording = false;
+        println!("Stopped recording");
+      }
+      ("record", None) => match self.recording {
+        true => println!("Recording is on"),
+        false => println!("Recording is off, turn it on with \":record on\""),
+      },
+      ("record", Some(other)) => {
+        println!("I can only \":record on\" or \"off\", not \"{}\"", other)
+      }
+      ("mode", None) => println!("Angles are in {}", self.env.angle_mode().name()),
+      ("mode", Some(name)) => match AngleMode::from_name(name) {
+        Some(mode) => {
+          self.env.set_angle_mode(mode);
+          println!("Angles are in {} now", mode.name());
+        }
+        None => println!("I can only \":mode deg\" or \"rad\", not \"{}\"", name),
+      },
+      ("precision", None) => match self.env.precision() {
+        Some(digits) => println!("Floats show at most {} significant digits", digits),
+        None => println!("Floats show every digit"),
+      },
+      ("precision", Some("off")) => {
+        self.env.set_precision(None);
+        println!("Floats show every digit now");
+      }
+      ("precision", Some(digits)) => match digits.parse::<usize>() {
+        Ok(digits) if digits > 0 => {
+          self.env.set_precision(Some(digits));
+          println!("Floats show at most {} significant digits now", digits);
+        }
+        _ => println!(
+          "I can only \":precision\" a whole number above 0 or \"off\", not \"{}\"",
+          digits
+        ),
+      },
+      ("diff", Some(var)) => self.differentiate(var, rest.trim_start()[var.len()..].trim()),
+      ("diff", None) => println!("Try \":diff x x^2\", the variable comes first"),
+      ("latex", Some(_)) => match parse(rest) {
+        Ok(exprs) => exprs
+          .iter()
+          .for_each(|expr| println!("{}", expr.to_latex())),
+        Err(errors) => println!(
+          "{}",
+          render_all_with(&errors, rest, &RenderOptions::terminal())
+        ),
+      },
+      ("latex", None) => println!("Try \":latex x^2 / 2\""),
+      ("time", Some(_)) => {
+        let start = Instant::now();
+        self.eval(rest);
+        println!("took {:.2?}", start.elapsed());
+      }
+      ("time", None) => println!("Try \":time sum(i, 1, 100000, i)\""),
+      ("plot", Some(_)) => self.plot(rest.trim()),
+      ("plot", None) => println!("Try \":plot sin(x) from 0 to 6.28\""),
+      ("save-env", Some(_)) => match self.env.save(rest.trim()) {
+        Ok(()) => println!("Saved {} variable(s)", self.env.vars().count()),
+        Err(err) => println!("{}", err),
+      },
+      ("save-env", None) => println!("Try \":save-env vars.math\", the file comes after it"),
+      ("load-env", Some(_)) => match self.env.load(rest.trim()) {
+        Ok(()) => println!(
+          "Loaded the variables, there are {} now",
+          self.env.vars().count()
+        ),
+        Err(err) => println!("{}", err),
+      },
+      ("load-env", None) => println!("Try \":load-env vars.math\""),
+      ("back", count) => self.travel(count, false),
+      ("step", count) => self.travel(count, true),
+      ("tokens", _) => match tokenize(rest) {
+        Ok(tokens) => print!("{}", dump_tokens(rest, &tokens)),
+        Err(err) => println!(
+          "{}",
+          Error::from(err).render_with(rest, &RenderOptions::terminal())
+        ),
+      },
+      ("", _) => println!("Type a command name after the \":\""),
+      (other, _) => println!("I don't know the \":{}\" command :(", other),
+    }
+  }
+
+  // Prints the derivative of a single expression
+  fn differentiate(&self, var: &str, input: &str) {
+    let exprs = match parse(input) {
+      Ok(exprs) => exprs,
+      Err(errors) => {
+        return println!(
+          "{}",
+          render_all_with(&errors, input, &RenderOptions::terminal())
+        )
+      }
+    };
+    match exprs.as_slice() {
+      [expr] => match differentiate(expr, var) {
+        Ok(derivative) => println!("{}", simplify(&derivative)),
+        Err(err) => println!("{}", err),
+      },
+      _ => println!("Hey, I can only differentiate one expression at a time"),
+    }
+  }
+
+  // Plots an expression of x, e.g. "sin(x) from 0 to 6.28"
+  fn plot(&mut self, input: &str) {
+    let (source, a, b) = match input
+      .rsplit_once(" to ")
+      .and_then(|(rest, b)| Some((rest.rsplit_once(" from ")?, b)))
+    {
+      Some(((source, a), b)) => (source, a, b),
+      None => return println!("Hey, I need to know where to plot, like \"from 0 to 6.28\""),
+    };
+    match self.plot_between(source, a, b) {
+      Ok(graph) => println!("{}", graph),
+      Err(message) => println!("{}", message),
+    }
+  }
+
+  // Errors come back rendered against the part of the input they're in
+  fn plot_between(&mut self, source: &str, a: &str, b: &str) -> Result<String, String> {
+    let exprs = parse(source)
+      .map_err(|errors| render_all_with(&errors, source, &RenderOptions::terminal()))?;
+    let expr = match exprs.as_slice() {
+      [expr] => expr,
+      _ => return Err("Hey, I can only plot one expression at a time".to_owned()),
+    };
+    let (a, b) = (self.number(a)?, self.number(b)?);
+    let (a, b) = interval(a, b).map_err(|err| err.message())?;
+    let xs = plot::sample_points(a, b);
+    let ys: Vec<f64> = eval_batch(expr, &["x"], &[&xs], &mut self.env)
+      .and_then(|vals| vals.iter().map(Value::f64).collect())
+      .map_err(|err| Error::from(err).render_with(source, &RenderOptions::terminal()))?;
+    plot::render(&xs, &ys).ok_or_else(|| {
+      format!(
+        "Hmm, \"{}\" isn't a number anywhere from {} to {}, so there's nothing to plot",
+        source.trim(),
+        a,
+        b
+      )
+    })
+  }
+
+  // Evaluates a single expression that has to give a number
+  fn number(&mut self, source: &str) -> Result<f64, String> {
+    let exprs = parse(source)
+      .map_err(|errors| render_all_with(&errors, source, &RenderOptions::terminal()))?;
+    match exprs.as_slice() {
+      [expr] => expr
+        .eval(&mut self.env)
+        .and_then(|val| val.f64())
+        .map_err(|err| Error::from(err).render_with(source, &RenderOptions::terminal())),
+      _ => Err(format!(
+        "Hey, \"{}\" should be a single number",
+        source.trim()
+      )),
+    }
+  }
+
+  // Moves through the last recording, a step at a time unless a count is
+  // given
+  fn travel(&mut self, count: Option<&str>, forward: bool) {
+    let (recording, position) = match &mut self.timeline {
+      Some(timeline) if !timeline.0.steps().is_empty() => timeline,
+      _ => return println!("There's nothing recorded yet, try \":record on\" first"),
+    };
+    let count = match count.map(str::parse::<usize>) {
+      None => 1,
+      Some(Ok(count)) => count,
+      Some(Err(_)) => return println!("Hey, I can only move a whole number of steps"),
+    };
+    let last = recording.steps().len() - 1;
+    *position = match forward {
+      true => position.saturating_add(count).min(last),
+      false => position.saturating_sub(count),
+    };
+    let step = &recording.steps()[*position];
+    println!(
+      "[{}/{}] {}{}",
+      *position + 1,
+      last + 1,
+      "  ".repeat(step.depth),
+      step
+    );
+    if *position == last && recording.truncated() {
+      println!("(the recording stopped here, it got too long)");
+    }
+  }
+
+  // Opens the last input (or a named buffer) in $EDITOR, then evaluates
+  // whatever was saved
+  fn edit(&mut self, buffer: Option<&str>) {
+    let initial = match buffer {
+      Some(name) => self.buffers.get(name).cloned().unwrap_or_default(),
+      None => self.last_input.clone(),
+    };
+    let edited = match edit_in_editor(&initial) {
+      Ok(edited) => edited,
+      Err(err) => return println!("{}", err),
+    };
+    self.eval(&edited);
+    match buffer {
+      Some(name) => {
+        self.buffers.insert(name.to_owned(), edited);
+      }
+      None => self.last_input = edited,
+    }
+  }
+}
+
+// A new file only this user can read, with a name nobody can guess. Opening
+// it fails instead of following a file or link someone else put there first.
+fn create_temp_file() -> std::io::Result<(PathBuf, File)> {
+  let mut rng = Rng::new(Rng::from_time().next_u64() ^ u64::from(std::process::id()));
+  let mut options = OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+  loop {
+    let name = format!("math-parser-{:016x}.math", rng.next_u64());
+    let path = std::env::temp_dir().join(name);
+    match options.open(&path) {
+      Ok(file) => return Ok((path, file)),
+      Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+      Err(err) => return Err(err),
+    }
+  }
+}
+
+fn edit_in_editor(initial: &str) -> Result<String, String> {
+  let editor = ["VISUAL", "EDITOR"]
+    .iter()
+    .filter_map(|var| std::env::var(var).ok())
+    .find(|editor| !editor.is_empty())
+    .unwrap_or_else(|| "vi".to_owned());
+  let (path, mut file) =
+    create_temp_file().map_err(|_| "I couldn't create a file for the editor :(")?;
+  let written = file.write_all(initial.as_bytes());
+  drop(file);
+  let edited = written
+    .map_err(|_| "I couldn't create a file for the editor :(".to_owned())
+    .and_then(|()| run_editor(&editor, &path));
+  // Whatever happened, the file isn't needed anymore
+  let _ = std::fs::remove_file(&path);
+  edited
+}
+
+fn run_editor(editor: &str, path: &Path) -> Result<String, String> {
+  // Editors are often configured with flags, e.g. EDITOR="code --wait"
+  let mut words = editor.split_whitespace();
+  let status = std::process::Command::new(words.next().unwrap_or("vi"))
+    .args(words)
+    .arg(path)
+    .status()
+    .map_err(|_| format!("I couldn't start your editor ({}) :(", editor))?;
+  if !status.success() {
+    return Err(format!("Your editor ({}) exited with an error :(", editor));
+  }
+  std::fs::read_to_string(path).map_err(|_| "I couldn't read back the edited file :(".to_owned())
+}
@@ -0,0 +1,144 @@
+use math_parser::error::{line_col, Error, Source, Span, Warning};
+use math_parser::expr::{Env, Value};
+use math_parser::stdlib;
+use serde_json::{json, Value as Json};
+use std::io::{self, Write};
+
+// What --json prints instead of text meant for people: one JSON object per
+// line, each with a "kind" saying what it is:
+//
+//   {"kind":"result","type":"int","value":6,"text":"6"}
+//   {"kind":"print","text":"hello"}
+//   {"kind":"warning","message":"...","span":{...}}
+//   {"kind":"error","stage":"runtime","message":"...","span":{...},"trace":[...]}
+//
+// Spans are byte offsets into the script, with the line and column of the
+// start counted from 1 like error messages do. Spans in the functions of an
+// imported file are into that file, and say which one with "file". Ones in
+// the prelude's functions are into stdlib.math and have "prelude": true.
+
+fn span(input: &str, span: Span) -> Json {
+  let (line, column) = line_col(input, span.start);
+  json!({ "start": span.start, "end": span.end, "line": line, "column": column })
+}
+
+fn span_in(input: &str, source: &Source, at: Span) -> Json {
+  match source {
+    Source::Input => span(input, at),
+    Source::Prelude => {
+      let mut record = span(stdlib::SOURCE, at);
+      record["prelude"] = json!(true);
+      record
+    }
+    Source::File(file) => {
+      let mut record = span(&file.text, at);
+      record["file"] = json!(file.path);
+      record
+    }
+  }
+}
+
+// Numbers, bools, strings and lists as the JSON they look like, functions
+// as the text they're shown as. NaN and the infinities become null, JSON
+// has no numbers for them.
+fn value(val: &Value, env: &Env) -> Json {
+  match val {
+    Value::Int(num) => json!(num),
+    Value::Float(num) => json!(num),
+    Value::Bool(b) => json!(b),
+    Value::Str(text) => json!(&**text),
+    Value::List(vals) => Json::Array(vals.iter().map(|val| value(val, env)).collect()),
+    Value::Function(_) => json!(env.display(val)),
+  }
+}
+
+fn stage(err: &Error) -> &'static str {
+  match err {
+    Error::Lex(_) | Error::Parse(_) => "parse",
+    Error::Type(_) => "type",
+    Error::Denied(_) => "lint",
+    Error::Runtime(_) => "runtime",
+  }
+}
+
+// Added to everything about a script when more than one runs
+fn in_script(mut record: Json, script: Option<&str>) -> String {
+  if let Some(script) = script {
+    record["script"] = json!(script);
+  }
+  record.to_string()
+}
+
+pub fn result(val: &Value, env: &Env) -> String {
+  json!({
+    "kind": "result",
+    "type": val.type_name(),
+    "value": value(val, env),
+    "text": env.display(val),
+  })
+  .to_string()
+}
+
+pub fn warning(warning: &Warning, input: &str, script: Option<&str>) -> String {
+  let record = json!({
+    "kind": "warning",
+    "lint": warning.kind.lint().name(),
+    "message": warning.message(),
+    "span": span(input, warning.span),
+  });
+  in_script(record, script)
+}
+
+// One line for every error
+pub fn errors(errors: &[Error], input: &str, script: Option<&str>) -> String {
+  let lines = errors.iter().map(|err| {
+    // Runtime errors can be in the body of a function from another file
+    let source = match err {
+      Error::Runtime(err) => err.source(),
+      _ => &Source::Input,
+    };
+    let mut record = json!({
+      "kind": "error",
+      "stage": stage(err),
+      "message": err.message(),
+      "span": err.span().map(|err_span| span_in(input, source, err_span)),
+    });
+    if let Error::Runtime(err) = err {
+      let trace = err.call_sites().map(|(frame, source)| {
+        json!({ "function": frame.function, "span": span_in(input, source, frame.call_site) })
+      });
+      record["trace"] = Json::Array(trace.collect());
+    }
+    in_script(record, script)
+  });
+  lines.collect::<Vec<String>>().join("\n")
+}
+
+// For failures that aren't about any script, like a file that can't be read
+pub fn failure(stage: &str, message: &str) -> String {
+  json!({ "kind": "error", "stage": stage, "message": message }).to_string()
+}
+
+// Where print statements write with --json, so what they print comes out
+// as records between the others instead of as loose text
+#[derive(Default)]
+pub struct PrintRecords {
+  line: Vec<u8>,
+}
+
+impl Write for PrintRecords {
+  fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+    self.line.extend_from_slice(bytes);
+    while let Some(end) = self.line.iter().position(|&byte| byte == b'\n') {
+      let line = self.line.drain(..=end).collect::<Vec<u8>>();
+      let text = String::from_utf8_lossy(&line[..end]);
+      let record = json!({ "kind": "print", "text": text });
+      writeln!(io::stdout(), "{}", record)?;
+    }
+    Ok(bytes.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    io::stdout().flush()
+  }
+}
@@ -0,0 +1,61 @@
+#[cfg(all(
+  feature = "std",
+  not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// SplitMix64, small and fast with good enough numbers for Monte Carlo
+// scripts. Not for anything where guessing the numbers would matter.
+#[derive(Debug, Clone)]
+pub struct Rng {
+  state: u64,
+}
+
+impl Rng {
+  pub fn new(seed: u64) -> Self {
+    Rng { state: seed }
+  }
+
+  // Different every run, for when no seed was asked for
+  #[cfg(all(
+    feature = "std",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+  ))]
+  pub fn from_time() -> Self {
+    let nanos = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |time| time.as_nanos() as u64);
+    Rng::new(nanos)
+  }
+
+  // Browsers have no clock std can read, the wasm bindings seed it from
+  // Math.random() instead. Without std there's no clock at all, call seed()
+  // with something that changes, like a hardware timer.
+  #[cfg(any(
+    not(feature = "std"),
+    all(target_arch = "wasm32", target_os = "unknown")
+  ))]
+  pub fn from_time() -> Self {
+    Rng::new(0)
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = self.state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+  }
+
+  // Uniform in [0, 1), from the top 53 bits so every float is equally likely
+  pub fn next_f64(&mut self) -> f64 {
+    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  // Uniform in [low, high], both included, which needs low <= high
+  pub fn range(&mut self, low: i64, high: i64) -> i64 {
+    let span = (i128::from(high) - i128::from(low) + 1) as u128;
+    let offset = (u128::from(self.next_u64()) * span) >> 64;
+    (i128::from(low) + offset as i128) as i64
+  }
+}
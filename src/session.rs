@@ -0,0 +1,66 @@
+use math_parser::error::{render_all_with, RenderOptions};
+use math_parser::expr::Env;
+use std::path::PathBuf;
+
+// A named session keeps its variables and REPL history in
+// $XDG_DATA_HOME/math-parser/sessions/<name> (or ~/.local/share/...)
+pub struct Session {
+  dir: PathBuf,
+}
+
+fn data_dir() -> Option<PathBuf> {
+  match std::env::var_os("XDG_DATA_HOME") {
+    Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+    _ => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")),
+  }
+}
+
+impl Session {
+  pub fn open(name: &str) -> Result<Self, String> {
+    let valid_name = !name.is_empty()
+      && name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid_name {
+      return Err(format!(
+        "\"{}\" isn't a good session name, use only letters, digits, \"-\" and \"_\"",
+        name
+      ));
+    }
+    let dir = data_dir()
+      .ok_or("I couldn't find a place to keep sessions in, is $HOME set?")?
+      .join("math-parser")
+      .join("sessions")
+      .join(name);
+    std::fs::create_dir_all(&dir).map_err(|_| {
+      format!(
+        "I couldn't create the session directory :(\n{}",
+        dir.display()
+      )
+    })?;
+    Ok(Session { dir })
+  }
+
+  pub fn history_path(&self) -> PathBuf {
+    self.dir.join("history.txt")
+  }
+
+  fn env_path(&self) -> PathBuf {
+    self.dir.join("env.math")
+  }
+
+  pub fn restore_env(&self, env: &mut Env) -> Result<(), String> {
+    let input = match std::fs::read_to_string(self.env_path()) {
+      Ok(input) => input,
+      Err(_) => return Ok(()),
+    };
+    env
+      .load_script(&input)
+      .map_err(|errors| render_all_with(&errors, &input, &RenderOptions::terminal()))
+  }
+
+  pub fn save_env(&self, env: &Env) -> Result<(), String> {
+    std::fs::write(self.env_path(), env.to_script())
+      .map_err(|_| "I couldn't save the session variables :(".to_owned())
+  }
+}
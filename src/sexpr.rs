@@ -0,0 +1,119 @@
+use crate::expr::{BinOp, Expr, Function, UnOp, Value};
+use crate::lexer::quote;
+use crate::prelude::*;
+use crate::symbol::Symbol;
+
+// The tree written as s-expressions, like "(add (int 2) (mul (var x) (int 3)))".
+// Every node is a name followed by its children, with no spans and no
+// parentheses to work out, so other tools can read it with a few lines of
+// code.
+
+fn binary_name(op: BinOp) -> &'static str {
+  match op {
+    BinOp::Pow => "pow",
+    BinOp::Mul => "mul",
+    BinOp::Div => "div",
+    BinOp::FloorDiv => "floor-div",
+    BinOp::Add => "add",
+    BinOp::Sub => "sub",
+    BinOp::Less => "less",
+    BinOp::LessEqual => "less-equal",
+    BinOp::Greater => "greater",
+    BinOp::GreaterEqual => "greater-equal",
+    BinOp::Equal => "equal",
+    BinOp::NotEqual => "not-equal",
+    BinOp::BitAnd => "bit-and",
+    BinOp::BitOr => "bit-or",
+    BinOp::BitXor => "bit-xor",
+    BinOp::ShiftLeft => "shift-left",
+    BinOp::ShiftRight => "shift-right",
+  }
+}
+
+fn unary_name(op: UnOp) -> &'static str {
+  match op {
+    UnOp::Neg => "neg",
+    UnOp::Sqrt => "sqrt",
+    UnOp::Percent => "percent",
+    UnOp::Not => "not",
+  }
+}
+
+// "(name child child ...)"
+fn node(name: &str, children: impl IntoIterator<Item = String>) -> String {
+  let mut text = format!("({}", name);
+  for child in children {
+    text.push(' ');
+    text.push_str(&child);
+  }
+  text.push(')');
+  text
+}
+
+fn function(function: &Function) -> String {
+  node(
+    "fn",
+    vec![
+      function.name.clone(),
+      node("params", function.params.iter().map(Symbol::to_string)),
+      function.body.to_sexpr(),
+    ],
+  )
+}
+
+fn value(val: &Value) -> String {
+  match val {
+    Value::Int(num) => format!("(int {})", num),
+    // Debug formatting keeps the ".0", and writes inf and NaN as words
+    Value::Float(num) => format!("(float {:?})", num),
+    Value::Bool(b) => format!("(bool {})", b),
+    Value::Str(text) => format!("(str {})", quote(text)),
+    Value::List(vals) => node("list", vals.iter().map(value)),
+    Value::Function(f) => function(f),
+  }
+}
+
+impl Expr {
+  pub fn to_sexpr(&self) -> String {
+    let children = |exprs: &[Expr]| exprs.iter().map(Expr::to_sexpr).collect::<Vec<_>>();
+    match self {
+      Expr::Literal(val, _) => value(val),
+      Expr::VarDeclaration(name, val, _) => node("let", vec![name.to_string(), val.to_sexpr()]),
+      Expr::Var(name, _) => format!("(var {})", name),
+      Expr::Print(vals, _) => node("print", children(vals)),
+      Expr::Assert(cond, _) => node("assert", vec![cond.to_sexpr()]),
+      Expr::Binary(op, left, right, _) => {
+        node(binary_name(*op), vec![left.to_sexpr(), right.to_sexpr()])
+      }
+      Expr::Unary(op, val, _) => node(unary_name(*op), vec![val.to_sexpr()]),
+      Expr::Conditional(cond, val_if_true, val_if_false, _) => node(
+        "if",
+        vec![
+          cond.to_sexpr(),
+          val_if_true.to_sexpr(),
+          val_if_false.to_sexpr(),
+        ],
+      ),
+      Expr::Call(name, args, _) => {
+        node("call", core::iter::once(name.clone()).chain(children(args)))
+      }
+      Expr::Series(kind, index, from, to, term, _) => node(
+        kind.name(),
+        vec![
+          index.to_string(),
+          from.to_sexpr(),
+          to.to_sexpr(),
+          term.to_sexpr(),
+        ],
+      ),
+      Expr::List(items, _) => node("list", children(items)),
+      Expr::Interpolation(parts, _) => node("interpolate", children(parts)),
+      Expr::FnDeclaration(f, _) => function(f),
+      Expr::Import(path, _) => node("import", vec![quote(path)]),
+      Expr::Module(name, body, _) => node(
+        "module",
+        core::iter::once(name.clone()).chain(children(body)),
+      ),
+    }
+  }
+}
@@ -0,0 +1,28 @@
+use crate::error::Source;
+use crate::expr::{Env, Value};
+use crate::parser::parse;
+use crate::program::eval_all;
+use crate::symbol::SymbolMap;
+
+// The standard library: constants like pi and helpers like gcd, written in
+// the language itself and loaded into every new Env as its prelude. Variables
+// a program declares hide the prelude's, and the prelude is never part of
+// what an Env saves. Env::without_prelude leaves it out.
+
+pub const SOURCE: &str = include_str!("stdlib.math");
+
+pub(crate) fn load() -> SymbolMap<Value> {
+  let exprs = match parse(SOURCE) {
+    Ok(exprs) => exprs,
+    Err(_) => unreachable!("the standard library parses"),
+  };
+  let mut env = Env::without_prelude();
+  env.source = Source::Prelude;
+  if eval_all(&exprs, &mut env).is_err() {
+    unreachable!("the standard library runs");
+  }
+  env
+    .vars()
+    .map(|(name, val)| (name.clone(), val.clone()))
+    .collect()
+}
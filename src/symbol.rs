@@ -0,0 +1,280 @@
+use crate::prelude::*;
+#[cfg(feature = "std")]
+use alloc::rc::Rc;
+use core::borrow::Borrow;
+#[cfg(feature = "std")]
+use core::cell::RefCell;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::iter::FromIterator;
+use core::ops::Deref;
+
+// Names of variables and parameters, interned so every use of a name
+// shares one allocation and has a number. Comparing two symbols compares
+// their numbers, and an Env finds a variable by indexing with it instead of
+// hashing the name.
+//
+// The table lives as long as the thread and only ever grows, which is fine
+// for names. Symbols can't be sent to other threads, where the same number
+// would mean another name.
+//
+// Without std there are no thread locals, so there's one table behind a lock
+// and the names in it are never freed.
+
+#[cfg(feature = "std")]
+type Name = Rc<str>;
+#[cfg(not(feature = "std"))]
+type Name = &'static str;
+
+#[cfg(feature = "std")]
+fn new_name(name: &str) -> Name {
+  name.into()
+}
+
+#[cfg(not(feature = "std"))]
+fn new_name(name: &str) -> Name {
+  Box::leak(name.into())
+}
+
+// Either kind of name derefs to the text
+fn name_str(name: &Name) -> &str {
+  name
+}
+
+#[derive(Default)]
+struct Interner {
+  ids: HashMap<Name, u32>,
+  names: Vec<Name>,
+}
+
+#[cfg(feature = "std")]
+thread_local! {
+  static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+#[cfg(feature = "std")]
+fn with_interner<T>(f: impl FnOnce(&mut Interner) -> T) -> T {
+  INTERNER.with(|interner| f(&mut interner.borrow_mut()))
+}
+
+#[cfg(not(feature = "std"))]
+static INTERNER: spin::Mutex<Option<Interner>> = spin::Mutex::new(None);
+
+#[cfg(not(feature = "std"))]
+fn with_interner<T>(f: impl FnOnce(&mut Interner) -> T) -> T {
+  f(INTERNER.lock().get_or_insert_with(Interner::default))
+}
+
+#[derive(Clone)]
+pub struct Symbol {
+  id: u32,
+  name: Name,
+}
+
+impl Symbol {
+  // The symbol for that name, added to the table the first time it's seen
+  pub fn new(name: &str) -> Symbol {
+    with_interner(|interner| {
+      if let Some(&id) = interner.ids.get(name) {
+        let name = Name::clone(&interner.names[id as usize]);
+        return Symbol { id, name };
+      }
+      let id = interner.names.len() as u32;
+      let name = new_name(name);
+      interner.names.push(Name::clone(&name));
+      interner.ids.insert(Name::clone(&name), id);
+      Symbol { id, name }
+    })
+  }
+
+  // Only names that are already in the table, nothing can be stored under
+  // any other name yet
+  pub fn existing(name: &str) -> Option<Symbol> {
+    with_interner(|interner| {
+      let id = *interner.ids.get(name)?;
+      let name = Name::clone(&interner.names[id as usize]);
+      Some(Symbol { id, name })
+    })
+  }
+
+  pub fn as_str(&self) -> &str {
+    name_str(&self.name)
+  }
+
+  // Small and dense, counting up from 0 in the order names were first seen
+  pub fn id(&self) -> usize {
+    self.id as usize
+  }
+}
+
+impl PartialEq for Symbol {
+  fn eq(&self, other: &Symbol) -> bool {
+    self.id == other.id
+  }
+}
+
+impl Eq for Symbol {}
+
+impl PartialEq<str> for Symbol {
+  fn eq(&self, other: &str) -> bool {
+    self.as_str() == other
+  }
+}
+
+impl PartialEq<&str> for Symbol {
+  fn eq(&self, other: &&str) -> bool {
+    self.as_str() == *other
+  }
+}
+
+// Hashed like the name, so sets and maps of symbols can be asked about a &str
+impl Hash for Symbol {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.as_str().hash(state);
+  }
+}
+
+impl PartialOrd for Symbol {
+  fn partial_cmp(&self, other: &Symbol) -> Option<core::cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+// Alphabetical, not in the order they were interned
+impl Ord for Symbol {
+  fn cmp(&self, other: &Symbol) -> core::cmp::Ordering {
+    self.as_str().cmp(other.as_str())
+  }
+}
+
+impl Deref for Symbol {
+  type Target = str;
+
+  fn deref(&self) -> &str {
+    self.as_str()
+  }
+}
+
+impl Borrow<str> for Symbol {
+  fn borrow(&self) -> &str {
+    self.as_str()
+  }
+}
+
+impl AsRef<str> for Symbol {
+  fn as_ref(&self) -> &str {
+    self.as_str()
+  }
+}
+
+impl From<&str> for Symbol {
+  fn from(name: &str) -> Symbol {
+    Symbol::new(name)
+  }
+}
+
+impl From<String> for Symbol {
+  fn from(name: String) -> Symbol {
+    Symbol::new(&name)
+  }
+}
+
+impl fmt::Debug for Symbol {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{:?}", self.as_str())
+  }
+}
+
+impl fmt::Display for Symbol {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+// Written as the plain name, the number only means something in this thread
+#[cfg(feature = "serde")]
+impl serde::Serialize for Symbol {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(self.as_str())
+  }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Symbol {
+  fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Symbol, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    Ok(Symbol::new(&name))
+  }
+}
+
+// Values stored at the number of their symbol
+#[derive(Clone)]
+pub struct SymbolMap<V> {
+  slots: Vec<Option<(Symbol, V)>>,
+  len: usize,
+}
+
+impl<V> Default for SymbolMap<V> {
+  fn default() -> Self {
+    SymbolMap::new()
+  }
+}
+
+impl<V> SymbolMap<V> {
+  pub fn new() -> Self {
+    SymbolMap {
+      slots: Vec::new(),
+      len: 0,
+    }
+  }
+
+  pub fn get(&self, symbol: &Symbol) -> Option<&V> {
+    match self.slots.get(symbol.id()) {
+      Some(Some((_, val))) => Some(val),
+      _ => None,
+    }
+  }
+
+  // Returns what was stored under the symbol before
+  pub fn insert(&mut self, symbol: Symbol, val: V) -> Option<V> {
+    let id = symbol.id();
+    if id >= self.slots.len() {
+      self.slots.resize_with(id + 1, || None);
+    }
+    let before = self.slots[id].replace((symbol, val)).map(|(_, val)| val);
+    if before.is_none() {
+      self.len += 1;
+    }
+    before
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  // In the order the names were first interned
+  pub fn iter(&self) -> impl Iterator<Item = (&Symbol, &V)> {
+    self
+      .slots
+      .iter()
+      .filter_map(|slot| slot.as_ref().map(|(symbol, val)| (symbol, val)))
+  }
+
+  pub fn keys(&self) -> impl Iterator<Item = &Symbol> {
+    self.iter().map(|(symbol, _)| symbol)
+  }
+}
+
+impl<V> FromIterator<(Symbol, V)> for SymbolMap<V> {
+  fn from_iter<I: IntoIterator<Item = (Symbol, V)>>(iter: I) -> Self {
+    let mut map = SymbolMap::new();
+    for (symbol, val) in iter {
+      map.insert(symbol, val);
+    }
+    map
+  }
+}
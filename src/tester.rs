@@ -0,0 +1,145 @@
+use math_parser::error::{render_all_with, RenderOptions, RuntimeErrorKind};
+use math_parser::expr::EnvBuilder;
+use math_parser::parser::{parse_with, ParseOptions};
+use math_parser::program::declare_functions;
+use math_parser::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// Test files are found by their name, so helpers next to them aren't run
+pub const TEST_SUFFIX: &str = "_test.math";
+
+// What happened in one test file
+pub struct FileResult {
+  pub path: PathBuf,
+  pub passed: u64,
+  // Every failure rendered with its location in the file
+  pub failures: Vec<String>,
+}
+
+pub struct Summary {
+  pub files: Vec<FileResult>,
+}
+
+impl Summary {
+  pub fn passed(&self) -> u64 {
+    self.files.iter().map(|file| file.passed).sum()
+  }
+
+  pub fn failed(&self) -> usize {
+    self.files.iter().map(|file| file.failures.len()).sum()
+  }
+}
+
+// Every test file in the directory and the ones below it, in a stable order.
+// A path to a file is a single test file, whatever its name.
+pub fn discover(path: &Path) -> Result<Vec<PathBuf>, String> {
+  if path.is_file() {
+    return Ok(vec![path.to_owned()]);
+  }
+  let mut found = Vec::new();
+  let mut dirs = vec![path.to_owned()];
+  while let Some(dir) = dirs.pop() {
+    let entries = std::fs::read_dir(&dir)
+      .map_err(|_| format!("I failed to look inside {} :(", dir.display()))?;
+    for entry in entries.flatten() {
+      let path = entry.path();
+      if path.is_dir() {
+        dirs.push(path);
+      } else if path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(TEST_SUFFIX))
+      {
+        found.push(path);
+      }
+    }
+  }
+  found.sort();
+  Ok(found)
+}
+
+fn is_assertion(err: &Error) -> bool {
+  matches!(
+    err,
+    Error::Runtime(err) if matches!(err.kind, RuntimeErrorKind::AssertionFailed(..))
+  )
+}
+
+// Runs a test file in a fresh environment. A failed assert doesn't stop the
+// file, but any other error does since the rest of it probably depends on
+// what failed.
+pub fn run_file(path: &Path, input: &str, options: &ParseOptions, env: &EnvBuilder) -> FileResult {
+  let mut result = FileResult {
+    path: path.to_owned(),
+    passed: 0,
+    failures: Vec::new(),
+  };
+  let exprs = match parse_with(input, options) {
+    Ok((exprs, _)) => exprs,
+    Err(errors) => {
+      result
+        .failures
+        .push(render_all_with(&errors, input, &RenderOptions::terminal()));
+      return result;
+    }
+  };
+  let mut env = env.clone().build();
+  env.set_script_path(path);
+  if let Err(err) = declare_functions(&exprs, &mut env) {
+    result.failures.push(render_all_with(
+      &[err.into()],
+      input,
+      &RenderOptions::terminal(),
+    ));
+    return result;
+  }
+  for expr in &exprs {
+    if let Err(err) = expr.eval(&mut env) {
+      let err = Error::from(err);
+      let assertion = is_assertion(&err);
+      result
+        .failures
+        .push(render_all_with(&[err], input, &RenderOptions::terminal()));
+      if !assertion {
+        break;
+      }
+    }
+  }
+  result.passed = env.passed_asserts();
+  result
+}
+
+impl fmt::Display for FileResult {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let status = if self.failures.is_empty() {
+      "ok"
+    } else {
+      "FAILED"
+    };
+    write!(
+      f,
+      "{} {}: {} passed, {} failed",
+      status,
+      self.path.display(),
+      self.passed,
+      self.failures.len()
+    )?;
+    for failure in &self.failures {
+      write!(f, "\n{}", failure)?;
+    }
+    Ok(())
+  }
+}
+
+impl fmt::Display for Summary {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{} passed, {} failed, in {} file(s)",
+      self.passed(),
+      self.failed(),
+      self.files.len()
+    )
+  }
+}
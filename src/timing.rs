@@ -0,0 +1,55 @@
+use math_parser::error::RuntimeError;
+use math_parser::expr::{Env, Expr, Value};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Statements longer than this are cut short in the report
+const MAX_SOURCE_WIDTH: usize = 60;
+
+// How long each top-level statement took, for --time
+#[derive(Default)]
+pub struct Timings {
+  // Every statement written as source, in the order they ran
+  statements: Vec<(String, Duration)>,
+}
+
+impl Timings {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  // Evaluates a statement, also remembering how long that took
+  pub fn eval(&mut self, expr: &Expr, env: &mut Env) -> Result<Value, RuntimeError> {
+    let start = Instant::now();
+    let result = expr.eval(env);
+    self.statements.push((expr.to_string(), start.elapsed()));
+    result
+  }
+
+  fn total(&self) -> Duration {
+    self.statements.iter().map(|(_, elapsed)| *elapsed).sum()
+  }
+}
+
+fn shorten(source: &str) -> String {
+  match source.char_indices().nth(MAX_SOURCE_WIDTH) {
+    Some((end, _)) => format!("{}...", &source[..end]),
+    None => source.to_owned(),
+  }
+}
+
+impl fmt::Display for Timings {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let times = self
+      .statements
+      .iter()
+      .map(|(_, elapsed)| format!("{:.2?}", elapsed))
+      .collect::<Vec<String>>();
+    let total = format!("{:.2?}", self.total());
+    let width = times.iter().map(String::len).fold(total.len(), usize::max);
+    for ((source, _), time) in self.statements.iter().zip(&times) {
+      writeln!(f, "  {:>width$}  {}", time, shorten(source), width = width)?;
+    }
+    write!(f, "  {:>width$}  in total", total, width = width)
+  }
+}
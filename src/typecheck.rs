@@ -0,0 +1,357 @@
+use crate::builtins::{self, Arity};
+use crate::error::{Span, TypeError, TypeErrorKind, NO_SPAN};
+use crate::expr::{BinOp, Expr, UnOp, Value};
+use crate::prelude::*;
+use crate::symbol::Symbol;
+
+// Finds type errors before anything runs, so a program that would fail
+// halfway doesn't print half of its output first. Only mistakes that are
+// certain get reported: whatever can't be known before running, like a
+// function's parameters, is Unknown and allowed everywhere.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Type {
+  Int,
+  Float,
+  Bool,
+  Str,
+  List,
+  Function,
+  Unknown,
+}
+
+impl Type {
+  pub fn of(val: &Value) -> Type {
+    match val {
+      Value::Int(_) => Type::Int,
+      Value::Float(_) => Type::Float,
+      Value::Bool(_) => Type::Bool,
+      Value::Str(_) => Type::Str,
+      Value::List(_) => Type::List,
+      Value::Function(_) => Type::Function,
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Type::Int => "int",
+      Type::Float => "float",
+      Type::Bool => "bool",
+      Type::Str => "string",
+      Type::List => "list",
+      Type::Function => "function",
+      Type::Unknown => "unknown",
+    }
+  }
+
+  fn is_number(self) -> bool {
+    matches!(self, Type::Int | Type::Float | Type::Unknown)
+  }
+}
+
+// A number that's an int only when both operands are
+fn numeric(left: Type, right: Type) -> Type {
+  match (left, right) {
+    (Type::Int, Type::Int) => Type::Int,
+    (Type::Float, _) | (_, Type::Float) => Type::Float,
+    _ => Type::Unknown,
+  }
+}
+
+struct Checker {
+  // Types of the variables declared so far
+  vars: HashMap<String, Type>,
+  // Parameter counts of the functions declared so far
+  arities: HashMap<String, usize>,
+  // Every name the program declares anywhere, these might not be builtins
+  declared: HashSet<String>,
+  // Where errors point, the span of the innermost node being checked that
+  // has one
+  span: Option<Span>,
+  errors: Vec<TypeError>,
+}
+
+impl Checker {
+  // Points errors at the span from now on, returning where they pointed
+  // before
+  fn point_at(&mut self, span: Span) -> Option<Span> {
+    if span == NO_SPAN {
+      return self.span;
+    }
+    self.span.replace(span)
+  }
+
+  fn error(&mut self, kind: TypeErrorKind, expr: &Expr) {
+    self.errors.push(TypeError {
+      kind,
+      expr: expr.to_string(),
+      span: self.span,
+    });
+  }
+
+  // Reports an operand of the wrong type, unless it's Unknown
+  fn expect(&mut self, op: &'static str, found: Type, ok: bool, expr: &Expr) {
+    if !ok && found != Type::Unknown {
+      self.error(TypeErrorKind::WrongOperand(op, found.name()), expr);
+    }
+  }
+
+  fn check(&mut self, expr: &Expr) -> Type {
+    match expr {
+      Expr::Literal(val, _) => Type::of(val),
+      // What the file declares isn't known until it runs
+      Expr::Import(..) => Type::Str,
+      Expr::VarDeclaration(name, val, span) => {
+        let outer = self.span.replace(*span);
+        let ty = self.check(val);
+        self.span = outer;
+        self.vars.insert(name.to_string(), ty);
+        ty
+      }
+      Expr::Var(name, _) => self
+        .vars
+        .get(name.as_str())
+        .copied()
+        .unwrap_or(Type::Unknown),
+      Expr::Print(vals, _) => {
+        let types: Vec<Type> = vals.iter().map(|val| self.check(val)).collect();
+        types.last().copied().unwrap_or(Type::Unknown)
+      }
+      Expr::Assert(cond, span) => {
+        let outer = self.span.replace(*span);
+        let ty = self.check(cond);
+        if !matches!(ty, Type::Bool | Type::Unknown) {
+          self.error(TypeErrorKind::AssertionNotBool(ty.name()), expr);
+        }
+        self.span = outer;
+        Type::Bool
+      }
+      Expr::Series(kind, index, from, to, term, span) => {
+        let outer_span = self.point_at(*span);
+        for bound in [from, to] {
+          let ty = self.check(bound);
+          self.expect(kind.name(), ty, matches!(ty, Type::Int), expr);
+        }
+        let outer = self.vars.insert(index.to_string(), Type::Int);
+        let ty = self.check(term);
+        match outer {
+          Some(outer) => self.vars.insert(index.to_string(), outer),
+          None => self.vars.remove(index.as_str()),
+        };
+        self.expect(kind.name(), ty, ty.is_number(), expr);
+        self.span = outer_span;
+        numeric(Type::Int, ty)
+      }
+      Expr::Interpolation(parts, _) => {
+        for part in parts {
+          self.check(part);
+        }
+        Type::Str
+      }
+      Expr::Binary(op, left, right, span) => {
+        let (left, right) = (self.check(left), self.check(right));
+        let outer = self.point_at(*span);
+        let ty = self.check_binary(*op, left, right, expr);
+        self.span = outer;
+        ty
+      }
+      Expr::Unary(op, val, span) => {
+        let ty = self.check(val);
+        let outer = self.point_at(*span);
+        let ty = self.check_unary(*op, ty, expr);
+        self.span = outer;
+        ty
+      }
+      Expr::Conditional(cond, val_if_true, val_if_false, span) => {
+        let cond_ty = self.check(cond);
+        let outer = self.point_at(cond.span());
+        if !matches!(cond_ty, Type::Bool | Type::Unknown) {
+          self.error(TypeErrorKind::ConditionNotBool(cond_ty.name()), expr);
+        }
+        self.span = outer;
+        // Only one branch runs, so variables it declares might not exist
+        // with that type afterwards
+        let before = self.vars.clone();
+        let if_true = self.check(val_if_true);
+        let after_true = core::mem::replace(&mut self.vars, before);
+        let if_false = self.check(val_if_false);
+        for (name, ty) in after_true {
+          let merged = match self.vars.get(&name) {
+            Some(&other) if other == ty => ty,
+            _ => Type::Unknown,
+          };
+          self.vars.insert(name, merged);
+        }
+        match (if_true, if_false) {
+          (a, b) if a == b => a,
+          (Type::Unknown, _) | (_, Type::Unknown) => Type::Unknown,
+          // Ints and floats mix in arithmetic anyway
+          (a, b) if a.is_number() && b.is_number() => Type::Float,
+          (a, b) => {
+            let outer = self.point_at(*span);
+            self.error(TypeErrorKind::MismatchedBranches(a.name(), b.name()), expr);
+            self.span = outer;
+            Type::Unknown
+          }
+        }
+      }
+      Expr::Call(name, args, span) => {
+        let outer = self.span.replace(*span);
+        for arg in args {
+          self.check(arg);
+        }
+        let arity = match self.vars.get(name) {
+          Some(Type::Function) => self.arities.get(name).copied(),
+          Some(_) => None,
+          None if self.declared.contains(name) => None,
+          // Builtins taking a minimum count are left to the runtime
+          None => match builtins::lookup(name).map(|builtin| builtin.arity) {
+            Some(Arity::Exact(arity)) => Some(arity),
+            _ => None,
+          },
+        };
+        if let Some(arity) = arity.filter(|&arity| arity != args.len()) {
+          self.error(
+            TypeErrorKind::WrongArgumentCount(name.clone(), arity, args.len()),
+            expr,
+          );
+        }
+        self.span = outer;
+        Type::Unknown
+      }
+      Expr::List(items, _) => {
+        for item in items {
+          self.check(item);
+        }
+        Type::List
+      }
+      // Its declarations stay, qualified, for what comes after it
+      Expr::Module(_, body, _) => {
+        for statement in body {
+          self.check(statement);
+        }
+        Type::Str
+      }
+      Expr::FnDeclaration(function, _) => {
+        self.vars.insert(function.name.clone(), Type::Function);
+        self
+          .arities
+          .insert(function.name.clone(), function.params.len());
+        // Parameters could be anything, and so could the globals by the
+        // time the function gets called
+        let outer = core::mem::take(&mut self.vars);
+        self.check(&function.body);
+        self.vars = outer;
+        Type::Function
+      }
+    }
+  }
+
+  fn check_binary(&mut self, op: BinOp, left: Type, right: Type, expr: &Expr) -> Type {
+    let symbol = op.symbol();
+    match op {
+      BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::FloorDiv => {
+        self.expect(symbol, left, left.is_number(), expr);
+        self.expect(symbol, right, right.is_number(), expr);
+        numeric(left, right)
+      }
+      BinOp::Div => {
+        self.expect(symbol, left, left.is_number(), expr);
+        self.expect(symbol, right, right.is_number(), expr);
+        Type::Float
+      }
+      // A negative exponent makes a float out of ints
+      BinOp::Pow => {
+        self.expect(symbol, left, left.is_number(), expr);
+        self.expect(symbol, right, right.is_number(), expr);
+        match numeric(left, right) {
+          Type::Float => Type::Float,
+          _ => Type::Unknown,
+        }
+      }
+      BinOp::Less | BinOp::LessEqual | BinOp::Greater | BinOp::GreaterEqual => {
+        self.expect(symbol, left, left.is_number(), expr);
+        self.expect(symbol, right, right.is_number(), expr);
+        Type::Bool
+      }
+      BinOp::Equal | BinOp::NotEqual => Type::Bool,
+      BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor => match (left, right) {
+        (Type::Int, Type::Int) => Type::Int,
+        (Type::Bool, Type::Bool) => Type::Bool,
+        (Type::Unknown, other) | (other, Type::Unknown) => {
+          self.expect(
+            symbol,
+            other,
+            other != Type::Float && other != Type::Function,
+            expr,
+          );
+          other
+        }
+        (Type::Int, other) | (other, _) => {
+          self.expect(symbol, other, false, expr);
+          Type::Unknown
+        }
+      },
+      BinOp::ShiftLeft | BinOp::ShiftRight => {
+        self.expect(symbol, left, matches!(left, Type::Int), expr);
+        self.expect(symbol, right, matches!(right, Type::Int), expr);
+        Type::Int
+      }
+    }
+  }
+
+  fn check_unary(&mut self, op: UnOp, ty: Type, expr: &Expr) -> Type {
+    let symbol = op.symbol();
+    match op {
+      UnOp::Neg => {
+        self.expect(symbol, ty, ty.is_number(), expr);
+        ty
+      }
+      UnOp::Sqrt | UnOp::Percent => {
+        self.expect(symbol, ty, ty.is_number(), expr);
+        Type::Float
+      }
+      UnOp::Not => {
+        self.expect(symbol, ty, ty == Type::Bool, expr);
+        Type::Bool
+      }
+    }
+  }
+}
+
+fn declared_names(exprs: &[&Expr]) -> HashSet<String> {
+  let mut names = HashSet::new();
+  for expr in exprs {
+    expr.walk(|expr| match expr {
+      Expr::VarDeclaration(name, ..) => {
+        names.insert(name.to_string());
+      }
+      Expr::FnDeclaration(function, _) => {
+        names.insert(function.name.clone());
+        names.extend(function.params.iter().map(Symbol::to_string));
+      }
+      _ => (),
+    });
+  }
+  names
+}
+
+// Checks a whole program, returning every type error found
+pub fn typecheck<'a>(exprs: impl IntoIterator<Item = &'a Expr>) -> Result<(), Vec<TypeError>> {
+  let exprs: Vec<&Expr> = exprs.into_iter().collect();
+  let mut checker = Checker {
+    vars: HashMap::new(),
+    arities: HashMap::new(),
+    declared: declared_names(&exprs),
+    span: None,
+    errors: Vec::new(),
+  };
+  for expr in exprs {
+    checker.check(expr);
+  }
+  if checker.errors.is_empty() {
+    Ok(())
+  } else {
+    Err(checker.errors)
+  }
+}
@@ -0,0 +1,123 @@
+use crate::expr::{AngleMode, Env, Value};
+use crate::program::eval_str_with_env;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+// Bindings for a calculator widget on a web page. Everything crosses over
+// to JavaScript as numbers and strings: results come back as they'd be
+// shown in the REPL and errors as rendered messages, e.g.
+//
+//   const env = new Env();
+//   env.set_number("x", 2);
+//   parse_and_eval("x ^ 10", env); // "1024"
+
+#[wasm_bindgen]
+extern "C" {
+  #[wasm_bindgen(js_namespace = Math)]
+  fn random() -> f64;
+}
+
+// Lines printed by the input, kept until the page asks for them
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+  fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+    self.0.borrow_mut().extend_from_slice(bytes);
+    Ok(bytes.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+fn fresh_env(output: &Output) -> Env {
+  let mut env = Env::new();
+  env.set_output(output.clone());
+  env.seed((random() * u64::MAX as f64) as u64);
+  env
+}
+
+// Called WasmEnv here, JavaScript sees it as Env
+#[wasm_bindgen(js_name = Env)]
+pub struct WasmEnv {
+  env: Env,
+  output: Output,
+}
+
+impl Default for WasmEnv {
+  fn default() -> Self {
+    WasmEnv::new()
+  }
+}
+
+#[wasm_bindgen(js_class = Env)]
+impl WasmEnv {
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> WasmEnv {
+    let output = Output::default();
+    WasmEnv {
+      env: fresh_env(&output),
+      output,
+    }
+  }
+
+  // Shown like in the REPL, undefined for variables that aren't set
+  pub fn get(&self, name: &str) -> Option<String> {
+    self.env.get(name).map(|val| self.env.display(&val))
+  }
+
+  pub fn set_number(&mut self, name: &str, val: f64) {
+    self.env.set(name, Value::Float(val));
+  }
+
+  pub fn set_string(&mut self, name: &str, text: &str) {
+    self.env.set(name, Value::Str(text.into()));
+  }
+
+  pub fn var_names(&self) -> Vec<String> {
+    self.env.var_names().map(str::to_owned).collect()
+  }
+
+  // Forgets every variable, settings like the angle mode stay
+  pub fn clear(&mut self) {
+    let mut env = fresh_env(&self.output);
+    env.set_angle_mode(self.env.angle_mode());
+    env.set_precision(self.env.precision());
+    self.env = env;
+  }
+
+  // "deg" or "rad"
+  pub fn set_angle_mode(&mut self, name: &str) -> Result<(), JsValue> {
+    let mode = AngleMode::from_name(name)
+      .ok_or_else(|| JsValue::from_str(&format!("Hey, \"{}\" isn't deg or rad", name)))?;
+    self.env.set_angle_mode(mode);
+    Ok(())
+  }
+
+  // Significant digits results show, 0 for all of them
+  pub fn set_precision(&mut self, digits: usize) {
+    self
+      .env
+      .set_precision(Some(digits).filter(|&digits| digits > 0));
+  }
+
+  // What print statements printed since the last time this was called
+  pub fn take_output(&mut self) -> String {
+    let bytes = std::mem::take(&mut *self.output.0.borrow_mut());
+    String::from_utf8_lossy(&bytes).into_owned()
+  }
+}
+
+// Evaluates a script in the environment and returns the last value, or
+// throws the error rendered against the input
+#[wasm_bindgen]
+pub fn parse_and_eval(input: &str, env: &mut WasmEnv) -> Result<String, JsValue> {
+  match eval_str_with_env(input, &mut env.env) {
+    Ok(val) => Ok(env.env.display(&val)),
+    Err(err) => Err(JsValue::from_str(&err.render(input))),
+  }
+}